@@ -0,0 +1,336 @@
+//! Finding the time offset between two timelines of activity, e.g. the cues of the same subtitle
+//! track in two releases.
+//!
+//! Timelines are lists of `(start, end)` intervals in milliseconds. The offset is the shift of the
+//! candidate that maximizes how long its intervals overlap the reference's, searched on a coarse
+//! grid and then refined.
+
+use crate::read_cues;
+use crate::Cue;
+use crate::FfmpegOptions;
+use crate::SubtitleTrack;
+use clap::ValueEnum;
+use std::path::Path;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// How far apart two releases are searched by default
+pub const DEFAULT_MAX_OFFSET: Duration = Duration::from_secs(60);
+
+/// The grid the first pass of the search steps through
+const COARSE_STEP_MS: i64 = 100;
+
+/// The resolution of the final offset
+const FINE_STEP_MS: i64 = 10;
+
+/// Speed changes between common frame rates, tried when looking for drift: PAL speed-up and
+/// slow-down, and 24 vs 23.976 fps
+const SPEED_RATIOS: &[f64] = &[
+    1.0,
+    25.0 / (24000.0 / 1001.0),
+    (24000.0 / 1001.0) / 25.0,
+    25.0 / 24.0,
+    24.0 / 25.0,
+    24.0 / (24000.0 / 1001.0),
+    (24000.0 / 1001.0) / 24.0,
+];
+
+/// How many stretches of the candidate are lined up on their own to measure remaining drift
+const DRIFT_CHUNKS: usize = 8;
+
+/// How far a stretch may be from the global offset
+const DRIFT_CHUNK_MAX_OFFSET_MS: i64 = 2_000;
+
+/// What two releases are compared by to find the offset between them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum SyncMethod {
+    /// The loudness of the first audio track of each.
+    #[default]
+    Audio,
+
+    /// The cue timing of a subtitle track in the same language in each.
+    Subtitles,
+}
+
+/// How to move a timeline onto another: `t' = t * scale + offset`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Retiming {
+    /// Milliseconds added after scaling; negative moves cues earlier.
+    pub offset_ms: i64,
+
+    /// 1.0 unless the two timelines run at different speeds, e.g. 25/23.976 for a PAL speed-up.
+    pub scale: f64,
+}
+
+impl Default for Retiming {
+    fn default() -> Self {
+        Self {
+            offset_ms: 0,
+            scale: 1.0,
+        }
+    }
+}
+
+impl Retiming {
+    /// Retime a point in milliseconds; may be negative
+    pub fn apply_ms(&self, ms: i64) -> i64 {
+        (ms as f64 * self.scale).round() as i64 + self.offset_ms
+    }
+
+    /// Retime a timestamp, clamping at zero
+    pub fn apply(&self, time: Duration) -> Duration {
+        Duration::from_millis(self.apply_ms(time.as_millis() as i64).max(0) as u64)
+    }
+
+    /// Retime cues, dropping those that would end before zero
+    pub fn apply_cues(&self, cues: &[Cue]) -> Vec<Cue> {
+        cues.iter()
+            .filter(|cue| self.apply_ms(cue.end.as_millis() as i64) > 0)
+            .map(|cue| Cue {
+                start: self.apply(cue.start),
+                end: self.apply(cue.end),
+                text: cue.text.clone(),
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for Retiming {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:+.3}s", self.offset_ms as f64 / 1000.0)?;
+        if self.scale != 1.0 {
+            write!(f, ", speed x{:.6}", self.scale)?;
+        }
+        Ok(())
+    }
+}
+
+/// The best retiming found and how well it fits
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Alignment {
+    pub retiming: Retiming,
+
+    /// The share of the candidate's (retimed) duration that overlaps the reference, 0 to 1.
+    pub score: f64,
+}
+
+/// Sort intervals and merge those that touch, so overlaps are not counted twice
+pub fn merge_intervals(mut intervals: Vec<(i64, i64)>) -> Vec<(i64, i64)> {
+    intervals.retain(|(start, end)| end > start);
+    intervals.sort();
+    let mut merged: Vec<(i64, i64)> = Vec::with_capacity(intervals.len());
+    for (start, end) in intervals {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// The merged `(start, end)` intervals of cues, in milliseconds
+pub fn cue_intervals(cues: &[Cue]) -> Vec<(i64, i64)> {
+    merge_intervals(
+        cues.iter()
+            .map(|cue| (cue.start.as_millis() as i64, cue.end.as_millis() as i64))
+            .collect(),
+    )
+}
+
+/// Retime every interval
+fn retime_intervals(intervals: &[(i64, i64)], retiming: &Retiming) -> Vec<(i64, i64)> {
+    intervals
+        .iter()
+        .map(|(start, end)| (retiming.apply_ms(*start), retiming.apply_ms(*end)))
+        .collect()
+}
+
+/// Total time two merged interval lists overlap when `candidate` is shifted by `offset`
+fn overlap(reference: &[(i64, i64)], candidate: &[(i64, i64)], offset: i64) -> i64 {
+    let (mut i, mut j, mut total) = (0, 0, 0);
+    while i < reference.len() && j < candidate.len() {
+        let (a_start, a_end) = reference[i];
+        let (b_start, b_end) = (candidate[j].0 + offset, candidate[j].1 + offset);
+        total += (a_end.min(b_end) - a_start.max(b_start)).max(0);
+        if a_end < b_end {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    total
+}
+
+/// The offset within `±max_offset_ms` that best lines `candidate` up with `reference`.
+///
+/// Both lists must be merged (see [`merge_intervals`]). Ties go to the offset closest to zero.
+pub fn find_offset(
+    reference: &[(i64, i64)],
+    candidate: &[(i64, i64)],
+    max_offset_ms: i64,
+) -> Alignment {
+    let total: i64 = candidate.iter().map(|(start, end)| end - start).sum();
+    let best_in = |from: i64, to: i64, step: i64| {
+        let mut best = (0i64, i64::MIN);
+        let mut offset = from;
+        while offset <= to {
+            let score = overlap(reference, candidate, offset);
+            if score > best.1 || (score == best.1 && offset.abs() < best.0.abs()) {
+                best = (offset, score);
+            }
+            offset += step;
+        }
+        best
+    };
+    let (coarse, _) = best_in(-max_offset_ms, max_offset_ms, COARSE_STEP_MS);
+    let (offset_ms, score) = best_in(
+        coarse - COARSE_STEP_MS,
+        coarse + COARSE_STEP_MS,
+        FINE_STEP_MS,
+    );
+    Alignment {
+        retiming: Retiming {
+            offset_ms,
+            scale: 1.0,
+        },
+        score: if total > 0 {
+            score as f64 / total as f64
+        } else {
+            0.0
+        },
+    }
+}
+
+/// Read the cues of a track in each file and find the offset that lines up `candidate_track` with
+/// `reference_track`. Works for text and PGS tracks.
+pub async fn subtitle_track_offset(
+    reference_path: &Path,
+    reference_track: &SubtitleTrack,
+    candidate_path: &Path,
+    candidate_track: &SubtitleTrack,
+    max_offset_ms: i64,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Alignment> {
+    let reference = read_cues(reference_path, reference_track, options, cancel).await?;
+    let candidate = read_cues(candidate_path, candidate_track, options, cancel).await?;
+    let alignment = find_offset(
+        &cue_intervals(&reference),
+        &cue_intervals(&candidate),
+        max_offset_ms,
+    );
+    info!(
+        "{} lines up with {} at {} ({:.0}% overlap)",
+        candidate_track,
+        reference_track,
+        alignment.retiming,
+        alignment.score * 100.0
+    );
+    Ok(alignment)
+}
+
+/// The share of the retimed candidate that overlaps the reference
+fn score(reference: &[(i64, i64)], candidate: &[(i64, i64)], retiming: &Retiming) -> f64 {
+    let retimed = retime_intervals(candidate, retiming);
+    let total: i64 = retimed.iter().map(|(start, end)| end - start).sum();
+    if total > 0 {
+        overlap(reference, &retimed, 0) as f64 / total as f64
+    } else {
+        0.0
+    }
+}
+
+/// Line up stretches of the retimed candidate on their own and fit a line through their remaining
+/// offsets, correcting drift that isn't one of the [`SPEED_RATIOS`]
+fn refine_drift(
+    reference: &[(i64, i64)],
+    candidate: &[(i64, i64)],
+    retiming: Retiming,
+) -> Retiming {
+    let retimed = retime_intervals(candidate, &retiming);
+    // (time, remaining offset, weight)
+    let points: Vec<(f64, f64, f64)> = retimed
+        .chunks(retimed.len().div_ceil(DRIFT_CHUNKS).max(1))
+        .filter_map(|chunk| {
+            let local = find_offset(reference, chunk, DRIFT_CHUNK_MAX_OFFSET_MS);
+            let center = (chunk.first()?.0 + chunk.last()?.1) as f64 / 2.0;
+            let weight: i64 = chunk.iter().map(|(start, end)| end - start).sum();
+            (local.score >= 0.5).then_some((center, local.retiming.offset_ms as f64, weight as f64))
+        })
+        .collect();
+    if points.len() < 3 {
+        return retiming;
+    }
+    let total: f64 = points.iter().map(|(_, _, w)| w).sum();
+    let mean_x = points.iter().map(|(x, _, w)| x * w).sum::<f64>() / total;
+    let mean_y = points.iter().map(|(_, y, w)| y * w).sum::<f64>() / total;
+    let spread: f64 = points
+        .iter()
+        .map(|(x, _, w)| w * (x - mean_x).powi(2))
+        .sum();
+    if spread == 0.0 {
+        return retiming;
+    }
+    let slope = points
+        .iter()
+        .map(|(x, y, w)| w * (x - mean_x) * (y - mean_y))
+        .sum::<f64>()
+        / spread;
+    let intercept = mean_y - slope * mean_x;
+    // t'' = t' + intercept + slope * t', where t' = t * scale + offset
+    Retiming {
+        offset_ms: ((1.0 + slope) * retiming.offset_ms as f64 + intercept).round() as i64,
+        scale: retiming.scale * (1.0 + slope),
+    }
+}
+
+/// The retiming that best lines `candidate` up with `reference`: an offset within
+/// `±max_offset_ms`, and with `drift`, a speed change too.
+///
+/// Drift is found by trying the speed changes between common frame rates, then fitting a line
+/// through the offsets of separate stretches. Both lists must be merged (see [`merge_intervals`]).
+pub fn find_retiming(
+    reference: &[(i64, i64)],
+    candidate: &[(i64, i64)],
+    max_offset_ms: i64,
+    drift: bool,
+) -> Alignment {
+    if !drift {
+        return find_offset(reference, candidate, max_offset_ms);
+    }
+    let best = SPEED_RATIOS
+        .iter()
+        .map(|scale| {
+            let scaled = retime_intervals(
+                candidate,
+                &Retiming {
+                    offset_ms: 0,
+                    scale: *scale,
+                },
+            );
+            let alignment = find_offset(reference, &scaled, max_offset_ms);
+            Alignment {
+                retiming: Retiming {
+                    offset_ms: alignment.retiming.offset_ms,
+                    scale: *scale,
+                },
+                score: alignment.score,
+            }
+        })
+        .fold(None, |best: Option<Alignment>, x| match best {
+            Some(best) if best.score >= x.score => Some(best),
+            _ => Some(x),
+        })
+        .unwrap_or_default();
+    let refined = refine_drift(reference, candidate, best.retiming);
+    let refined_score = score(reference, candidate, &refined);
+    if refined_score > best.score {
+        Alignment {
+            retiming: refined,
+            score: refined_score,
+        }
+    } else {
+        best
+    }
+}
@@ -0,0 +1,204 @@
+//! Exporting the cues of a track as Anki flashcards.
+//!
+//! Anki imports a CSV whose header lines name the deck and note type, so a deck is written as
+//! `cards.csv` with its audio clips and screenshots in `media/`, ready to be copied into Anki's
+//! `collection.media` folder.
+
+use crate::clip_file_name;
+use crate::clips::csv_field;
+use crate::confirm_overwrite;
+use crate::export_clips;
+use crate::merge_cues;
+use crate::read_cues;
+use crate::ClipOptions;
+use crate::ClipRequest;
+use crate::Cue;
+use crate::FfmpegOptions;
+use crate::OverwritePolicy;
+use crate::SubtitleTrack;
+use eyre::bail;
+use std::path::Path;
+use std::path::PathBuf;
+use tokio::fs;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// The file Anki imports, inside the output directory
+pub const ANKI_CARDS_FILE_NAME: &str = "cards.csv";
+
+/// The directory the clips and screenshots are written to, inside the output directory
+pub const ANKI_MEDIA_DIR_NAME: &str = "media";
+
+/// What goes on the cards
+#[derive(Debug, Clone, Default)]
+pub struct AnkiOptions {
+    /// The deck the cards are imported into.
+    pub deck: String,
+
+    /// Add a frame from the middle of each cue to the back.
+    pub screenshots: bool,
+
+    /// How the audio clips and screenshots are cut.
+    pub clips: ClipOptions,
+}
+
+/// One note: the cue on the front with its audio, the other language and the screenshot on the back
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AnkiCard {
+    pub text: String,
+
+    /// The cue text of the second track, if any.
+    pub back: String,
+
+    /// The file name of the audio clip in the media folder.
+    pub audio: Option<String>,
+
+    /// The file name of the screenshot in the media folder.
+    pub image: Option<String>,
+
+    /// Space-separated tags.
+    pub tags: String,
+}
+
+/// SRT cue text as a card field: it is already HTML apart from its line breaks
+fn html(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\n', "<br>")
+}
+
+/// `stem` with the characters Anki's media handling rejects replaced, e.g. the `]` that would end
+/// a `[sound:...]` tag early
+fn media_stem(stem: &str) -> String {
+    stem.chars()
+        .map(|c| match c {
+            c if c.is_alphanumeric() || " .,-_()+!".contains(c) => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Work out a card per cue of `front` (skipping empty ones) and the media to cut for them.
+///
+/// `back` cues are paired to the front by overlap, like `--merge-with`. Media files are named with
+/// `stem`, minus the characters Anki rejects, so they don't collide with other decks in Anki's
+/// shared media folder.
+pub fn plan_anki_cards(
+    stem: &str,
+    front: &[Cue],
+    back: &[Cue],
+    media_dir: &Path,
+    anki_options: &AnkiOptions,
+) -> (Vec<AnkiCard>, Vec<ClipRequest>) {
+    let tag = stem.split_whitespace().collect::<Vec<_>>().join("_");
+    let stem = media_stem(stem);
+    let mut cards = Vec::new();
+    let mut clips = Vec::new();
+    for (index, cue) in merge_cues(front, back)
+        .into_iter()
+        .filter(|cue| !cue.primary.trim().is_empty())
+        .enumerate()
+    {
+        let ext = anki_options.clips.audio_format.extension();
+        let audio = clip_file_name(&stem, index + 1, cue.start, ext);
+        let image = anki_options
+            .screenshots
+            .then(|| clip_file_name(&stem, index + 1, cue.start, "jpg"));
+        clips.push(ClipRequest {
+            start: cue.start,
+            end: cue.end,
+            audio: Some(media_dir.join(&audio)),
+            screenshot: image.as_ref().map(|x| media_dir.join(x)),
+        });
+        cards.push(AnkiCard {
+            text: cue.primary,
+            back: cue.secondary,
+            audio: Some(audio),
+            image,
+            tags: tag.clone(),
+        });
+    }
+    (cards, clips)
+}
+
+/// Write cards as a CSV for Anki's importer, for the stock `Basic` note type
+pub fn write_anki_csv(deck: &str, cards: &[AnkiCard]) -> String {
+    let mut out =
+        format!("#separator:Comma\n#html:true\n#notetype:Basic\n#deck:{deck}\n#tags column:3\n");
+    for card in cards {
+        let mut front = html(&card.text);
+        if let Some(audio) = &card.audio {
+            front.push_str(&format!("<br>[sound:{audio}]"));
+        }
+        let back = [
+            Some(html(&card.back)).filter(|x| !x.is_empty()),
+            card.image.as_ref().map(|x| format!("<img src=\"{x}\">")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join("<br>");
+        out.push_str(&format!(
+            "{},{},{}\n",
+            csv_field(&front),
+            csv_field(&back),
+            csv_field(&card.tags)
+        ));
+    }
+    out
+}
+
+/// Export a card per cue of the text track `front` of `video` to `output_dir`, with the paired cues
+/// of `back` on the other side.
+///
+/// Returns the CSV written, or `None` if the user chose to keep an existing export.
+#[allow(clippy::too_many_arguments)]
+pub async fn export_anki_deck(
+    video: &Path,
+    front: &SubtitleTrack,
+    back: Option<&SubtitleTrack>,
+    output_dir: &Path,
+    anki_options: &AnkiOptions,
+    overwrite: OverwritePolicy,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Option<PathBuf>> {
+    for track in std::iter::once(front).chain(back) {
+        if !track.is_text() {
+            bail!("Only text tracks can be made into cards: {}", track);
+        }
+    }
+    let csv_path = output_dir.join(ANKI_CARDS_FILE_NAME);
+    if fs::try_exists(&csv_path).await? && !confirm_overwrite(&csv_path, overwrite)? {
+        info!("Skipping existing file: {}", csv_path.display());
+        return Ok(None);
+    }
+    let front_cues = read_cues(video, front, options, cancel).await?;
+    let back_cues = match back {
+        Some(track) => read_cues(video, track, options, cancel).await?,
+        None => Vec::new(),
+    };
+    let media_dir = output_dir.join(ANKI_MEDIA_DIR_NAME);
+    let stem = video.file_stem().unwrap_or_default().to_string_lossy();
+    let (cards, clips) = plan_anki_cards(&stem, &front_cues, &back_cues, &media_dir, anki_options);
+    if cards.is_empty() {
+        bail!("{} has no cues with text", front);
+    }
+    fs::create_dir_all(&media_dir).await?;
+    info!(
+        "Cutting media for {} cards into {}",
+        cards.len(),
+        media_dir.display()
+    );
+    export_clips(
+        video,
+        &clips,
+        &anki_options.clips,
+        overwrite,
+        options,
+        cancel,
+    )
+    .await?;
+    // Written last, so an interrupted export doesn't leave cards pointing at missing media
+    fs::write(&csv_path, write_anki_csv(&anki_options.deck, &cards)).await?;
+    info!("Wrote {} cards to {}", cards.len(), csv_path.display());
+    Ok(Some(csv_path))
+}
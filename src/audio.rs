@@ -0,0 +1,258 @@
+//! Decoding audio through ffmpeg into a loudness envelope, and lining up two envelopes.
+//!
+//! ffmpeg band-passes the audio to the speech range and resamples it to 8 kHz mono PCM, which is
+//! streamed and reduced to one RMS value per 10 ms frame, so a whole film needs a few megabytes
+//! rather than the decoded audio.
+
+use crate::stream_with_limits;
+use crate::FfmpegOptions;
+use eyre::bail;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::process::ChildStdout;
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+use tracing::info;
+
+/// The sample rate ffmpeg resamples to; enough for the speech band
+const SAMPLE_RATE: usize = 8000;
+
+/// Drops rumble and hiss, leaving mostly dialogue
+const SPEECH_BAND_FILTER: &str = "highpass=f=200,lowpass=f=3400";
+
+/// The length of one envelope frame
+pub const FRAME_MS: i64 = 10;
+
+const SAMPLES_PER_FRAME: usize = SAMPLE_RATE * FRAME_MS as usize / 1000;
+
+/// How far from the noise floor towards the typical speech level a frame must be to count as speech
+const SPEECH_THRESHOLD: f32 = 0.4;
+
+/// How many frames the loudness is smoothed over before it is compared with the threshold
+const SMOOTHING_FRAMES: usize = 5;
+
+/// Shorter pauses are bridged, since they are gaps between words rather than between lines
+const MAX_PAUSE_MS: i64 = 300;
+
+/// Shorter bursts are clicks and bangs rather than speech
+const MIN_SPEECH_MS: i64 = 200;
+
+/// How much of each file is decoded to line two releases up
+const AUDIO_SAMPLE_LENGTH: Duration = Duration::from_secs(10 * 60);
+
+/// How many frames the first pass of [`envelope_offset`] averages together
+const COARSE_FRAMES: usize = 10;
+
+/// The RMS loudness (0 to 1) of each 10 ms frame of an audio stream.
+///
+/// `audio_stream` counts audio streams only, like `-map 0:a:N`. With `limit`, only that much of the
+/// start of the file is decoded.
+pub async fn decode_audio_envelope(
+    path: &Path,
+    audio_stream: u32,
+    limit: Option<Duration>,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Vec<f32>> {
+    let mut cmd = Command::new(&options.ffmpeg);
+    cmd.args(["-v", "error", "-i"])
+        .arg(path)
+        .args(["-map", &format!("0:a:{audio_stream}")])
+        .args(["-af", SPEECH_BAND_FILTER])
+        .args(["-ac", "1", "-ar", &SAMPLE_RATE.to_string()]);
+    if let Some(limit) = limit {
+        cmd.args(["-t", &limit.as_secs_f64().to_string()]);
+    }
+    cmd.args(["-f", "s16le", "pipe:1"]);
+    debug!("Running {:?}", cmd.as_std());
+
+    let read = |mut stdout: ChildStdout| async move {
+        let mut envelope = Vec::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut pending: Option<u8> = None;
+        let (mut sum, mut count) = (0f64, 0usize);
+        loop {
+            let n = stdout.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            let mut bytes = buf[..n].iter().copied();
+            loop {
+                let (lo, hi) = match pending.take() {
+                    Some(lo) => match bytes.next() {
+                        Some(hi) => (lo, hi),
+                        None => {
+                            pending = Some(lo);
+                            break;
+                        }
+                    },
+                    None => match (bytes.next(), bytes.next()) {
+                        (Some(lo), Some(hi)) => (lo, hi),
+                        (Some(lo), None) => {
+                            pending = Some(lo);
+                            break;
+                        }
+                        _ => break,
+                    },
+                };
+                let sample = i16::from_le_bytes([lo, hi]) as f64 / i16::MAX as f64;
+                sum += sample * sample;
+                count += 1;
+                if count == SAMPLES_PER_FRAME {
+                    envelope.push((sum / count as f64).sqrt() as f32);
+                    (sum, count) = (0.0, 0);
+                }
+            }
+        }
+        eyre::Ok(envelope)
+    };
+    let (output, envelope) = stream_with_limits(cmd, options.extract_timeout, cancel, read).await?;
+    if !output.status.success() {
+        bail!(
+            "Failed to decode the audio of {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    if envelope.is_empty() {
+        bail!("No audio decoded from {}", path.display());
+    }
+    Ok(envelope)
+}
+
+/// Log loudness with zero mean and unit variance, so two releases mastered differently compare
+fn normalize(envelope: &[f32]) -> Vec<f32> {
+    let logs: Vec<f32> = envelope.iter().map(|x| (x + 1e-4).ln()).collect();
+    let mean = logs.iter().sum::<f32>() / logs.len().max(1) as f32;
+    let variance = logs.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / logs.len().max(1) as f32;
+    let std = variance.sqrt().max(1e-6);
+    logs.iter().map(|x| (x - mean) / std).collect()
+}
+
+/// Average every `n` values
+fn downsample(values: &[f32], n: usize) -> Vec<f32> {
+    values
+        .chunks(n)
+        .map(|chunk| chunk.iter().sum::<f32>() / chunk.len() as f32)
+        .collect()
+}
+
+/// The mean product of `reference[i + lag]` and `candidate[i]` where both exist, or `None` if they
+/// share less than half of the shorter signal
+fn correlation(reference: &[f32], candidate: &[f32], lag: i64) -> Option<f64> {
+    let from = (-lag).max(0) as usize;
+    let to = (reference.len() as i64 - lag).min(candidate.len() as i64);
+    if to <= from as i64 {
+        return None;
+    }
+    let to = to as usize;
+    if (to - from) * 2 < reference.len().min(candidate.len()) {
+        return None;
+    }
+    let sum: f64 = (from..to)
+        .map(|i| reference[(i as i64 + lag) as usize] as f64 * candidate[i] as f64)
+        .sum();
+    Some(sum / (to - from) as f64)
+}
+
+fn best_lag(
+    reference: &[f32],
+    candidate: &[f32],
+    lags: std::ops::RangeInclusive<i64>,
+) -> (i64, f64) {
+    lags.filter_map(|lag| Some((lag, correlation(reference, candidate, lag)?)))
+        .fold((0, f64::MIN), |best, (lag, score)| {
+            if score > best.1 {
+                (lag, score)
+            } else {
+                best
+            }
+        })
+}
+
+/// The offset in milliseconds to add to `candidate` times so its audio lines up with `reference`,
+/// within `±max_offset_ms`, and the correlation there (1 is a perfect match, below ~0.3 is noise).
+pub fn envelope_offset(reference: &[f32], candidate: &[f32], max_offset_ms: i64) -> (i64, f64) {
+    let reference = normalize(reference);
+    let candidate = normalize(candidate);
+    let coarse_ms = FRAME_MS * COARSE_FRAMES as i64;
+    let max_lag = max_offset_ms / coarse_ms;
+    let (coarse, _) = best_lag(
+        &downsample(&reference, COARSE_FRAMES),
+        &downsample(&candidate, COARSE_FRAMES),
+        -max_lag..=max_lag,
+    );
+    let center = coarse * COARSE_FRAMES as i64;
+    let span = COARSE_FRAMES as i64;
+    let (lag, score) = best_lag(&reference, &candidate, center - span..=center + span);
+    (lag * FRAME_MS, score)
+}
+
+/// The offset in milliseconds to add to the times of `candidate` so they match `reference`, from
+/// the first audio track of the first minutes of each, and the correlation there.
+pub async fn audio_offset(
+    reference: &Path,
+    candidate: &Path,
+    max_offset_ms: i64,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<(i64, f64)> {
+    let limit = Some(AUDIO_SAMPLE_LENGTH);
+    let reference_envelope = decode_audio_envelope(reference, 0, limit, options, cancel).await?;
+    let candidate_envelope = decode_audio_envelope(candidate, 0, limit, options, cancel).await?;
+    let (offset_ms, score) =
+        envelope_offset(&reference_envelope, &candidate_envelope, max_offset_ms);
+    info!(
+        "The audio of {} lines up with {} at {:+.3}s (correlation {:.2})",
+        candidate.display(),
+        reference.display(),
+        offset_ms as f64 / 1000.0,
+        score
+    );
+    Ok((offset_ms, score))
+}
+
+/// The `(start, end)` intervals in milliseconds where an envelope sounds like speech.
+///
+/// A frame is speech when its smoothed loudness is well above the noise floor (the 10th percentile)
+/// relative to the level of the louder frames (the 90th percentile), so it adapts to how the film
+/// was mixed. Short pauses are bridged and short bursts dropped.
+pub fn detect_speech(envelope: &[f32]) -> Vec<(i64, i64)> {
+    if envelope.is_empty() {
+        return Vec::new();
+    }
+    let logs: Vec<f32> = envelope.iter().map(|x| (x + 1e-4).ln()).collect();
+    let smoothed: Vec<f32> = (0..logs.len())
+        .map(|i| {
+            let window = &logs[i.saturating_sub(SMOOTHING_FRAMES / 2)
+                ..(i + SMOOTHING_FRAMES / 2 + 1).min(logs.len())];
+            window.iter().sum::<f32>() / window.len() as f32
+        })
+        .collect();
+    let mut sorted = smoothed.clone();
+    sorted.sort_by(f32::total_cmp);
+    let floor = sorted[sorted.len() / 10];
+    let level = sorted[sorted.len() * 9 / 10];
+    let threshold = floor + SPEECH_THRESHOLD * (level - floor);
+
+    let mut intervals: Vec<(i64, i64)> = Vec::new();
+    let mut start = None;
+    for (i, value) in smoothed.iter().chain([&f32::MIN]).enumerate() {
+        let time = i as i64 * FRAME_MS;
+        match (start, *value > threshold) {
+            (None, true) => start = Some(time),
+            (Some(from), false) => {
+                match intervals.last_mut() {
+                    Some(last) if from - last.1 < MAX_PAUSE_MS => last.1 = time,
+                    _ => intervals.push((from, time)),
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    intervals.retain(|(start, end)| end - start >= MIN_SPEECH_MS);
+    intervals
+}
@@ -0,0 +1,362 @@
+//! Conversion between the bitmap subtitle formats: PGS (`.sup`) and VobSub (`.idx`/`.sub`).
+//!
+//! Both formats are decoded to [`RenderedSubtitle`] images, optionally resized to a new screen
+//! resolution, then quantized down to the palette the target format allows.
+
+use crate::confirm_overwrite;
+use crate::pgs;
+use crate::pgs::RenderedSubtitle;
+use crate::vobsub;
+use crate::FfmpegOptions;
+use crate::OverwritePolicy;
+use crate::SubtitleTrack;
+use clap::ValueEnum;
+use eyre::bail;
+use eyre::eyre;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use tokio::fs;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+use tracing::warn;
+
+/// VobSub screens are DVD sized, NTSC unless `--resolution 720x576` is given
+pub const DEFAULT_VOBSUB_RESOLUTION: (u16, u16) = (720, 480);
+
+/// A bitmap subtitle file format to convert to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BitmapFormat {
+    /// Blu-ray PGS, `.sup`
+    Sup,
+    /// DVD VobSub, `.idx` + `.sub`
+    Vobsub,
+}
+
+impl BitmapFormat {
+    /// The extension of the file that names the subtitle, `.sup` or `.idx`
+    pub fn ext(self) -> &'static str {
+        match self {
+            BitmapFormat::Sup => "sup",
+            BitmapFormat::Vobsub => "idx",
+        }
+    }
+}
+
+/// Parse a `WIDTHxHEIGHT` resolution, e.g. `720x576`
+pub fn parse_resolution(value: &str) -> eyre::Result<(u16, u16)> {
+    let (width, height) = value
+        .split_once(['x', 'X'])
+        .ok_or_else(|| eyre!("Expected WIDTHxHEIGHT, e.g. 720x480, got {value:?}"))?;
+    let width: u16 = width.trim().parse()?;
+    let height: u16 = height.trim().parse()?;
+    if width == 0 || height == 0 {
+        bail!("Resolution must not be zero, got {value:?}");
+    }
+    Ok((width, height))
+}
+
+/// Split weighted colours into at most `max_boxes` groups of similar colours (median cut).
+///
+/// Returns the groups as indices into `colors`.
+pub(crate) fn median_cut(colors: &[([u8; 4], u32)], max_boxes: usize) -> Vec<Vec<usize>> {
+    if colors.is_empty() || max_boxes == 0 {
+        return Vec::new();
+    }
+    let range = |group: &[usize], channel: usize| {
+        let values = group.iter().map(|&i| colors[i].0[channel]);
+        values.clone().max().unwrap_or(0) - values.min().unwrap_or(0)
+    };
+    let mut boxes = vec![(0..colors.len()).collect::<Vec<_>>()];
+    while boxes.len() < max_boxes {
+        // Split the group with the widest spread in any channel at its weighted median
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, group)| group.len() > 1)
+            .flat_map(|(i, group)| (0..4).map(move |channel| (i, channel, group)))
+            .max_by_key(|(_, channel, group)| range(group, *channel));
+        let Some((i, channel, _)) = widest else {
+            break;
+        };
+        let mut group = boxes.swap_remove(i);
+        group.sort_by_key(|&c| colors[c].0[channel]);
+        let total: u64 = group.iter().map(|&c| colors[c].1 as u64).sum();
+        let mut seen = 0;
+        let mut split = group.len() / 2;
+        for (k, &c) in group.iter().enumerate() {
+            seen += colors[c].1 as u64;
+            if seen * 2 >= total {
+                split = k + 1;
+                break;
+            }
+        }
+        let rest = group.split_off(split.clamp(1, group.len() - 1));
+        boxes.push(group);
+        boxes.push(rest);
+    }
+    boxes
+}
+
+/// The weighted average colour of a median cut group
+pub(crate) fn mean_color(colors: &[([u8; 4], u32)], group: &[usize]) -> [u8; 4] {
+    let mut sums = [0u64; 4];
+    let mut total = 0u64;
+    for &i in group {
+        let (color, weight) = colors[i];
+        for (sum, value) in sums.iter_mut().zip(color) {
+            *sum += value as u64 * weight as u64;
+        }
+        total += weight as u64;
+    }
+    sums.map(|sum| (sum + total / 2).checked_div(total).unwrap_or(0) as u8)
+}
+
+/// Reduce RGBA pixels to at most `max_colors` colours, returning the palette and one index per pixel.
+///
+/// Palette entry 0 is always fully transparent and used for every pixel with zero alpha.
+pub fn quantize(rgba: &[u8], max_colors: usize) -> (Vec<[u8; 4]>, Vec<u8>) {
+    let mut counts: HashMap<[u8; 4], u32> = HashMap::new();
+    for px in rgba.chunks_exact(4) {
+        if px[3] != 0 {
+            *counts.entry([px[0], px[1], px[2], px[3]]).or_default() += 1;
+        }
+    }
+    let mut colors: Vec<([u8; 4], u32)> = counts.into_iter().collect();
+    colors.sort();
+
+    let mut palette = vec![[0u8; 4]];
+    let mut lookup = HashMap::new();
+    for group in median_cut(&colors, max_colors.clamp(1, 256) - 1) {
+        for &i in &group {
+            lookup.insert(colors[i].0, palette.len() as u8);
+        }
+        palette.push(mean_color(&colors, &group));
+    }
+    let indices = rgba
+        .chunks_exact(4)
+        .map(|px| match px[3] {
+            0 => 0,
+            _ => lookup[&[px[0], px[1], px[2], px[3]]],
+        })
+        .collect();
+    (palette, indices)
+}
+
+/// Scale an image and its position from its screen size to `screen_width` x `screen_height`.
+///
+/// Each output pixel averages the source pixels it covers, weighting colour by alpha.
+pub fn resize_subtitle(
+    image: &RenderedSubtitle,
+    screen_width: u16,
+    screen_height: u16,
+) -> RenderedSubtitle {
+    if (image.screen_width, image.screen_height) == (screen_width, screen_height) {
+        return image.clone();
+    }
+    let scale_x = screen_width as f64 / image.screen_width.max(1) as f64;
+    let scale_y = screen_height as f64 / image.screen_height.max(1) as f64;
+    let x = ((image.x as f64 * scale_x).round() as u16).min(screen_width - 1);
+    let y = ((image.y as f64 * scale_y).round() as u16).min(screen_height - 1);
+    let width = ((image.width as f64 * scale_x).round() as u16).clamp(1, screen_width - x);
+    let height = ((image.height as f64 * scale_y).round() as u16).clamp(1, screen_height - y);
+
+    // Source pixels [start, end) covered by output pixel `i` of `n`
+    let span = |i: usize, n: usize, source: usize| {
+        let start = i * source / n;
+        let end = ((i + 1) * source).div_ceil(n).max(start + 1).min(source);
+        start..end
+    };
+    let (src_w, src_h) = (image.width as usize, image.height as usize);
+    let mut rgba = vec![0u8; width as usize * height as usize * 4];
+    for dy in 0..height as usize {
+        let rows = span(dy, height as usize, src_h);
+        for dx in 0..width as usize {
+            let cols = span(dx, width as usize, src_w);
+            let mut sums = [0u64; 4];
+            let mut count = 0u64;
+            for sy in rows.clone() {
+                for sx in cols.clone() {
+                    let px = &image.rgba[(sy * src_w + sx) * 4..][..4];
+                    let alpha = px[3] as u64;
+                    sums[0] += px[0] as u64 * alpha;
+                    sums[1] += px[1] as u64 * alpha;
+                    sums[2] += px[2] as u64 * alpha;
+                    sums[3] += alpha;
+                    count += 1;
+                }
+            }
+            if sums[3] == 0 {
+                continue;
+            }
+            let dst = (dy * width as usize + dx) * 4;
+            rgba[dst] = (sums[0] / sums[3]) as u8;
+            rgba[dst + 1] = (sums[1] / sums[3]) as u8;
+            rgba[dst + 2] = (sums[2] / sums[3]) as u8;
+            rgba[dst + 3] = (sums[3] / count) as u8;
+        }
+    }
+    RenderedSubtitle {
+        x,
+        y,
+        width,
+        height,
+        screen_width,
+        screen_height,
+        rgba,
+        ..image.clone()
+    }
+}
+
+/// Read a `.sup` file, or a `.idx`/`.sub` pair, as images
+pub async fn read_bitmap_subtitles(path: &Path) -> eyre::Result<Vec<RenderedSubtitle>> {
+    let ext = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "sup" => {
+            let data = fs::read(path).await?;
+            pgs::render_display_sets(&pgs::parse_display_sets(&data)?)
+        }
+        "idx" | "sub" => {
+            let idx_path = path.with_extension("idx");
+            let idx = fs::read_to_string(&idx_path)
+                .await
+                .map_err(|e| eyre!("Failed to read {}: {}", idx_path.display(), e))?;
+            let header = vobsub::parse_idx(&idx)?;
+            let data = fs::read(path.with_extension("sub")).await?;
+            vobsub::decode_sub(&header, &data)
+        }
+        _ => bail!("Expected a .sup, .idx or .sub file, got {}", path.display()),
+    }
+}
+
+/// Write images as `format` next to `path` (its extension is replaced), returning the files written
+pub async fn write_bitmap_subtitles(
+    images: &[RenderedSubtitle],
+    format: BitmapFormat,
+    path: &Path,
+    lang: Option<&str>,
+) -> eyre::Result<Vec<PathBuf>> {
+    match format {
+        BitmapFormat::Sup => {
+            let sup_path = path.with_extension("sup");
+            let sets = pgs::encode_display_sets(images);
+            fs::write(&sup_path, pgs::write_display_sets(&sets)).await?;
+            Ok(vec![sup_path])
+        }
+        BitmapFormat::Vobsub => {
+            let (idx, sub) = vobsub::encode_vobsub(images, lang)?;
+            let idx_path = path.with_extension("idx");
+            let sub_path = path.with_extension("sub");
+            fs::write(&sub_path, sub).await?;
+            fs::write(&idx_path, idx).await?;
+            Ok(vec![idx_path, sub_path])
+        }
+    }
+}
+
+/// Convert a `.sup` or `.idx`/`.sub` file to `to`, written next to `output` with the right extension.
+///
+/// Images are resized to `resolution`, or for VobSub to [`DEFAULT_VOBSUB_RESOLUTION`] when they are
+/// larger than a DVD screen. Returns the files written, or `None` if the user chose to keep an existing one.
+pub async fn convert_bitmap_subtitles(
+    input: &Path,
+    output: &Path,
+    to: BitmapFormat,
+    resolution: Option<(u16, u16)>,
+    lang: Option<&str>,
+    overwrite: OverwritePolicy,
+) -> eyre::Result<Option<Vec<PathBuf>>> {
+    let target = output.with_extension(to.ext());
+    if fs::try_exists(&target).await? && !confirm_overwrite(&target, overwrite)? {
+        info!("Skipping existing file: {}", target.display());
+        return Ok(None);
+    }
+    info!("Converting {} to {:?}", input.display(), to);
+    let mut images = read_bitmap_subtitles(input).await?;
+    let too_big_for_dvd = images
+        .iter()
+        .any(|image| image.screen_width > 720 || image.screen_height > 576);
+    let resolution = match (resolution, to) {
+        (Some(resolution), _) => Some(resolution),
+        (None, BitmapFormat::Vobsub) if too_big_for_dvd => Some(DEFAULT_VOBSUB_RESOLUTION),
+        (None, _) => None,
+    };
+    if let Some((width, height)) = resolution {
+        info!("Resizing {} images to {}x{}", images.len(), width, height);
+        images = images
+            .iter()
+            .map(|image| resize_subtitle(image, width, height))
+            .collect();
+    }
+    let written = write_bitmap_subtitles(&images, to, output, lang).await?;
+    info!("Wrote {} images to {}", images.len(), written[0].display());
+    Ok(Some(written))
+}
+
+/// Convert a freshly extracted PGS (`.sup`) or DVD (`.sub`) track to `to`, removing the intermediate files.
+///
+/// DVD tracks arrive as a bare `.sub`; their `.idx` is written from the palette ffprobe reports first.
+/// Returns the main output file, or `None` if the user chose to keep an existing one.
+#[allow(clippy::too_many_arguments)]
+pub async fn convert_extracted_track(
+    input: &Path,
+    track: &SubtitleTrack,
+    extracted: &Path,
+    to: BitmapFormat,
+    resolution: Option<(u16, u16)>,
+    overwrite: OverwritePolicy,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Option<PathBuf>> {
+    let ext = extracted
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let lang = track.language();
+
+    let source = match ext.as_str() {
+        "sup" => extracted.to_path_buf(),
+        "sub" => {
+            let header = vobsub::probe_idx_header(input, track, options, cancel).await?;
+            let data = fs::read(extracted).await?;
+            let packets = vobsub::parse_sub(&data)?;
+            let idx_path = extracted.with_extension("idx");
+            fs::write(&idx_path, vobsub::write_idx(&header, &packets, lang)).await?;
+            idx_path
+        }
+        _ => {
+            warn!(
+                "Only PGS and DVD bitmap tracks can be converted, keeping {}",
+                extracted.display()
+            );
+            return Ok(Some(extracted.to_path_buf()));
+        }
+    };
+    let same_format = match to {
+        BitmapFormat::Sup => ext == "sup",
+        BitmapFormat::Vobsub => ext == "sub",
+    };
+    if same_format && resolution.is_none() {
+        return Ok(Some(source));
+    }
+
+    // Resizing in place replaces the file we just extracted, which needs no confirmation
+    let overwrite = match same_format {
+        true => OverwritePolicy::Always,
+        false => overwrite,
+    };
+    let written =
+        convert_bitmap_subtitles(&source, extracted, to, resolution, lang, overwrite).await?;
+    if !same_format {
+        for intermediate in [extracted.to_path_buf(), source] {
+            let kept = written.as_ref().is_some_and(|x| x.contains(&intermediate));
+            if !kept && fs::try_exists(&intermediate).await? {
+                fs::remove_file(&intermediate).await?;
+            }
+        }
+    }
+    Ok(written.map(|_| extracted.with_extension(to.ext())))
+}
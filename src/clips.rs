@@ -0,0 +1,405 @@
+//! Cutting an audio clip and grabbing a frame of video for each cue, for flashcards, datasets and
+//! clip-making.
+
+use crate::confirm_overwrite;
+use crate::format_srt_timestamp;
+use crate::langdetect::strip_markup;
+use crate::read_cues;
+use crate::remux::path_arg;
+use crate::run_with_limits;
+use crate::Cue;
+use crate::FfmpegOptions;
+use crate::OverwritePolicy;
+use crate::SubtitleTrack;
+use clap::ValueEnum;
+use eyre::bail;
+use regex::Regex;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::fs;
+use tokio::process::Command;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+use tracing::info;
+
+/// The timing file written next to the clips
+pub const CLIP_INDEX_FILE_NAME: &str = "index.csv";
+
+/// The most ffmpeg processes run at once
+const MAX_PARALLEL_CLIPS: usize = 8;
+
+/// The file audio clips are written as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum AudioFormat {
+    /// AAC in `.m4a`, supported by every ffmpeg build and by Anki
+    #[default]
+    M4a,
+    /// MP3, which needs an ffmpeg built with libmp3lame
+    Mp3,
+    /// Uncompressed 16-bit PCM
+    Wav,
+    /// Lossless FLAC
+    Flac,
+}
+
+impl AudioFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AudioFormat::M4a => "m4a",
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::Wav => "wav",
+            AudioFormat::Flac => "flac",
+        }
+    }
+
+    fn encoder_args(&self) -> &'static [&'static str] {
+        match self {
+            AudioFormat::M4a => &["-c:a", "aac", "-b:a", "128k"],
+            AudioFormat::Mp3 => &["-c:a", "libmp3lame", "-q:a", "4"],
+            AudioFormat::Wav => &["-c:a", "pcm_s16le"],
+            AudioFormat::Flac => &["-c:a", "flac"],
+        }
+    }
+}
+
+/// How clips are cut
+#[derive(Debug, Clone)]
+pub struct ClipOptions {
+    /// The audio stream to cut from, counting audio streams only (the N in `0:a:N`).
+    pub audio_stream: u32,
+
+    pub audio_format: AudioFormat,
+
+    /// Added before and after each cue, since cue timing is rarely exact.
+    pub padding: Duration,
+
+    /// Scale screenshots to this height, keeping the aspect ratio; `None` keeps the video's size.
+    pub screenshot_height: Option<u32>,
+}
+
+impl Default for ClipOptions {
+    fn default() -> Self {
+        Self {
+            audio_stream: 0,
+            audio_format: AudioFormat::default(),
+            padding: Duration::from_millis(250),
+            screenshot_height: None,
+        }
+    }
+}
+
+/// The media to cut for one cue
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipRequest {
+    pub start: Duration,
+    pub end: Duration,
+
+    /// Where to write the audio clip, if wanted.
+    pub audio: Option<PathBuf>,
+
+    /// Where to write a frame from the middle of the cue, if wanted.
+    pub screenshot: Option<PathBuf>,
+}
+
+/// Quote a CSV field, doubling the quotes inside it
+pub(crate) fn csv_field(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+fn seconds_arg(time: Duration) -> String {
+    format!("{:.3}", time.as_secs_f64())
+}
+
+/// The file name of a clip, e.g. `Movie.0012.00-01-02.345.m4a` for the 12th cue at 1:02.345
+pub fn clip_file_name(stem: &str, index: usize, start: Duration, ext: &str) -> String {
+    let ms = start.as_millis();
+    format!(
+        "{stem}.{index:04}.{:02}-{:02}-{:02}.{:03}.{ext}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// The ffmpeg arguments that cut `start..end` (padded) of the audio of `video` to `output`
+pub fn audio_clip_args(
+    video: &Path,
+    start: Duration,
+    end: Duration,
+    output: &Path,
+    clip_options: &ClipOptions,
+) -> Vec<String> {
+    let start = start.saturating_sub(clip_options.padding);
+    let end = end + clip_options.padding;
+    let mut args = vec![
+        "-v".to_string(),
+        "error".to_string(),
+        // Before `-i`, so ffmpeg seeks the input instead of decoding up to the start
+        "-ss".to_string(),
+        seconds_arg(start),
+        "-i".to_string(),
+        path_arg(video),
+        "-t".to_string(),
+        seconds_arg(end.saturating_sub(start)),
+        "-map".to_string(),
+        format!("0:a:{}", clip_options.audio_stream),
+    ];
+    args.extend(
+        clip_options
+            .audio_format
+            .encoder_args()
+            .iter()
+            .map(|x| x.to_string()),
+    );
+    args.push(path_arg(output));
+    args
+}
+
+/// The ffmpeg arguments that save the frame of `video` at `at` to `output`, e.g. a `.jpg`
+pub fn screenshot_args(
+    video: &Path,
+    at: Duration,
+    output: &Path,
+    clip_options: &ClipOptions,
+) -> Vec<String> {
+    let mut args = vec![
+        "-v".to_string(),
+        "error".to_string(),
+        "-ss".to_string(),
+        seconds_arg(at),
+        "-i".to_string(),
+        path_arg(video),
+        "-map".to_string(),
+        "0:v:0".to_string(),
+        "-frames:v".to_string(),
+        "1".to_string(),
+    ];
+    if let Some(height) = clip_options.screenshot_height {
+        // -2 keeps the width even, which some encoders need
+        args.extend(["-vf".to_string(), format!("scale=-2:{height}")]);
+    }
+    args.extend(["-q:v".to_string(), "3".to_string(), path_arg(output)]);
+    args
+}
+
+/// Run the ffmpeg commands for every request, a few at a time, stopping at the first failure.
+///
+/// Existing files are only replaced if `overwrite` allows it.
+pub async fn export_clips(
+    video: &Path,
+    requests: &[ClipRequest],
+    clip_options: &ClipOptions,
+    overwrite: OverwritePolicy,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<()> {
+    let mut jobs = Vec::new();
+    for request in requests {
+        if let Some(audio) = &request.audio {
+            jobs.push((
+                audio,
+                audio_clip_args(video, request.start, request.end, audio, clip_options),
+            ));
+        }
+        if let Some(screenshot) = &request.screenshot {
+            let middle = request.start + (request.end.saturating_sub(request.start)) / 2;
+            jobs.push((
+                screenshot,
+                screenshot_args(video, middle, screenshot, clip_options),
+            ));
+        }
+    }
+    let mut confirmed = Vec::with_capacity(jobs.len());
+    for (output, mut args) in jobs {
+        if fs::try_exists(output).await? {
+            if !confirm_overwrite(output, overwrite)? {
+                info!("Skipping existing file: {}", output.display());
+                continue;
+            }
+            args.insert(0, "-y".to_string());
+        }
+        confirmed.push(args);
+    }
+    let jobs = confirmed;
+    let parallel = std::thread::available_parallelism()
+        .map_or(1, |x| x.get())
+        .min(MAX_PARALLEL_CLIPS);
+    let total = jobs.len();
+    let mut jobs = jobs.into_iter();
+    let mut running = JoinSet::new();
+    let mut done = 0;
+    loop {
+        while running.len() < parallel {
+            let Some(args) = jobs.next() else {
+                break;
+            };
+            let mut cmd = Command::new(&options.ffmpeg);
+            cmd.args(&args);
+            debug!("Running {:?}", cmd.as_std());
+            let timeout = options.extract_timeout;
+            let cancel = cancel.clone();
+            running.spawn(async move {
+                let output = run_with_limits(cmd, timeout, &cancel).await?;
+                if !output.status.success() {
+                    bail!(
+                        "ffmpeg failed writing {}: {}",
+                        args.last().map(String::as_str).unwrap_or_default(),
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    );
+                }
+                Ok(())
+            });
+        }
+        let Some(result) = running.join_next().await else {
+            break;
+        };
+        // Returning drops the set, which aborts the others and kills their ffmpeg
+        result??;
+        done += 1;
+        if done % 100 == 0 {
+            info!("Exported {done} of {total} clips");
+        }
+    }
+    info!("Exported {total} clips");
+    Ok(())
+}
+
+/// Which cues to export, and what for each
+#[derive(Debug, Clone, Default)]
+pub struct CueClipOptions {
+    /// Only cues whose text (without markup) matches.
+    pub filter: Option<Regex>,
+
+    /// Cut the audio of each cue.
+    pub audio: bool,
+
+    /// Grab the frame in the middle of each cue.
+    pub screenshots: bool,
+
+    /// How the clips are cut.
+    pub clips: ClipOptions,
+}
+
+/// A cue chosen for export, with its 1-based position in the track
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CueClip {
+    pub index: usize,
+    pub cue: Cue,
+    pub request: ClipRequest,
+}
+
+/// The cues of a track to export and the files to cut for them, named after `stem`, the cue's
+/// position in the whole track and its start (see [`clip_file_name`])
+pub fn plan_cue_clips(
+    stem: &str,
+    cues: &[Cue],
+    output_dir: &Path,
+    cue_clip_options: &CueClipOptions,
+) -> Vec<CueClip> {
+    let ext = cue_clip_options.clips.audio_format.extension();
+    cues.iter()
+        .enumerate()
+        .map(|(i, cue)| (i + 1, cue))
+        .filter(|(_, cue)| {
+            cue_clip_options
+                .filter
+                .as_ref()
+                .is_none_or(|filter| filter.is_match(&strip_markup(&cue.text)))
+        })
+        .map(|(index, cue)| CueClip {
+            index,
+            cue: cue.clone(),
+            request: ClipRequest {
+                start: cue.start,
+                end: cue.end,
+                audio: cue_clip_options
+                    .audio
+                    .then(|| output_dir.join(clip_file_name(stem, index, cue.start, ext))),
+                screenshot: cue_clip_options
+                    .screenshots
+                    .then(|| output_dir.join(clip_file_name(stem, index, cue.start, "jpg"))),
+            },
+        })
+        .collect()
+}
+
+/// The `index.csv` listing each exported cue's timing, files and text
+pub fn write_clip_index(clips: &[CueClip]) -> String {
+    let file_name = |path: &Option<PathBuf>| {
+        path.as_ref()
+            .and_then(|x| x.file_name())
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+    let mut out = String::from("index,start,end,audio,screenshot,text\n");
+    for clip in clips {
+        let text = strip_markup(&clip.cue.text)
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        // Every field is quoted, since SRT timestamps and file names can contain commas
+        let fields = [
+            clip.index.to_string(),
+            format_srt_timestamp(clip.cue.start),
+            format_srt_timestamp(clip.cue.end),
+            file_name(&clip.request.audio),
+            file_name(&clip.request.screenshot),
+            text,
+        ];
+        out.push_str(
+            &fields
+                .iter()
+                .map(|x| csv_field(x))
+                .collect::<Vec<_>>()
+                .join(","),
+        );
+        out.push('\n');
+    }
+    out
+}
+
+/// Export the audio and/or a frame of each (matching) cue of the text track `track` of `video` to
+/// `output_dir`, with an [`CLIP_INDEX_FILE_NAME`] listing them.
+///
+/// Returns how many cues were exported, or `None` if the user chose to keep an existing export.
+pub async fn export_cue_clips(
+    video: &Path,
+    track: &SubtitleTrack,
+    output_dir: &Path,
+    cue_clip_options: &CueClipOptions,
+    overwrite: OverwritePolicy,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Option<usize>> {
+    if !track.is_text() {
+        bail!("Only the cues of text tracks can be exported: {}", track);
+    }
+    let index_path = output_dir.join(CLIP_INDEX_FILE_NAME);
+    if fs::try_exists(&index_path).await? && !confirm_overwrite(&index_path, overwrite)? {
+        info!("Skipping existing file: {}", index_path.display());
+        return Ok(None);
+    }
+    let cues = read_cues(video, track, options, cancel).await?;
+    let stem = video.file_stem().unwrap_or_default().to_string_lossy();
+    let clips = plan_cue_clips(&stem, &cues, output_dir, cue_clip_options);
+    info!("{} of {} cues to export", clips.len(), cues.len());
+    if clips.is_empty() {
+        return Ok(Some(0));
+    }
+    fs::create_dir_all(output_dir).await?;
+    let requests: Vec<ClipRequest> = clips.iter().map(|x| x.request.clone()).collect();
+    export_clips(
+        video,
+        &requests,
+        &cue_clip_options.clips,
+        overwrite,
+        options,
+        cancel,
+    )
+    .await?;
+    fs::write(&index_path, write_clip_index(&clips)).await?;
+    Ok(Some(clips.len()))
+}
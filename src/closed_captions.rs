@@ -0,0 +1,100 @@
+use crate::run_with_limits;
+use crate::FfmpegOptions;
+use crate::SubtitleTrack;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+/// The pseudo codec name we give caption tracks carried inside a video stream
+pub const CLOSED_CAPTIONS_FORMAT: &str = "eia_608";
+
+/// Probe results by file, since several commands list the tracks of the same file more than once
+static PROBED: Mutex<BTreeMap<PathBuf, Vec<u32>>> = Mutex::new(BTreeMap::new());
+
+/// Ask ffprobe which video streams carry embedded EIA-608/708 closed captions, once per file
+pub async fn probe_closed_captions(
+    path: &Path,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Vec<u32>> {
+    let key = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    if let Some(video_streams) = PROBED.lock().unwrap().get(&key) {
+        debug!("Reusing the closed caption probe of {}", path.display());
+        return Ok(video_streams.clone());
+    }
+
+    // Example: ffprobe -v error -select_streams v -show_entries stream=index,closed_captions -of csv=p=0 input.ts
+    let mut cmd = Command::new(&options.ffprobe);
+    cmd.args([
+        "-v",
+        "error",
+        "-select_streams",
+        "v",
+        "-show_entries",
+        "stream=index,closed_captions",
+        "-of",
+        "csv=p=0",
+    ]);
+    cmd.arg(path);
+    let output = run_with_limits(cmd, options.probe_timeout, cancel).await?;
+    if !output.status.success() {
+        eyre::bail!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    debug!("stdout: {}", stdout);
+    let video_streams = parse_closed_caption_streams(&stdout);
+    PROBED.lock().unwrap().insert(key, video_streams.clone());
+    Ok(video_streams)
+}
+
+/// Parse `index,closed_captions` CSV lines (e.g. `0,1`) into the indices of streams with captions
+pub fn parse_closed_caption_streams(csv: &str) -> Vec<u32> {
+    csv.lines()
+        .filter_map(|line| {
+            let mut fields = line.trim().split(',');
+            let index = fields.next()?.trim().parse::<u32>().ok()?;
+            let has_captions = fields.next()?.trim() == "1";
+            has_captions.then_some(index)
+        })
+        .collect()
+}
+
+/// Build the picker entry for captions embedded in `video_stream`
+pub fn closed_caption_track(index: u32, video_stream: u32) -> SubtitleTrack {
+    SubtitleTrack {
+        stream_index: index,
+        lang: None,
+        format: CLOSED_CAPTIONS_FORMAT.to_string(),
+        title: Some("Closed Captions".to_string()),
+        hearing_impaired: true,
+        closed_captions_from: Some(video_stream),
+        ..Default::default()
+    }
+}
+
+/// The lavfi graph that exposes the captions of `video_stream` as a subtitle stream,
+/// e.g. `movie=input.ts:si=0[out0+subcc]`.
+///
+/// The file name is escaped twice: once as a filter option value and once for the filtergraph.
+pub fn lavfi_movie_graph(file_name: &str, video_stream: u32) -> String {
+    let escape = |value: &str, special: &[char]| {
+        let mut out = String::with_capacity(value.len());
+        for c in value.chars() {
+            if special.contains(&c) {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+        out
+    };
+    let option_value = escape(file_name, &['\\', '\'', ':']);
+    let graph_value = escape(&option_value, &['\\', '\'', '[', ']', ',', ';']);
+    format!("movie={graph_value}:si={video_stream}[out0+subcc]")
+}
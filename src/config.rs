@@ -0,0 +1,148 @@
+use crate::FfmpegOptions;
+use crate::MergeFormat;
+use crate::OutputOptions;
+use crate::OverwritePolicy;
+use crate::TrackFilter;
+use eyre::Context;
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::Path;
+use std::path::PathBuf;
+use tracing::debug;
+
+/// Name of the per-directory config file, looked up in the current directory
+pub const LOCAL_CONFIG_FILE_NAME: &str = ".mkvsubs.toml";
+
+/// Persistent defaults read from `config.toml` files.
+///
+/// Every field is optional so that files can be layered; command-line flags override all of them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// The ffmpeg binary to run.
+    pub ffmpeg: Option<PathBuf>,
+
+    /// The ffprobe binary to run.
+    pub ffprobe: Option<PathBuf>,
+
+    /// Seconds to wait for ffmpeg to list the tracks of a file (0 = no limit).
+    pub probe_timeout: Option<u64>,
+
+    /// Seconds to wait for ffmpeg to extract a single track (0 = no limit).
+    pub extract_timeout: Option<u64>,
+
+    /// Preferred languages, listed first in the track picker in this order.
+    pub languages: Option<Vec<String>>,
+
+    /// Ranked track filters, see [`TrackFilter`]; the best match is pre-selected.
+    pub prefer: Option<Vec<TrackFilter>>,
+
+    /// Track filters whose matches are always pre-selected, e.g. `lang=eng forced`.
+    pub always: Option<Vec<TrackFilter>>,
+
+    /// Extract the rule-selected tracks without showing the picker.
+    pub auto_select: Option<bool>,
+
+    /// Read same-language tracks to flag untagged forced subsets.
+    pub detect_forced: Option<bool>,
+
+    /// Read untagged text tracks to detect their language.
+    pub detect_lang: Option<bool>,
+
+    /// Output file name template.
+    pub naming: Option<String>,
+
+    /// What to do when an output file already exists.
+    pub overwrite: Option<OverwritePolicy>,
+
+    /// Write outputs here instead of next to the input file.
+    pub output_dir: Option<PathBuf>,
+
+    /// The file written when merging two tracks with `--merge-with`.
+    pub merge_format: Option<MergeFormat>,
+}
+
+/// The merged config and the files it came from, in the order they were applied
+#[derive(Debug, Clone, Default)]
+pub struct LoadedConfig {
+    pub config: Config,
+    pub sources: Vec<PathBuf>,
+}
+
+impl Config {
+    /// The values used when neither a config file nor a flag sets them
+    pub fn built_in() -> Self {
+        let ffmpeg = FfmpegOptions::default();
+        let output = OutputOptions::default();
+        Self {
+            ffmpeg: Some(ffmpeg.ffmpeg),
+            ffprobe: Some(ffmpeg.ffprobe),
+            probe_timeout: Some(ffmpeg.probe_timeout.map(|x| x.as_secs()).unwrap_or(0)),
+            extract_timeout: Some(ffmpeg.extract_timeout.map(|x| x.as_secs()).unwrap_or(0)),
+            languages: Some(Vec::new()),
+            prefer: Some(Vec::new()),
+            always: Some(Vec::new()),
+            auto_select: Some(false),
+            detect_forced: Some(false),
+            detect_lang: Some(false),
+            naming: Some(output.naming_template),
+            overwrite: Some(output.overwrite),
+            output_dir: output.output_dir,
+            merge_format: Some(MergeFormat::default()),
+        }
+    }
+
+    /// Layer `other` on top of `self`, keeping our values where `other` has none
+    pub fn merge(self, other: Config) -> Config {
+        Config {
+            ffmpeg: other.ffmpeg.or(self.ffmpeg),
+            ffprobe: other.ffprobe.or(self.ffprobe),
+            probe_timeout: other.probe_timeout.or(self.probe_timeout),
+            extract_timeout: other.extract_timeout.or(self.extract_timeout),
+            languages: other.languages.or(self.languages),
+            prefer: other.prefer.or(self.prefer),
+            always: other.always.or(self.always),
+            auto_select: other.auto_select.or(self.auto_select),
+            detect_forced: other.detect_forced.or(self.detect_forced),
+            detect_lang: other.detect_lang.or(self.detect_lang),
+            naming: other.naming.or(self.naming),
+            overwrite: other.overwrite.or(self.overwrite),
+            output_dir: other.output_dir.or(self.output_dir),
+            merge_format: other.merge_format.or(self.merge_format),
+        }
+    }
+
+    /// Parse a single config file
+    pub async fn from_file(path: &Path) -> eyre::Result<Config> {
+        let text = tokio::fs::read_to_string(path)
+            .await
+            .wrap_err_with(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&text)
+            .wrap_err_with(|| format!("Failed to parse config file {}", path.display()))
+    }
+
+    /// Load the user config, then the per-directory `.mkvsubs.toml` on top of it
+    pub async fn load() -> eyre::Result<LoadedConfig> {
+        let mut loaded = LoadedConfig::default();
+        let candidates = [
+            user_config_path(),
+            Some(PathBuf::from(LOCAL_CONFIG_FILE_NAME)),
+        ];
+        for path in candidates.into_iter().flatten() {
+            if !tokio::fs::try_exists(&path).await? {
+                debug!("No config file at {}", path.display());
+                continue;
+            }
+            debug!("Loading config file {}", path.display());
+            let config = Config::from_file(&path).await?;
+            loaded.config = loaded.config.merge(config);
+            loaded.sources.push(path);
+        }
+        Ok(loaded)
+    }
+}
+
+/// The user-level config file, e.g. `~/.config/mkv-subtitle-extractor/config.toml`
+pub fn user_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("mkv-subtitle-extractor").join("config.toml"))
+}
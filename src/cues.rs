@@ -0,0 +1,177 @@
+use crate::pgs;
+use crate::read_subtitle_text;
+use crate::read_subtitle_track;
+use crate::FfmpegOptions;
+use crate::SubtitleTrack;
+use eyre::bail;
+use std::path::Path;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// One subtitle event: when it shows and what it says (empty for bitmap tracks)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cue {
+    pub start: Duration,
+    pub end: Duration,
+    pub text: String,
+}
+
+impl Cue {
+    /// True if the two cues are on screen at the same time for at least half of the shorter one
+    pub fn overlaps(&self, other: &Cue) -> bool {
+        let start = self.start.max(other.start);
+        let end = self.end.min(other.end);
+        // Saturating, since malformed tracks can end a cue before it starts
+        let shorter =
+            (self.end.saturating_sub(self.start)).min(other.end.saturating_sub(other.start));
+        (end > start && (end - start) * 2 >= shorter) || self.start == other.start
+    }
+}
+
+/// Parse an SRT timestamp such as `00:01:02,345` (a `.` is accepted instead of the `,`)
+pub fn parse_srt_timestamp(value: &str) -> Option<Duration> {
+    let (hms, ms) = value.trim().split_once([',', '.'])?;
+    let mut fields = hms.split(':').map(|x| x.trim().parse::<u64>().ok());
+    let (h, m, s) = (fields.next()??, fields.next()??, fields.next()??);
+    let ms: u64 = ms.trim().get(..3.min(ms.trim().len()))?.parse().ok()?;
+    Some(Duration::from_millis(((h * 60 + m) * 60 + s) * 1000 + ms))
+}
+
+/// Format a duration as an SRT timestamp, `HH:MM:SS,mmm`
+pub fn format_srt_timestamp(value: Duration) -> String {
+    let ms = value.as_millis();
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Parse SRT text into cues, skipping blocks without a valid timing line
+pub fn parse_srt(text: &str) -> Vec<Cue> {
+    let text = text.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+    let mut cues = Vec::new();
+    for block in text.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        let Some(timing) = lines.next() else {
+            continue;
+        };
+        let Some((start, end)) = timing.split_once("-->") else {
+            continue;
+        };
+        // The end may be followed by position hints, e.g. `X1:100 X2:200`
+        let end = end.split_whitespace().next().unwrap_or_default();
+        let (Some(start), Some(end)) = (parse_srt_timestamp(start), parse_srt_timestamp(end))
+        else {
+            continue;
+        };
+        cues.push(Cue {
+            start,
+            end: end.max(start),
+            text: lines.collect::<Vec<_>>().join("\n"),
+        });
+    }
+    cues
+}
+
+/// Write cues as SRT text
+pub fn write_srt(cues: &[Cue]) -> String {
+    let mut out = String::new();
+    for (i, cue) in cues.iter().enumerate() {
+        out.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            format_srt_timestamp(cue.start),
+            format_srt_timestamp(cue.end),
+            cue.text
+        ));
+    }
+    out
+}
+
+/// Parse an ASS timestamp such as `0:01:02.34`
+pub fn parse_ass_timestamp(value: &str) -> Option<Duration> {
+    let (hms, cs) = value.trim().split_once('.')?;
+    let mut fields = hms.split(':').map(|x| x.trim().parse::<u64>().ok());
+    let (h, m, s) = (fields.next()??, fields.next()??, fields.next()??);
+    let cs: u64 = cs.trim().parse().ok()?;
+    Some(Duration::from_millis(
+        ((h * 60 + m) * 60 + s) * 1000 + cs * 10,
+    ))
+}
+
+/// Format a duration as an ASS timestamp, `H:MM:SS.cc`
+pub fn format_ass_timestamp(value: Duration) -> String {
+    let cs = (value.as_millis() + 5) / 10;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        cs / 360_000,
+        cs / 6000 % 60,
+        cs / 100 % 60,
+        cs % 100
+    )
+}
+
+/// Split an ASS `Dialogue:` or `Comment:` line into its kind and its ten fields, the last being
+/// the text, which may itself contain commas
+pub(crate) fn split_ass_event(line: &str) -> Option<(&str, Vec<&str>)> {
+    let (kind, rest) = line.split_once(':')?;
+    if kind != "Dialogue" && kind != "Comment" {
+        return None;
+    }
+    let fields: Vec<&str> = rest.trim_start().splitn(10, ',').collect();
+    (fields.len() == 10).then_some((kind, fields))
+}
+
+/// Parse the `Dialogue:` events of an ASS/SSA script into cues, keeping the raw event text
+pub fn parse_ass(text: &str) -> Vec<Cue> {
+    text.lines()
+        .filter_map(split_ass_event)
+        .filter(|(kind, _)| *kind == "Dialogue")
+        .filter_map(|(_, fields)| {
+            let start = parse_ass_timestamp(fields[1])?;
+            let end = parse_ass_timestamp(fields[2])?;
+            Some(Cue {
+                start,
+                end: end.max(start),
+                text: fields[9].to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Read the cues of a text or PGS track without writing anything to disk.
+///
+/// Text tracks are converted to SRT by ffmpeg; PGS cues carry no text.
+pub async fn read_cues(
+    path: &Path,
+    track: &SubtitleTrack,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Vec<Cue>> {
+    if track.is_text() {
+        let srt = read_subtitle_text(path, track, options, cancel).await?;
+        return Ok(parse_srt(&srt));
+    }
+    if !track.is_pgs() {
+        bail!("Only text and PGS tracks have readable cues: {}", track);
+    }
+    let data = read_subtitle_track(path, track, options, cancel).await?;
+    let sets = pgs::parse_display_sets(&data)?;
+    Ok(pgs::summarize_display_sets(&sets)
+        .events
+        .into_iter()
+        .map(|event| {
+            let start = pgs::pts_to_duration(event.start);
+            // Out-of-order PTS can put the next display set before this one
+            let end = pgs::pts_to_duration(event.end.unwrap_or(event.start)).max(start);
+            Cue {
+                start,
+                end,
+                text: String::new(),
+            }
+        })
+        .collect())
+}
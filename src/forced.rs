@@ -0,0 +1,110 @@
+use crate::language_matches;
+use crate::read_cues;
+use crate::Cue;
+use crate::FfmpegOptions;
+use crate::SubtitleTrack;
+use std::path::Path;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+use tracing::warn;
+
+/// A forced subset has at most this share of the full track's events
+const MAX_SUBSET_RATIO: f64 = 0.5;
+
+/// ...and at least this share of its events line up with events of the full track
+const MIN_MATCH_RATIO: f64 = 0.8;
+
+/// True if `candidate` looks like the forced-only part of `full`: far fewer events, nearly all of
+/// which are on screen at the same time as an event of `full`
+pub fn is_forced_subset(candidate: &[Cue], full: &[Cue]) -> bool {
+    if candidate.is_empty() || candidate.len() as f64 > full.len() as f64 * MAX_SUBSET_RATIO {
+        return false;
+    }
+    let mut sorted: Vec<&Cue> = full.iter().collect();
+    sorted.sort_by_key(|cue| cue.start);
+    let longest = full
+        .iter()
+        .map(|x| x.end.saturating_sub(x.start))
+        .max()
+        .unwrap_or_default();
+    let matched = candidate
+        .iter()
+        .filter(|cue| {
+            // Only events starting at most the longest event's duration before this one can overlap it
+            let from = sorted.partition_point(|x| x.start + longest < cue.start);
+            sorted[from..]
+                .iter()
+                .take_while(|x| x.start <= cue.end)
+                .any(|x| cue.overlaps(x))
+        })
+        .count();
+    matched as f64 >= candidate.len() as f64 * MIN_MATCH_RATIO
+}
+
+/// True if both tracks have a language and it's the same one, e.g. `en` and `eng`
+fn same_language(a: &SubtitleTrack, b: &SubtitleTrack) -> bool {
+    a.language()
+        .zip(b.language())
+        .is_some_and(|(a, b)| language_matches(a, b))
+}
+
+/// Set `likely_forced` on untagged tracks whose cues are a forced subset of another track in the same language.
+///
+/// `cues[i]` holds the cues of `tracks[i]`, or `None` if they could not be read.
+pub fn flag_forced_subsets(tracks: &mut [SubtitleTrack], cues: &[Option<Vec<Cue>>]) {
+    for i in 0..tracks.len() {
+        let Some(candidate) = &cues[i] else {
+            continue;
+        };
+        if tracks[i].forced {
+            continue;
+        }
+        let full = (0..tracks.len()).find(|&j| {
+            j != i
+                && same_language(&tracks[j], &tracks[i])
+                && cues[j]
+                    .as_ref()
+                    .is_some_and(|full| is_forced_subset(candidate, full))
+        });
+        if let Some(j) = full {
+            info!(
+                "{} looks like the forced subtitles of {}",
+                tracks[i], tracks[j]
+            );
+            tracks[i].likely_forced = true;
+        }
+    }
+}
+
+/// Read the text and PGS tracks that share a language with another track, then flag forced subsets
+pub async fn detect_forced_subsets(
+    path: &Path,
+    tracks: &mut [SubtitleTrack],
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<()> {
+    let mut cues = Vec::with_capacity(tracks.len());
+    for track in tracks.iter() {
+        let comparable = (track.is_text() || track.is_pgs())
+            && track.closed_captions_from.is_none()
+            && tracks
+                .iter()
+                .filter(|other| same_language(other, track) && other.closed_captions_from.is_none())
+                .count()
+                > 1;
+        if !comparable {
+            cues.push(None);
+            continue;
+        }
+        match read_cues(path, track, options, cancel).await {
+            Ok(x) => cues.push(Some(x)),
+            Err(e) if cancel.is_cancelled() => return Err(e),
+            Err(e) => {
+                warn!("Could not read the cues of {}: {}", track, e);
+                cues.push(None);
+            }
+        }
+    }
+    flag_forced_subsets(tracks, &cues);
+    Ok(())
+}
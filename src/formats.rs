@@ -0,0 +1,67 @@
+use std::path::Path;
+use tracing::debug;
+
+/// File extensions we offer in the file picker: Matroska, MP4/MOV, WebM, AVI and MPEG-TS
+pub const INPUT_EXTENSIONS: &[&str] = &[
+    "mkv", "mka", "mks", "mk3d", "mp4", "m4v", "mov", "webm", "avi", "ts", "m2ts", "mts",
+];
+
+/// True if `path` has one of the [`INPUT_EXTENSIONS`]
+pub fn is_supported_input(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| INPUT_EXTENSIONS.iter().any(|x| ext.eq_ignore_ascii_case(x)))
+}
+
+/// How a subtitle codec is written to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputFormat {
+    /// File extension without the dot, e.g. `srt`.
+    pub ext: &'static str,
+
+    /// The ffmpeg muxer passed to `-f`, e.g. `srt`.
+    pub muxer: &'static str,
+
+    /// The subtitle encoder passed to `-c:s`, or `None` to stream copy.
+    pub encoder: Option<&'static str>,
+}
+
+/// Decide the output file type for a subtitle codec as reported by ffmpeg
+pub fn output_format_for_codec(codec: &str) -> OutputFormat {
+    let (ext, muxer, encoder) = match codec {
+        "subrip" | "srt" => ("srt", "srt", None),
+        "ass" | "ssa" => ("ass", "ass", None),
+        "webvtt" => ("vtt", "webvtt", None),
+        "hdmv_pgs_subtitle" | "pgssub" => ("sup", "sup", None), // PGS typically .sup
+        // MP4/MOV timed text has no standalone container, so we convert it to SRT
+        "mov_text" | "text" => ("srt", "srt", Some("srt")),
+        // Closed captions decoded from the video stream are converted to SRT as well
+        "eia_608" => ("srt", "srt", Some("srt")),
+        // DVD/DVB bitmaps and teletext have no standalone muxer in ffmpeg, keep them in a subtitle-only Matroska file
+        "dvd_subtitle" | "dvb_subtitle" | "dvb_teletext" => ("mks", "matroska", None),
+        other => {
+            // fallback
+            debug!("Unknown subtitle format: {} => .mks", other);
+            ("mks", "matroska", None)
+        }
+    };
+    OutputFormat {
+        ext,
+        muxer,
+        encoder,
+    }
+}
+
+/// The text subtitle encoder that writes what a muxer expects, used when stream copy is impossible
+pub fn encoder_for_muxer(muxer: &str) -> Option<&'static str> {
+    match muxer {
+        "srt" => Some("srt"),
+        "ass" => Some("ass"),
+        "webvtt" => Some("webvtt"),
+        _ => None,
+    }
+}
+
+/// Detect the subtitle format -> extension
+pub fn extension_for_format(fmt: &str) -> &'static str {
+    output_format_for_codec(fmt).ext
+}
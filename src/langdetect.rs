@@ -0,0 +1,263 @@
+//! Offline language identification for untagged text tracks.
+//!
+//! Text in a non-Latin script is identified by its script. Latin-script text is compared against
+//! character trigram profiles built from the samples in `resources/langdetect`, using the
+//! "out of place" rank distance of Cavnar & Trenkle.
+
+use crate::parse_srt;
+use crate::read_subtitle_text;
+use crate::Cue;
+use crate::FfmpegOptions;
+use crate::SubtitleTrack;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+use tracing::info;
+use tracing::warn;
+
+/// Training text for each Latin-script language, keyed by ISO 639-2/B code
+const SAMPLES: &[(&str, &str)] = &[
+    ("cze", include_str!("../resources/langdetect/cze.txt")),
+    ("dan", include_str!("../resources/langdetect/dan.txt")),
+    ("dut", include_str!("../resources/langdetect/dut.txt")),
+    ("eng", include_str!("../resources/langdetect/eng.txt")),
+    ("fin", include_str!("../resources/langdetect/fin.txt")),
+    ("fre", include_str!("../resources/langdetect/fre.txt")),
+    ("ger", include_str!("../resources/langdetect/ger.txt")),
+    ("hun", include_str!("../resources/langdetect/hun.txt")),
+    ("ita", include_str!("../resources/langdetect/ita.txt")),
+    ("nor", include_str!("../resources/langdetect/nor.txt")),
+    ("pol", include_str!("../resources/langdetect/pol.txt")),
+    ("por", include_str!("../resources/langdetect/por.txt")),
+    ("rum", include_str!("../resources/langdetect/rum.txt")),
+    ("spa", include_str!("../resources/langdetect/spa.txt")),
+    ("swe", include_str!("../resources/langdetect/swe.txt")),
+    ("tur", include_str!("../resources/langdetect/tur.txt")),
+];
+
+/// How many of the most frequent trigrams make up a profile
+const PROFILE_SIZE: usize = 300;
+
+/// Below this many letters there is too little text to say anything
+const MIN_LETTERS: usize = 40;
+
+/// The runner-up language must be at least this much further off than the best, as a share of its distance
+const MIN_MARGIN: f64 = 0.12;
+
+/// How many cues are sampled from a track
+const SAMPLE_CUES: usize = 200;
+
+/// The most frequent trigrams of `text`, most frequent first.
+///
+/// Words are lowercased and padded with spaces, so `the` gives ` th`, `the` and `he `.
+fn ranked_trigrams(text: &str) -> Vec<String> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for word in text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|w| !w.is_empty())
+    {
+        let padded: Vec<char> = format!(" {} ", word.to_lowercase()).chars().collect();
+        for window in padded.windows(3) {
+            *counts.entry(window.iter().collect()).or_default() += 1;
+        }
+    }
+    let mut ranked: Vec<(String, usize)> = counts.into_iter().collect();
+    // Ties are broken alphabetically so profiles are stable between runs
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    ranked
+        .into_iter()
+        .take(PROFILE_SIZE)
+        .map(|(trigram, _)| trigram)
+        .collect()
+}
+
+/// The trigram ranks of every sample language, built on first use
+fn profiles() -> &'static [(&'static str, HashMap<String, usize>)] {
+    static PROFILES: OnceLock<Vec<(&'static str, HashMap<String, usize>)>> = OnceLock::new();
+    PROFILES.get_or_init(|| {
+        SAMPLES
+            .iter()
+            .map(|(lang, sample)| {
+                let ranks = ranked_trigrams(sample)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(rank, trigram)| (trigram, rank))
+                    .collect();
+                (*lang, ranks)
+            })
+            .collect()
+    })
+}
+
+/// Scripts whose language we can name without looking at the words
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Script {
+    Latin,
+    Cyrillic,
+    Greek,
+    Hebrew,
+    Arabic,
+    Devanagari,
+    Thai,
+    Hangul,
+    Kana,
+    Han,
+}
+
+fn script_of(c: char) -> Option<Script> {
+    Some(match c as u32 {
+        0x0041..=0x024F | 0x1E00..=0x1EFF if c.is_alphabetic() => Script::Latin,
+        0x0370..=0x03FF => Script::Greek,
+        0x0400..=0x04FF => Script::Cyrillic,
+        0x0590..=0x05FF => Script::Hebrew,
+        0x0600..=0x06FF => Script::Arabic,
+        0x0900..=0x097F => Script::Devanagari,
+        0x0E00..=0x0E7F => Script::Thai,
+        0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF => Script::Hangul,
+        0x3040..=0x30FF => Script::Kana,
+        0x3400..=0x4DBF | 0x4E00..=0x9FFF => Script::Han,
+        _ => return None,
+    })
+}
+
+/// Guess the ISO 639-2/B language code of `text`, or `None` if there is too little to go on
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    let mut scripts: HashMap<Script, usize> = HashMap::new();
+    for c in text.chars() {
+        if let Some(script) = script_of(c) {
+            *scripts.entry(script).or_default() += 1;
+        }
+    }
+    let count = |script| scripts.get(&script).copied().unwrap_or(0);
+    let letters: usize = scripts.values().sum();
+    if letters < MIN_LETTERS {
+        return None;
+    }
+    let (&script, _) = scripts.iter().max_by_key(|(_, count)| **count)?;
+    match script {
+        // Japanese mixes kanji with kana; Chinese has no kana at all
+        Script::Kana | Script::Han => {
+            let kana = count(Script::Kana);
+            Some(if kana * 20 >= kana + count(Script::Han) {
+                "jpn"
+            } else {
+                "chi"
+            })
+        }
+        Script::Cyrillic => {
+            let ukrainian = text.chars().filter(|c| "іїєґІЇЄҐ".contains(*c)).count();
+            Some(if ukrainian * 100 >= count(Script::Cyrillic) {
+                "ukr"
+            } else {
+                "rus"
+            })
+        }
+        Script::Greek => Some("gre"),
+        Script::Hebrew => Some("heb"),
+        Script::Arabic => Some("ara"),
+        Script::Devanagari => Some("hin"),
+        Script::Thai => Some("tha"),
+        Script::Hangul => Some("kor"),
+        Script::Latin => {
+            let trigrams = ranked_trigrams(text);
+            let mut distances = profiles()
+                .iter()
+                .map(|(lang, ranks)| {
+                    // Trigrams missing from the profile cost the most
+                    let distance: usize = trigrams
+                        .iter()
+                        .enumerate()
+                        .map(|(rank, trigram)| match ranks.get(trigram) {
+                            Some(other) => rank.abs_diff(*other),
+                            None => PROFILE_SIZE,
+                        })
+                        .sum();
+                    (*lang, distance)
+                })
+                .collect::<Vec<_>>();
+            distances.sort_by_key(|(_, distance)| *distance);
+            let [(best, best_distance), (runner_up, runner_up_distance), ..] = distances[..] else {
+                return None;
+            };
+            // Close languages (Danish and Norwegian, say) score alike on short samples; better no guess than a wrong one
+            if ((runner_up_distance - best_distance) as f64) < best_distance as f64 * MIN_MARGIN {
+                debug!(
+                    "Too close to call between {} ({}) and {} ({})",
+                    best, best_distance, runner_up, runner_up_distance
+                );
+                return None;
+            }
+            Some(best)
+        }
+    }
+}
+
+/// Remove `<i>`-style tags and `{\an8}`-style overrides from cue text
+pub(crate) fn strip_markup(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut closing = None;
+    for c in text.chars() {
+        match (closing, c) {
+            (None, '<') => closing = Some('>'),
+            (None, '{') => closing = Some('}'),
+            (None, c) => out.push(c),
+            (Some(end), c) if c == end => closing = None,
+            (Some(_), _) => {}
+        }
+    }
+    out
+}
+
+/// Guess the language of a track from an even sample of its cues
+pub fn detect_cue_language(cues: &[Cue]) -> Option<&'static str> {
+    let step = cues.len().div_ceil(SAMPLE_CUES).max(1);
+    let sample = cues
+        .iter()
+        .step_by(step)
+        .map(|cue| strip_markup(&cue.text))
+        .collect::<Vec<_>>()
+        .join("\n");
+    detect_language(&sample)
+}
+
+/// Read the untagged text tracks and fill in their language from the cue text, marking it as detected.
+///
+/// Closed captions are left alone, since reading them means decoding the whole video.
+pub async fn detect_track_languages(
+    path: &Path,
+    tracks: &mut [SubtitleTrack],
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<()> {
+    for track in tracks.iter_mut() {
+        if track.language().is_some() || !track.is_text() {
+            continue;
+        }
+        if track.closed_captions_from.is_some() {
+            debug!(
+                "Not detecting the language of {} since it is closed captions",
+                track
+            );
+            continue;
+        }
+        let srt = match read_subtitle_text(path, track, options, cancel).await {
+            Ok(x) => x,
+            Err(e) if cancel.is_cancelled() => return Err(e),
+            Err(e) => {
+                warn!("Could not read the text of {}: {}", track, e);
+                continue;
+            }
+        };
+        match detect_cue_language(&parse_srt(&srt)) {
+            Some(lang) => {
+                info!("Detected language {} for {}", lang, track);
+                track.lang = Some(lang.to_string());
+                track.lang_detected = true;
+            }
+            None => info!("Could not tell the language of {}", track),
+        }
+    }
+    Ok(())
+}
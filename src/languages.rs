@@ -0,0 +1,264 @@
+//! Language codes as they appear in subtitle tracks: ISO 639-1 (`de`), ISO 639-2 bibliographic
+//! (`ger`) and terminologic (`deu`) codes, and BCP-47 tags such as `pt-BR` or `zh-Hant`.
+
+/// One row of the language table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Language {
+    /// ISO 639-1 code, e.g. `de`; not every language has one.
+    pub alpha2: Option<&'static str>,
+
+    /// ISO 639-2/B code, e.g. `ger`, which is what Matroska and ffmpeg use.
+    pub bibliographic: &'static str,
+
+    /// ISO 639-2/T code, e.g. `deu`; the same as the B code for most languages.
+    pub terminologic: &'static str,
+
+    /// English name, e.g. `German`.
+    pub name: &'static str,
+}
+
+const fn lang(
+    alpha2: &'static str,
+    bibliographic: &'static str,
+    terminologic: &'static str,
+    name: &'static str,
+) -> Language {
+    Language {
+        alpha2: if alpha2.is_empty() {
+            None
+        } else {
+            Some(alpha2)
+        },
+        bibliographic,
+        terminologic,
+        name,
+    }
+}
+
+/// The languages we know by name, roughly those found on discs and in subtitle releases
+const LANGUAGES: &[Language] = &[
+    lang("af", "afr", "afr", "Afrikaans"),
+    lang("am", "amh", "amh", "Amharic"),
+    lang("ar", "ara", "ara", "Arabic"),
+    lang("hy", "arm", "hye", "Armenian"),
+    lang("az", "aze", "aze", "Azerbaijani"),
+    lang("eu", "baq", "eus", "Basque"),
+    lang("be", "bel", "bel", "Belarusian"),
+    lang("bn", "ben", "ben", "Bengali"),
+    lang("bs", "bos", "bos", "Bosnian"),
+    lang("bg", "bul", "bul", "Bulgarian"),
+    lang("my", "bur", "mya", "Burmese"),
+    lang("", "yue", "yue", "Cantonese"),
+    lang("ca", "cat", "cat", "Catalan"),
+    lang("zh", "chi", "zho", "Chinese"),
+    lang("hr", "hrv", "hrv", "Croatian"),
+    lang("cs", "cze", "ces", "Czech"),
+    lang("da", "dan", "dan", "Danish"),
+    lang("nl", "dut", "nld", "Dutch"),
+    lang("en", "eng", "eng", "English"),
+    lang("eo", "epo", "epo", "Esperanto"),
+    lang("et", "est", "est", "Estonian"),
+    lang("fo", "fao", "fao", "Faroese"),
+    lang("", "fil", "fil", "Filipino"),
+    lang("fi", "fin", "fin", "Finnish"),
+    lang("fr", "fre", "fra", "French"),
+    lang("gl", "glg", "glg", "Galician"),
+    lang("ka", "geo", "kat", "Georgian"),
+    lang("de", "ger", "deu", "German"),
+    lang("el", "gre", "ell", "Greek"),
+    lang("gu", "guj", "guj", "Gujarati"),
+    lang("he", "heb", "heb", "Hebrew"),
+    lang("hi", "hin", "hin", "Hindi"),
+    lang("hu", "hun", "hun", "Hungarian"),
+    lang("is", "ice", "isl", "Icelandic"),
+    lang("id", "ind", "ind", "Indonesian"),
+    lang("ga", "gle", "gle", "Irish"),
+    lang("it", "ita", "ita", "Italian"),
+    lang("ja", "jpn", "jpn", "Japanese"),
+    lang("kn", "kan", "kan", "Kannada"),
+    lang("kk", "kaz", "kaz", "Kazakh"),
+    lang("km", "khm", "khm", "Khmer"),
+    lang("ko", "kor", "kor", "Korean"),
+    lang("ku", "kur", "kur", "Kurdish"),
+    lang("lo", "lao", "lao", "Lao"),
+    lang("la", "lat", "lat", "Latin"),
+    lang("lv", "lav", "lav", "Latvian"),
+    lang("lt", "lit", "lit", "Lithuanian"),
+    lang("lb", "ltz", "ltz", "Luxembourgish"),
+    lang("mk", "mac", "mkd", "Macedonian"),
+    lang("ms", "may", "msa", "Malay"),
+    lang("ml", "mal", "mal", "Malayalam"),
+    lang("mt", "mlt", "mlt", "Maltese"),
+    lang("mi", "mao", "mri", "Maori"),
+    lang("mr", "mar", "mar", "Marathi"),
+    lang("mn", "mon", "mon", "Mongolian"),
+    lang("ne", "nep", "nep", "Nepali"),
+    lang("no", "nor", "nor", "Norwegian"),
+    lang("nb", "nob", "nob", "Norwegian Bokmål"),
+    lang("nn", "nno", "nno", "Norwegian Nynorsk"),
+    lang("fa", "per", "fas", "Persian"),
+    lang("pl", "pol", "pol", "Polish"),
+    lang("pt", "por", "por", "Portuguese"),
+    lang("pa", "pan", "pan", "Punjabi"),
+    lang("ro", "rum", "ron", "Romanian"),
+    lang("ru", "rus", "rus", "Russian"),
+    lang("sr", "srp", "srp", "Serbian"),
+    lang("si", "sin", "sin", "Sinhala"),
+    lang("sk", "slo", "slk", "Slovak"),
+    lang("sl", "slv", "slv", "Slovenian"),
+    lang("so", "som", "som", "Somali"),
+    lang("es", "spa", "spa", "Spanish"),
+    lang("sw", "swa", "swa", "Swahili"),
+    lang("sv", "swe", "swe", "Swedish"),
+    lang("tl", "tgl", "tgl", "Tagalog"),
+    lang("ta", "tam", "tam", "Tamil"),
+    lang("te", "tel", "tel", "Telugu"),
+    lang("th", "tha", "tha", "Thai"),
+    lang("bo", "tib", "bod", "Tibetan"),
+    lang("tr", "tur", "tur", "Turkish"),
+    lang("uk", "ukr", "ukr", "Ukrainian"),
+    lang("ur", "urd", "urd", "Urdu"),
+    lang("uz", "uzb", "uzb", "Uzbek"),
+    lang("vi", "vie", "vie", "Vietnamese"),
+    lang("cy", "wel", "cym", "Welsh"),
+    lang("yi", "yid", "yid", "Yiddish"),
+    lang("zu", "zul", "zul", "Zulu"),
+];
+
+/// Names for the region and script subtags seen in subtitle tracks
+const SUBTAGS: &[(&str, &str)] = &[
+    ("419", "Latin America"),
+    ("AR", "Argentina"),
+    ("AT", "Austria"),
+    ("AU", "Australia"),
+    ("BE", "Belgium"),
+    ("BR", "Brazil"),
+    ("CA", "Canada"),
+    ("CH", "Switzerland"),
+    ("CN", "China"),
+    ("DE", "Germany"),
+    ("ES", "Spain"),
+    ("FR", "France"),
+    ("GB", "United Kingdom"),
+    ("HK", "Hong Kong"),
+    ("IE", "Ireland"),
+    ("IN", "India"),
+    ("MX", "Mexico"),
+    ("NZ", "New Zealand"),
+    ("PT", "Portugal"),
+    ("SG", "Singapore"),
+    ("TW", "Taiwan"),
+    ("US", "United States"),
+    ("Cyrl", "Cyrillic"),
+    ("Hans", "Simplified"),
+    ("Hant", "Traditional"),
+    ("Latn", "Latin"),
+];
+
+/// Find a language by its 639-1, 639-2/B or 639-2/T code, ignoring case and any BCP-47 subtags
+pub fn find_language(code: &str) -> Option<&'static Language> {
+    let primary = LanguageTag::parse(code).primary;
+    LANGUAGES.iter().find(|lang| {
+        lang.alpha2.is_some_and(|x| x == primary)
+            || lang.bibliographic == primary
+            || lang.terminologic == primary
+    })
+}
+
+/// A language code split into its primary language and BCP-47 subtags, e.g. `pt-BR`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanguageTag {
+    /// The primary language subtag, lowercased, e.g. `pt` or `por`.
+    pub primary: String,
+
+    /// The remaining subtags in their conventional case, e.g. `["BR"]` or `["Hant", "TW"]`.
+    pub subtags: Vec<String>,
+}
+
+impl LanguageTag {
+    /// Split a tag at `-` or `_`, normalizing the case of each part
+    pub fn parse(code: &str) -> Self {
+        let mut parts = code.trim().split(['-', '_']).filter(|x| !x.is_empty());
+        let primary = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let subtags = parts
+            .map(|part| match part.len() {
+                // Regions are upper case, scripts title case, e.g. `zh-Hant-TW`; valid subtags are
+                // ASCII, so anything else is left alone apart from its ASCII letters
+                2 if part.is_ascii() => part.to_ascii_uppercase(),
+                4 if part.is_ascii() => part
+                    .chars()
+                    .enumerate()
+                    .map(|(i, c)| match i {
+                        0 => c.to_ascii_uppercase(),
+                        _ => c.to_ascii_lowercase(),
+                    })
+                    .collect(),
+                _ => part.to_ascii_lowercase(),
+            })
+            .collect();
+        Self { primary, subtags }
+    }
+
+    fn with_primary(&self, primary: &str) -> String {
+        std::iter::once(primary)
+            .chain(self.subtags.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+}
+
+/// The 639-2/B code for `code`, e.g. `de`, `deu` and `de-AT` all give `ger`.
+///
+/// Unknown codes come back lowercased.
+pub fn normalize_language(code: &str) -> String {
+    match find_language(code) {
+        Some(lang) => lang.bibliographic.to_string(),
+        None => LanguageTag::parse(code).primary,
+    }
+}
+
+/// The two-letter form of `code`, keeping BCP-47 subtags, e.g. `ger` gives `de` and `por-BR` gives `pt-BR`.
+///
+/// Languages without a 639-1 code keep their three-letter code.
+pub fn two_letter_code(code: &str) -> String {
+    let tag = LanguageTag::parse(code);
+    match find_language(code).and_then(|lang| lang.alpha2) {
+        Some(alpha2) => tag.with_primary(alpha2),
+        None => tag.with_primary(&tag.primary),
+    }
+}
+
+/// A human-readable name such as `German` or `Portuguese (Brazil)`, or `None` for unknown codes
+pub fn language_name(code: &str) -> Option<String> {
+    let lang = find_language(code)?;
+    let tag = LanguageTag::parse(code);
+    if tag.subtags.is_empty() {
+        return Some(lang.name.to_string());
+    }
+    let subtags = tag
+        .subtags
+        .iter()
+        .map(|subtag| {
+            SUBTAGS
+                .iter()
+                .find(|(code, _)| code == subtag)
+                .map_or(subtag.as_str(), |(_, name)| name)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    Some(format!("{} ({})", lang.name, subtags))
+}
+
+/// True if `actual` is the language `wanted` asks for.
+///
+/// Any code for the same language matches (`en`, `eng`), and a tag without subtags matches every
+/// variant, so `pt` matches `pt-BR` but `pt-BR` does not match `pt-PT`.
+pub fn language_matches(wanted: &str, actual: &str) -> bool {
+    let wanted_tag = LanguageTag::parse(wanted);
+    let actual_tag = LanguageTag::parse(actual);
+    normalize_language(wanted) == normalize_language(actual)
+        && wanted_tag
+            .subtags
+            .iter()
+            .all(|subtag| actual_tag.subtags.contains(subtag))
+}
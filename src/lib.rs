@@ -1,5 +1,153 @@
 #![feature(try_blocks)]
 
+mod align;
+mod anki;
+mod audio;
+pub mod bitmap;
+mod clips;
+mod closed_captions;
+mod config;
+mod cues;
+mod forced;
+mod formats;
+mod langdetect;
+mod languages;
+mod matroska;
+mod merge;
+mod naming;
+pub mod pgs;
+mod process;
+mod remux;
+mod retime;
+mod search;
+mod selection;
+mod tagging;
+mod version;
+pub mod vobsub;
+
+pub use align::cue_intervals;
+pub use align::find_offset;
+pub use align::find_retiming;
+pub use align::merge_intervals;
+pub use align::subtitle_track_offset;
+pub use align::Alignment;
+pub use align::Retiming;
+pub use align::SyncMethod;
+pub use align::DEFAULT_MAX_OFFSET;
+pub use anki::export_anki_deck;
+pub use anki::plan_anki_cards;
+pub use anki::write_anki_csv;
+pub use anki::AnkiCard;
+pub use anki::AnkiOptions;
+pub use anki::ANKI_CARDS_FILE_NAME;
+pub use anki::ANKI_MEDIA_DIR_NAME;
+pub use audio::audio_offset;
+pub use audio::decode_audio_envelope;
+pub use audio::detect_speech;
+pub use audio::envelope_offset;
+pub use audio::FRAME_MS;
+pub use clips::audio_clip_args;
+pub use clips::clip_file_name;
+pub use clips::export_clips;
+pub use clips::export_cue_clips;
+pub use clips::plan_cue_clips;
+pub use clips::screenshot_args;
+pub use clips::write_clip_index;
+pub use clips::AudioFormat;
+pub use clips::ClipOptions;
+pub use clips::ClipRequest;
+pub use clips::CueClip;
+pub use clips::CueClipOptions;
+pub use clips::CLIP_INDEX_FILE_NAME;
+pub use closed_captions::closed_caption_track;
+pub use closed_captions::lavfi_movie_graph;
+pub use closed_captions::parse_closed_caption_streams;
+pub use closed_captions::probe_closed_captions;
+pub use closed_captions::CLOSED_CAPTIONS_FORMAT;
+pub use config::user_config_path;
+pub use config::Config;
+pub use config::LoadedConfig;
+pub use config::LOCAL_CONFIG_FILE_NAME;
+pub use cues::format_ass_timestamp;
+pub use cues::format_srt_timestamp;
+pub use cues::parse_ass;
+pub use cues::parse_ass_timestamp;
+pub use cues::parse_srt;
+pub use cues::parse_srt_timestamp;
+pub use cues::read_cues;
+pub use cues::write_srt;
+pub use cues::Cue;
+pub use forced::detect_forced_subsets;
+pub use forced::flag_forced_subsets;
+pub use forced::is_forced_subset;
+pub use formats::encoder_for_muxer;
+pub use formats::extension_for_format;
+pub use formats::is_supported_input;
+pub use formats::output_format_for_codec;
+pub use formats::OutputFormat;
+pub use formats::INPUT_EXTENSIONS;
+pub use langdetect::detect_cue_language;
+pub use langdetect::detect_language;
+pub use langdetect::detect_track_languages;
+pub use languages::find_language;
+pub use languages::language_matches;
+pub use languages::language_name;
+pub use languages::normalize_language;
+pub use languages::two_letter_code;
+pub use languages::Language;
+pub use languages::LanguageTag;
+pub use matroska::parse_matroska_tracks;
+pub use matroska::read_matroska_tracks;
+pub use matroska::MatroskaTrack;
+pub use merge::merge_cues;
+pub use merge::write_merged_ass;
+pub use merge::write_merged_srt;
+pub use merge::write_merged_subtitles;
+pub use merge::MergeFormat;
+pub use merge::MergedCue;
+pub use naming::render_output_name;
+pub use naming::NameParts;
+pub use naming::OutputOptions;
+pub use naming::OverwritePolicy;
+pub use naming::DEFAULT_NAMING_TEMPLATE;
+pub use process::run_with_limits;
+pub use process::stream_with_limits;
+pub use process::FfmpegOptions;
+pub use remux::backup_path_for;
+pub use remux::count_streams;
+pub use remux::find_fonts;
+pub use remux::find_sidecars;
+pub use remux::parse_sidecar_name;
+pub use remux::plan_mux;
+pub use remux::plan_strip;
+pub use remux::plan_transplant;
+pub use remux::probe_streams;
+pub use remux::run_remux;
+pub use remux::sidecar_from_path;
+pub use remux::tracks_to_drop;
+pub use remux::RemuxPlan;
+pub use remux::Sidecar;
+pub use remux::SIDECAR_EXTENSIONS;
+pub use retime::read_subtitle_file;
+pub use retime::retime_ass;
+pub use retime::retime_srt;
+pub use retime::write_retimed_subtitle;
+pub use retime::RETIMABLE_EXTENSIONS;
+pub use search::find_input_files_recursive;
+pub use search::search_cues;
+pub use search::search_file;
+pub use search::SearchMatch;
+pub use selection::SelectionRules;
+pub use selection::TrackFilter;
+pub use tagging::mkvpropedit_args;
+pub use tagging::plan_tag_remux;
+pub use tagging::tag_subtitle_tracks;
+pub use tagging::TrackEdits;
+pub use tokio_util::sync::CancellationToken;
+pub use version::check_ffmpeg;
+pub use version::parse_ffmpeg_version;
+pub use version::FfmpegVersion;
+
 use cloud_terrastodon_core_user_input::prelude::pick;
 use cloud_terrastodon_core_user_input::prelude::pick_many;
 use cloud_terrastodon_core_user_input::prelude::Choice;
@@ -13,23 +161,27 @@ use tokio::fs;
 use tokio::process::Command;
 use tracing::debug;
 use tracing::info;
+use tracing::warn;
 
-/// Prompt user (via FZF) to pick an MKV file in current directory
-pub async fn pick_mkv_file() -> eyre::Result<PathBuf> {
-    let mkv_files = gather_mkv_files().await?;
-    info!("Found {} mkv files", mkv_files.len());
-    if mkv_files.is_empty() {
-        bail!("No MKV files found in current directory");
+/// Prompt user (via FZF) to pick a video file in current directory
+pub async fn pick_input_file() -> eyre::Result<PathBuf> {
+    let input_files = gather_input_files().await?;
+    info!("Found {} video files", input_files.len());
+    if input_files.is_empty() {
+        bail!(
+            "No video files found in current directory (looked for {})",
+            INPUT_EXTENSIONS.join(", ")
+        );
     }
     let chosen = pick(FzfArgs {
-        choices: mkv_files
+        choices: input_files
             .into_iter()
             .map(|x| Choice {
                 key: x.display().to_string(),
                 value: x,
             })
             .collect_vec(),
-        header: Some("Choose an MKV file to extract subtitles from".to_string()),
+        header: Some("Choose a video file to extract subtitles from".to_string()),
         prompt: None,
     })
     .map_err(|e| eyre!(e))?
@@ -38,8 +190,8 @@ pub async fn pick_mkv_file() -> eyre::Result<PathBuf> {
     Ok(chosen)
 }
 
-/// Gather all MKV files in the current directory
-pub async fn gather_mkv_files() -> eyre::Result<Vec<PathBuf>> {
+/// Gather all supported video files (see [`INPUT_EXTENSIONS`]) in the current directory
+pub async fn gather_input_files() -> eyre::Result<Vec<PathBuf>> {
     let cwd = PathBuf::from("./");
     let mut entries = fs::read_dir(cwd).await?;
     let mut candidates = Vec::new();
@@ -48,18 +200,17 @@ pub async fn gather_mkv_files() -> eyre::Result<Vec<PathBuf>> {
         if !file_type.is_file() {
             continue;
         }
-        let file_name = entry.file_name();
-        if let Some(ext) = PathBuf::from(&file_name).extension() {
-            if ext.eq_ignore_ascii_case("mkv") {
-                candidates.push(PathBuf::from(file_name));
-            }
+        let file_name = PathBuf::from(entry.file_name());
+        if is_supported_input(&file_name) {
+            candidates.push(file_name);
         }
     }
+    candidates.sort();
     Ok(candidates)
 }
 
 /// A struct describing each found subtitle track
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct SubtitleTrack {
     /// This is the "N" in `Stream #0:N` (the real ffmpeg index).
     pub stream_index: u32,
@@ -67,17 +218,91 @@ pub struct SubtitleTrack {
     /// We parse the language from `(eng)` or similar if present.
     pub lang: Option<String>,
 
+    /// `lang` was guessed from the cue text rather than tagged in the file.
+    pub lang_detected: bool,
+
+    /// The BCP-47 language from Matroska's `LanguageBCP47` (formerly `LanguageIETF`), e.g. `es-419`,
+    /// which ffmpeg's `(spa)` marker collapses.
+    pub lang_ietf: Option<String>,
+
     /// The recognized format, e.g. `subrip`, `ass`, `hdmv_pgs_subtitle`.
     pub format: String,
 
     /// A "title" if found in subsequent metadata lines, e.g. "English subs".
     pub title: Option<String>,
+
+    /// The `(default)` disposition.
+    pub default: bool,
+
+    /// The `(forced)` disposition.
+    pub forced: bool,
+
+    /// Not tagged forced, but its events are a small subset of another track in the same language.
+    pub likely_forced: bool,
+
+    /// The `(hearing impaired)` disposition.
+    pub hearing_impaired: bool,
+
+    /// For closed-caption pseudo-tracks, the video stream (`Stream #0:N`) carrying the captions.
+    pub closed_captions_from: Option<u32>,
+}
+
+impl SubtitleTrack {
+    /// The most specific language we know: the BCP-47 tag if it adds a region or script, else `lang`
+    pub fn language(&self) -> Option<&str> {
+        match (&self.lang_ietf, &self.lang) {
+            (Some(ietf), Some(_)) if LanguageTag::parse(ietf).subtags.is_empty() => {
+                self.lang.as_deref()
+            }
+            (Some(ietf), _) => Some(ietf),
+            (None, lang) => lang.as_deref(),
+        }
+    }
+
+    /// True for text-based formats, false for image-based ones like PGS and VobSub
+    pub fn is_text(&self) -> bool {
+        !matches!(
+            self.format.as_str(),
+            "hdmv_pgs_subtitle" | "pgssub" | "dvd_subtitle" | "dvb_subtitle" | "xsub"
+        )
+    }
+
+    /// True for Blu-ray PGS bitmap subtitles
+    pub fn is_pgs(&self) -> bool {
+        matches!(self.format.as_str(), "hdmv_pgs_subtitle" | "pgssub")
+    }
+
+    /// True if the track is tagged forced or was detected as a forced subset
+    pub fn is_forced(&self) -> bool {
+        self.forced || self.likely_forced
+    }
+
+    /// True if the track is flagged hearing impaired or its title says SDH/CC
+    pub fn is_sdh(&self) -> bool {
+        self.hearing_impaired
+            || self.title.as_ref().is_some_and(|t| {
+                t.split(|c: char| !c.is_alphanumeric())
+                    .any(|word| word.eq_ignore_ascii_case("sdh") || word.eq_ignore_ascii_case("cc"))
+            })
+    }
 }
 
 impl std::fmt::Display for SubtitleTrack {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let lang_part = if let Some(lang) = &self.lang {
-            format!("({lang}) ")
+        if let Some(video_stream) = self.closed_captions_from {
+            return write!(
+                f,
+                "Closed captions {:?} in video Stream #0:{}",
+                self.format, video_stream
+            );
+        }
+        let lang_part = if let Some(lang) = self.language() {
+            let mut parts = vec![lang.to_string()];
+            parts.extend(language_name(lang));
+            if self.lang_detected {
+                parts.push("detected".to_string());
+            }
+            format!("({}) ", parts.join(", "))
         } else {
             "".to_string()
         };
@@ -90,67 +315,212 @@ impl std::fmt::Display for SubtitleTrack {
             f,
             "Stream #0:{} {}{:?}{}",
             self.stream_index, lang_part, self.format, title_part
-        )
+        )?;
+        for (flag, name) in [
+            (self.default, "default"),
+            (self.forced, "forced"),
+            (self.likely_forced, "likely forced"),
+            (self.hearing_impaired, "hearing impaired"),
+        ] {
+            if flag {
+                write!(f, " ({name})")?;
+            }
+        }
+        Ok(())
     }
 }
 
-/// Let the user pick which subtitle tracks to extract
-pub async fn pick_subtitle_tracks(path: &Path) -> eyre::Result<Vec<SubtitleTrack>> {
-    let tracks = enumerate_subtitle_tracks(path).await?;
+/// Let the user pick which subtitle tracks to extract.
+///
+/// Tracks chosen by `rules` are listed first and marked with `*`; with `rules.auto` they are returned without prompting.
+pub async fn pick_subtitle_tracks(
+    path: &Path,
+    rules: &SelectionRules,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Vec<SubtitleTrack>> {
+    let mut tracks = enumerate_subtitle_tracks_with_captions(path, options, cancel).await?;
     info!("Found {} subtitle tracks", tracks.len());
     if tracks.is_empty() {
         bail!("No subtitle tracks found in {}", path.display());
     }
+    if rules.detect_lang {
+        detect_track_languages(path, &mut tracks, options, cancel).await?;
+    }
+    if rules.detect_forced {
+        detect_forced_subsets(path, &mut tracks, options, cancel).await?;
+    }
+    sort_by_preferred_language(&mut tracks, &rules.languages);
+
+    let selected = rules.select(&tracks);
+    if !rules.is_empty() {
+        info!("Rules selected {} tracks", selected.len());
+    }
+    if rules.auto {
+        if !selected.is_empty() {
+            let tracks = tracks
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| selected.contains(i))
+                .map(|(_, track)| track)
+                .collect_vec();
+            info!("Automatically selected: {:#?}", tracks);
+            return Ok(tracks);
+        }
+        warn!("No track matched the selection rules, falling back to the picker");
+    }
+
+    // Move rule-selected tracks to the top, keeping the relative order otherwise
+    let (preselected, others): (Vec<_>, Vec<_>) = tracks
+        .into_iter()
+        .enumerate()
+        .partition(|(i, _)| selected.contains(i));
+    let choices = preselected
+        .into_iter()
+        .map(|(_, track)| Choice {
+            key: format!("* {track}"),
+            value: track,
+        })
+        .chain(others.into_iter().map(|(_, track)| Choice {
+            key: format!("  {track}"),
+            value: track,
+        }))
+        .collect_vec();
+
+    let header = if selected.is_empty() {
+        "Select subtitle tracks to extract".to_string()
+    } else {
+        "Select subtitle tracks to extract (* = suggested by rules)".to_string()
+    };
     let tracks = pick_many(FzfArgs {
-        choices: tracks,
-        header: Some("Select subtitle tracks to extract".to_string()),
+        choices,
+        header: Some(header),
         prompt: None,
     })
-    .map_err(|e| eyre!(e))?;
+    .map_err(|e| eyre!(e))?
+    .into_iter()
+    .map(|choice| choice.value)
+    .collect_vec();
 
     info!("You chose: {:#?}", tracks);
     Ok(tracks)
 }
 
-/// Detect the subtitle format -> extension
-pub fn extension_for_format(fmt: &str) -> &str {
-    match fmt {
-        "subrip" => "srt",
-        "ass" => "ass",
-        "hdmv_pgs_subtitle" | "pgssub" => "sup", // PGS typically .sup
-        other => {
-            // fallback
-            debug!("Unknown subtitle format: {} => .sub", other);
-            "sub"
+/// Stable-sort tracks so those in `preferred_languages` come first, in that order
+pub fn sort_by_preferred_language(tracks: &mut [SubtitleTrack], preferred_languages: &[String]) {
+    tracks.sort_by_key(|track| {
+        track
+            .language()
+            .and_then(|lang| {
+                preferred_languages
+                    .iter()
+                    .position(|x| language_matches(x, lang))
+            })
+            .unwrap_or(usize::MAX)
+    });
+}
+
+/// Parse the output of `ffmpeg -i` and build a list of subtitles with metadata
+pub async fn enumerate_subtitle_tracks(
+    path: &Path,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Vec<SubtitleTrack>> {
+    Ok(list_subtitle_tracks(path, options, cancel).await?.0)
+}
+
+/// Like [`enumerate_subtitle_tracks`], plus a pseudo-track for each video stream carrying closed captions.
+///
+/// Only use this where the captions can be read, since finding them runs ffprobe on the video.
+pub async fn enumerate_subtitle_tracks_with_captions(
+    path: &Path,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Vec<SubtitleTrack>> {
+    let (mut tracks, has_video) = list_subtitle_tracks(path, options, cancel).await?;
+    if !has_video {
+        debug!("Not checking for closed captions since there is no video stream");
+        return Ok(tracks);
+    }
+
+    // Captions inside the video stream don't show up as subtitle streams; offer them as pseudo-tracks
+    match probe_closed_captions(path, options, cancel).await {
+        Ok(video_streams) => {
+            for video_stream in video_streams {
+                info!("Found closed captions in video stream #0:{}", video_stream);
+                let index = tracks.len() as u32;
+                tracks.push(closed_caption_track(index, video_stream));
+            }
         }
+        Err(e) if cancel.is_cancelled() => return Err(e),
+        Err(e) => warn!("Could not check for closed captions: {}", e),
     }
+
+    Ok(tracks)
 }
 
-/// Parse the output of `ffmpeg -i` and build a list of subtitles with metadata
-pub async fn enumerate_subtitle_tracks(path: &Path) -> eyre::Result<Vec<SubtitleTrack>> {
+/// The subtitle tracks of `path`, and whether it has a video stream
+async fn list_subtitle_tracks(
+    path: &Path,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<(Vec<SubtitleTrack>, bool)> {
     info!("Enumerating subtitle tracks");
-    debug!("Running command `ffmpeg -i {}`", path.display());
+    debug!(
+        "Running command `{} -i {}`",
+        options.ffmpeg.display(),
+        path.display()
+    );
 
-    let mut cmd = Command::new("ffmpeg");
+    let mut cmd = Command::new(&options.ffmpeg);
     cmd.arg("-i").arg(path);
-    cmd.stdout(std::process::Stdio::piped());
-    cmd.stderr(std::process::Stdio::piped());
-    let output = cmd.output().await?;
+    let output = run_with_limits(cmd, options.probe_timeout, cancel).await?;
     // ffmpeg -i fails (exit code != 0) because no output file is specified, but we only want the console output
-    let stdout = String::from_utf8(output.stdout)?;
-    let stderr = String::from_utf8(output.stderr)?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
 
     debug!("stdout: {}", stdout);
     debug!("stderr: {}", stderr);
 
-    let lines: Vec<&str> = stderr.lines().collect();
+    let mut tracks = parse_subtitle_tracks(&stderr)?;
+
+    // ffmpeg only shows the ISO 639-2 language; Matroska may also carry a BCP-47 tag
+    let owned_path = path.to_path_buf();
+    match tokio::task::spawn_blocking(move || read_matroska_tracks(&owned_path)).await? {
+        Ok(headers) if headers.len() == tracks.len() => {
+            for (track, header) in tracks.iter_mut().zip(headers) {
+                if header.language_bcp47.is_some() {
+                    track.lang_ietf = header.language_bcp47;
+                }
+            }
+        }
+        Ok(headers) => debug!(
+            "Found {} Matroska subtitle headers for {} ffmpeg tracks, ignoring them",
+            headers.len(),
+            tracks.len()
+        ),
+        Err(e) => debug!("Not reading Matroska track headers: {}", e),
+    }
+
+    Ok((tracks, count_streams(&stderr, "Video") > 0))
+}
+
+/// Parse the stream listing printed by `ffmpeg -i` into subtitle tracks
+pub fn parse_subtitle_tracks(ffmpeg_output: &str) -> eyre::Result<Vec<SubtitleTrack>> {
+    let lines: Vec<&str> = ffmpeg_output.lines().collect();
 
     let mut result = Vec::new();
     let mut current: Option<SubtitleTrack> = None;
 
     // Iterate over each line to find subtitle streams
     for line in lines.iter() {
-        if line.trim_start().starts_with("Stream #") && line.contains("Subtitle") {
+        let is_stream_line = line.trim_start().starts_with("Stream #");
+        if is_stream_line && !line.contains("Subtitle:") {
+            // A video/audio/data stream ends the metadata block of the previous subtitle
+            if let Some(t) = current.take() {
+                result.push(t);
+            }
+        } else if is_stream_line {
             // Finalize the previous track if it exists
             if let Some(t) = current.take() {
                 result.push(t);
@@ -161,6 +531,8 @@ pub async fn enumerate_subtitle_tracks(path: &Path) -> eyre::Result<Vec<Subtitle
             //   "Stream #0:2(eng): Subtitle: subrip (default)"
             //   "Stream #0:3: Subtitle: hdmv_pgs_subtitle, 1920x1080"
             //   "Stream #0:0: Subtitle: subrip (default)"
+            //   "Stream #0:2[0x3](eng): Subtitle: mov_text (tx3g / 0x67337874), 0 kb/s (default)"
+            //   "Stream #0:4[0x1100](deu): Subtitle: dvb_subtitle ([6][0][0][0] / 0x0006)"
 
             let line_trim = line.trim_start();
             // Remove "Stream #"
@@ -180,6 +552,15 @@ pub async fn enumerate_subtitle_tracks(path: &Path) -> eyre::Result<Vec<Subtitle
             // remove trailing colons
             stream_part = stream_part.trim_end_matches(':').trim();
 
+            // MP4 and MPEG-TS add a container stream id, e.g. "0:2[0x3](eng)"; drop it
+            let without_id;
+            if let (Some(open), Some(close)) = (stream_part.find('['), stream_part.find(']')) {
+                if open < close {
+                    without_id = format!("{}{}", &stream_part[..open], &stream_part[close + 1..]);
+                    stream_part = &without_id;
+                }
+            }
+
             // Extract optional (lang). We'll do:
             //   - If we find '(' => parse everything up to '(' as e.g. "0:1"
             //   - Then parse what's inside '(...)' as the language
@@ -223,24 +604,36 @@ pub async fn enumerate_subtitle_tracks(path: &Path) -> eyre::Result<Vec<Subtitle
                 .trim()
                 .to_string();
 
+            // Dispositions follow the format, e.g. "subrip (default) (forced)"
             let track = SubtitleTrack {
                 stream_index: numeric_part,
                 lang,
+                lang_detected: false,
+                lang_ietf: None,
                 format: format_str,
                 title: None,
+                default: after_subtitle.contains("(default)"),
+                forced: after_subtitle.contains("(forced)"),
+                likely_forced: false,
+                hearing_impaired: after_subtitle.contains("(hearing impaired)"),
+                closed_captions_from: None,
             };
 
             current = Some(track);
         } else {
             // Possibly a metadata line if `current` is Some
             if let Some(current_track) = current.as_mut() {
-                if line.trim_start().starts_with("title") {
-                    // e.g. "title           : English subs"
-                    if let Some(colon_pos) = line.find(':') {
-                        let after_colon = &line[colon_pos + 1..].trim();
-                        if !after_colon.is_empty() {
-                            current_track.title = Some(after_colon.to_string());
+                // e.g. "title           : English subs"
+                if let Some((key, value)) = line.split_once(':') {
+                    let value = value.trim();
+                    match key.trim().to_ascii_lowercase().as_str() {
+                        _ if value.is_empty() => {}
+                        "title" => current_track.title = Some(value.to_string()),
+                        // Set by some muxers as a stream tag, e.g. "LANGUAGE_IETF   : es-419"
+                        "language_ietf" | "language_bcp47" | "language-ietf" => {
+                            current_track.lang_ietf = Some(value.to_string())
                         }
+                        _ => {}
                     }
                 }
             }
@@ -260,13 +653,116 @@ pub async fn enumerate_subtitle_tracks(path: &Path) -> eyre::Result<Vec<Subtitle
     Ok(result)
 }
 
-/// Actually run ffmpeg to copy a track to a new file with the correct extension
-pub async fn extract_subtitle_track(
+/// Everything needed to extract one track, worked out without touching the disk
+#[derive(Debug, Clone)]
+pub struct ExtractionPlan {
+    /// The input file, prefixed with `./` if it was relative.
+    pub input: PathBuf,
+
+    /// Where the finished subtitle file ends up.
+    pub output_path: PathBuf,
+
+    /// Where ffmpeg writes before we rename to `output_path`.
+    pub temp_path: PathBuf,
+
+    /// The directory ffmpeg runs in, since the arguments use bare file names.
+    pub working_dir: Option<PathBuf>,
+
+    /// The ffmpeg binary.
+    pub program: PathBuf,
+
+    /// The input format passed to `-f` before `-i`, e.g. `lavfi` for closed captions.
+    pub input_format: Option<String>,
+
+    /// The input as passed to `-i`, relative to `working_dir`.
+    pub input_arg: String,
+
+    /// The `-map` selector, e.g. `0:s:2`.
+    pub selector: String,
+
+    /// The subtitle encoder, or `None` to stream copy.
+    pub encoder: Option<String>,
+
+    /// Muxer options placed before `-f`, e.g. `-preload 0`.
+    pub muxer_options: Vec<String>,
+
+    /// The muxer passed to `-f`.
+    pub muxer: String,
+
+    /// The output as passed to ffmpeg, relative to `working_dir`.
+    pub output_arg: String,
+}
+
+impl ExtractionPlan {
+    /// The arguments passed to ffmpeg
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(input_format) = &self.input_format {
+            args.extend(["-f".to_string(), input_format.clone()]);
+        }
+        args.extend([
+            "-i".to_string(),
+            self.input_arg.clone(),
+            "-map".to_string(),
+            self.selector.clone(),
+        ]);
+        match &self.encoder {
+            Some(encoder) => args.extend(["-c:s".to_string(), encoder.clone()]),
+            None => args.extend(["-c".to_string(), "copy".to_string()]),
+        }
+        args.extend(self.muxer_options.iter().cloned());
+        args.extend([
+            "-f".to_string(),
+            self.muxer.clone(),
+            self.output_arg.clone(),
+        ]);
+        args
+    }
+
+    /// The plan to retry with if stream copy fails, converting with the output format's own encoder
+    pub fn transcode_fallback(&self) -> Option<ExtractionPlan> {
+        if self.encoder.is_some() {
+            return None;
+        }
+        let encoder = encoder_for_muxer(&self.muxer)?;
+        Some(ExtractionPlan {
+            encoder: Some(encoder.to_string()),
+            ..self.clone()
+        })
+    }
+
+    /// Build the ffmpeg command this plan describes
+    pub fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.program);
+        if let Some(dir) = &self.working_dir {
+            cmd.current_dir(dir);
+        }
+        cmd.args(self.args());
+        cmd
+    }
+
+    /// The ffmpeg command as it could be pasted into a shell, including the directory it runs in
+    pub fn command_line(&self) -> String {
+        let mut line = String::new();
+        if let Some(dir) = &self.working_dir {
+            line.push_str(&format!("cd {} && ", quote_arg(&dir.to_string_lossy())));
+        }
+        line.push_str(&quote_arg(&self.program.to_string_lossy()));
+        for arg in &self.args() {
+            line.push(' ');
+            line.push_str(&quote_arg(arg));
+        }
+        line
+    }
+}
+
+/// Work out the output path and ffmpeg command for extracting `track`
+pub fn plan_subtitle_extraction(
     path: &Path,
     track: &SubtitleTrack,
-) -> eyre::Result<Option<PathBuf>> {
-    info!("Extracting subtitle track: {}", track);
-
+    options: &FfmpegOptions,
+    output: &OutputOptions,
+) -> eyre::Result<ExtractionPlan> {
     // prepend with . if not present
     let path = if path.starts_with(".") {
         path.to_path_buf()
@@ -276,120 +772,379 @@ pub async fn extract_subtitle_track(
 
     debug!("Path: {}", path.display());
 
-    // Determine the file extension based on subtitle format
-    let ext = extension_for_format(&track.format);
+    // Determine the file extension, muxer and encoder based on subtitle format
+    let mut format = output_format_for_codec(&track.format);
+    let mut muxer_options = Vec::new();
+    if track.format == "dvd_subtitle" && output.convert_bitmaps.is_some() {
+        // Converting needs the raw subpictures, so write them as a VobSub .sub (an MPEG program stream).
+        // The mpeg muxer would otherwise delay every timestamp by its 0.5s preload.
+        format = OutputFormat {
+            ext: "sub",
+            muxer: "mpeg",
+            encoder: None,
+        };
+        muxer_options = vec!["-preload".to_string(), "0".to_string()];
+    }
+    let ext = format.ext;
 
-    // Build the output file name
+    // Build the output file name from the naming template
     // Example: "Blade Runner 2049.2.eng.srt" or "Jujutsu Kaisen.2.ass"
     let base_stem = path
         .file_stem()
         .unwrap_or_default()
         .to_string_lossy()
         .to_string();
-    let mut fname = format!("{}.{}", base_stem, track.stream_index);
-    if let Some(lang) = &track.lang {
-        fname.push_str(&format!(".{}", lang));
-    }
-    if let Some(t) = &track.title {
-        let sanitized = sanitize_to_windows_path_characters(t);
-        if !sanitized.is_empty() {
-            fname.push_str(&format!(".{}", sanitized));
-        }
-    }
-    fname.push('.');
-    fname.push_str(ext);
-
-    let output_path = path.with_file_name(fname);
-    if fs::try_exists(&output_path).await? {
-        let proceed = pick(FzfArgs {
-            choices: vec![
-                Choice {
-                    key: "Overwrite".to_string(),
-                    value: true,
-                },
-                Choice {
-                    key: "Skip".to_string(),
-                    value: false,
-                },
-            ],
-            header: Some(format!(
-                "Output file already exists: {}",
-                output_path.display()
-            )),
-            prompt: Some("Overwrite or skip?".to_string()),
-        })
-        .map_err(|e| eyre!(e))?
-        .value;
-        if !proceed {
-            return Ok(None);
-        }
-    }
+    let parts = NameParts {
+        stem: base_stem,
+        index: track.stream_index,
+        lang: track.language().map(str::to_string),
+        title: track
+            .title
+            .as_deref()
+            .map(sanitize_to_windows_path_characters)
+            .filter(|x| !x.is_empty()),
+        format: track.format.clone(),
+        forced: track.is_forced(),
+        ext: ext.to_string(),
+    };
+    let fname = render_output_name(&output.naming_template, &parts)?;
+
+    // Outputs go next to the input unless an output directory was given.
+    // ffmpeg runs in the input's directory, so an output directory must be absolute.
+    let output_dir = match &output.output_dir {
+        Some(dir) => Some(std::path::absolute(dir)?),
+        None => None,
+    };
+    let output_path = match &output_dir {
+        Some(dir) => dir.join(&fname),
+        None => path.with_file_name(&fname),
+    };
 
     // Temporarily write to "output.{ext}" in the same directory, then rename
     let temp_name = format!("output.{}", ext);
-    let temp_path = path.with_file_name(temp_name);
+    let temp_path = output_path.with_file_name(&temp_name);
+
+    // Build the ffmpeg command
+    // Example: ffmpeg -i input.mkv -map 0:s:2 -c copy -f srt output.srt
+    // Stream copy when the output container can hold the codec, otherwise convert (e.g. mov_text -> srt)
+    let input_arg = path
+        .file_name()
+        .ok_or(eyre!("No file name"))?
+        .to_string_lossy()
+        .to_string();
+    let output_arg = match output_dir {
+        Some(_) => temp_path.to_string_lossy().to_string(),
+        None => temp_name,
+    };
+
+    // Closed captions are pulled out of the video stream by the lavfi movie source
+    // Example: ffmpeg -f lavfi -i movie=input.ts:si=0[out0+subcc] -map 0:s -c:s srt -f srt output.srt
+    let (input_format, input_arg, selector) = match track.closed_captions_from {
+        Some(video_stream) => (
+            Some("lavfi".to_string()),
+            lavfi_movie_graph(&input_arg, video_stream),
+            "0:s".to_string(),
+        ),
+        None => (None, input_arg, format!("0:s:{}", track.stream_index)),
+    };
+
+    Ok(ExtractionPlan {
+        working_dir: path.parent().map(Path::to_path_buf),
+        input: path,
+        output_path,
+        temp_path,
+        program: options.ffmpeg.clone(),
+        input_format,
+        input_arg,
+        selector,
+        encoder: format.encoder.map(str::to_string),
+        muxer_options,
+        muxer: format.muxer.to_string(),
+        output_arg,
+    })
+}
+
+/// A successfully extracted track
+#[derive(Debug, Clone)]
+pub struct ExtractedTrack {
+    /// Where the subtitle file was written.
+    pub output_path: PathBuf,
+
+    /// The encoder used if the track had to be converted rather than stream copied, e.g. `srt`.
+    pub converted_with: Option<String>,
+}
+
+/// Actually run ffmpeg to copy a track to a new file with the correct extension.
+///
+/// If stream copy fails for a text track, this retries once converting with the output format's encoder.
+/// Returns `None` if the user chose to skip an existing output.
+pub async fn extract_subtitle_track(
+    path: &Path,
+    track: &SubtitleTrack,
+    options: &FfmpegOptions,
+    output: &OutputOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Option<ExtractedTrack>> {
+    info!("Extracting subtitle track: {}", track);
+
+    let plan = plan_subtitle_extraction(path, track, options, output)?;
+    let output_path = plan.output_path.clone();
+    let temp_path = plan.temp_path.clone();
+
+    if let Some(dir) = &output.output_dir {
+        fs::create_dir_all(dir).await?;
+    }
+
+    if fs::try_exists(&output_path).await? && !confirm_overwrite(&output_path, output.overwrite)? {
+        info!("Skipping existing file: {}", output_path.display());
+        return Ok(None);
+    }
+
     if fs::try_exists(&temp_path).await? {
-        let proceed = pick(FzfArgs {
-            choices: vec![
-                Choice {
-                    key: "Overwrite".to_string(),
-                    value: true,
-                },
-                Choice {
-                    key: "Abort".to_string(),
-                    value: false,
-                },
-            ],
-            header: Some(format!("Temp file already exists: {}", temp_path.display())),
-            prompt: Some("Overwrite or abort?".to_string()),
-        })
-        .map_err(|e| eyre!(e))?
-        .value;
+        let proceed = match output.overwrite {
+            OverwritePolicy::Always => true,
+            OverwritePolicy::Skip => false,
+            OverwritePolicy::Ask => {
+                pick(FzfArgs {
+                    choices: vec![
+                        Choice {
+                            key: "Overwrite".to_string(),
+                            value: true,
+                        },
+                        Choice {
+                            key: "Abort".to_string(),
+                            value: false,
+                        },
+                    ],
+                    header: Some(format!("Temp file already exists: {}", temp_path.display())),
+                    prompt: Some("Overwrite or abort?".to_string()),
+                })
+                .map_err(|e| eyre!(e))?
+                .value
+            }
+        };
         if !proceed {
             bail!("Temp file already exists: {}", temp_path.display());
         }
         fs::remove_file(&temp_path).await?;
     }
 
-    // Build and run the ffmpeg command
-    // Example: ffmpeg -i input.mkv -map 0:s:2 -c copy output.srt
-    let mut cmd = Command::new("ffmpeg");
-    if let Some(parent) = path.parent() {
-        cmd.current_dir(parent);
-    }
-
-    let selector = format!("0:s:{}", track.stream_index);
-    cmd.args([
-        "-i",
-        path.file_name().ok_or(eyre!("No file name"))?.to_string_lossy().as_ref(),
-        "-map",
-        &selector,
-        "-c",
-        "copy",
-    ]);
-
-    // Decide container format for text-based subtitles
-    let container_format = match track.format.as_str() {
-        "subrip" => "srt",
-        "ass" => "ass",
-        "hdmv_pgs_subtitle" | "pgssub" => "sup",
-        _ => "",
+    let mut result = run_extraction(&plan, options, cancel).await;
+    if result.is_err() && !cancel.is_cancelled() {
+        if let Some(fallback) = plan.transcode_fallback() {
+            warn!(
+                "Stream copy of {} failed, retrying with `-c:s {}`",
+                track,
+                fallback.encoder.as_deref().unwrap_or_default()
+            );
+            result = run_extraction(&fallback, options, cancel).await;
+        }
+    }
+    let converted_with = result?;
+    if let Some(encoder) = &converted_with {
+        info!("Converted {} with the {} encoder", track, encoder);
+    }
+
+    // Rename the temporary file to the final output path
+    fs::rename(&temp_path, &output_path).await?;
+
+    // Bitmap tracks can then be converted between PGS and VobSub
+    let output_path = match output.convert_bitmaps {
+        Some(to) if !track.is_text() => {
+            match bitmap::convert_extracted_track(
+                path,
+                track,
+                &output_path,
+                to,
+                output.resolution,
+                output.overwrite,
+                options,
+                cancel,
+            )
+            .await?
+            {
+                Some(converted) => converted,
+                None => return Ok(None),
+            }
+        }
+        _ => output_path,
     };
 
-    if !container_format.is_empty() {
-        cmd.arg("-f").arg(container_format);
+    Ok(Some(ExtractedTrack {
+        output_path,
+        converted_with,
+    }))
+}
+
+/// Decide whether to replace an existing file, prompting (via FZF) if the policy is to ask
+pub(crate) fn confirm_overwrite(path: &Path, policy: OverwritePolicy) -> eyre::Result<bool> {
+    Ok(match policy {
+        OverwritePolicy::Always => true,
+        OverwritePolicy::Skip => false,
+        OverwritePolicy::Ask => {
+            pick(FzfArgs {
+                choices: vec![
+                    Choice {
+                        key: "Overwrite".to_string(),
+                        value: true,
+                    },
+                    Choice {
+                        key: "Skip".to_string(),
+                        value: false,
+                    },
+                ],
+                header: Some(format!("Output file already exists: {}", path.display())),
+                prompt: Some("Overwrite or skip?".to_string()),
+            })
+            .map_err(|e| eyre!(e))?
+            .value
+        }
+    })
+}
+
+/// Read a track into memory instead of writing it to disk, e.g. to inspect a PGS track.
+///
+/// Uses the same codec mapping and transcode fallback as [`extract_subtitle_track`].
+pub async fn read_subtitle_track(
+    path: &Path,
+    track: &SubtitleTrack,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Vec<u8>> {
+    // Example: ffmpeg -i input.mkv -map 0:s:2 -c copy -f sup pipe:1
+    let mut plan = plan_subtitle_extraction(path, track, options, &OutputOptions::default())?;
+    plan.output_arg = "pipe:1".to_string();
+    let mut attempts = vec![plan.clone()];
+    attempts.extend(plan.transcode_fallback());
+    read_to_memory(track, attempts, options, cancel).await
+}
+
+/// Read a text track into memory converted to SRT, whatever its original format
+pub async fn read_subtitle_text(
+    path: &Path,
+    track: &SubtitleTrack,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<String> {
+    // Example: ffmpeg -i input.mkv -map 0:s:2 -c:s srt -f srt pipe:1
+    let mut plan = plan_subtitle_extraction(path, track, options, &OutputOptions::default())?;
+    plan.output_arg = "pipe:1".to_string();
+    plan.encoder = Some("srt".to_string());
+    plan.muxer = "srt".to_string();
+    let data = read_to_memory(track, vec![plan], options, cancel).await?;
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// Read several embedded text tracks of `path` as SRT with a single ffmpeg run, one output per track.
+///
+/// Closed caption pseudo-tracks come from a different input, so read those with [`read_subtitle_text`].
+pub async fn read_subtitle_texts(
+    path: &Path,
+    tracks: &[SubtitleTrack],
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Vec<String>> {
+    let Some(first) = tracks.first() else {
+        return Ok(Vec::new());
+    };
+    if let Some(track) = tracks.iter().find(|x| x.closed_captions_from.is_some()) {
+        bail!(
+            "Closed captions can't be read alongside other tracks: {}",
+            track
+        );
     }
 
-    // Finally, specify the output file (temp_path)
-    cmd.arg(
-        temp_path
-            .file_name()
-            .ok_or(eyre!("No file name on output"))?,
-    );
+    // Example: ffmpeg -i input.mkv -map 0:s:2 -c:s srt -f srt /tmp/a.2.srt -map 0:s:3 -c:s srt -f srt /tmp/a.3.srt
+    // ffmpeg runs in the input's directory, so the temp files need absolute paths
+    let plan = plan_subtitle_extraction(path, first, options, &OutputOptions::default())?;
+    let temp_dir = std::path::absolute(std::env::temp_dir())?;
+    let temp_paths = tracks
+        .iter()
+        .map(|track| {
+            temp_dir.join(format!(
+                "mkv-subtitle-extractor-{}.{}.srt",
+                std::process::id(),
+                track.stream_index
+            ))
+        })
+        .collect::<Vec<_>>();
+    let mut cmd = Command::new(&plan.program);
+    if let Some(dir) = &plan.working_dir {
+        cmd.current_dir(dir);
+    }
+    cmd.args(["-i", &plan.input_arg]);
+    for (track, temp_path) in tracks.iter().zip(&temp_paths) {
+        cmd.args(["-map", &format!("0:s:{}", track.stream_index)]);
+        cmd.args(["-c:s", "srt", "-f", "srt"]);
+        cmd.arg(temp_path);
+    }
+    debug!("Running command {:?}", cmd.as_std());
 
-    // Execute the command and handle errors
-    let output = cmd.output().await?;
+    let result = async {
+        let output = run_with_limits(cmd, options.extract_timeout, cancel).await?;
+        if !output.status.success() {
+            bail!(
+                "Failed to read the subtitle tracks of {}: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        let mut texts = Vec::with_capacity(temp_paths.len());
+        for temp_path in &temp_paths {
+            texts.push(String::from_utf8_lossy(&fs::read(temp_path).await?).into_owned());
+        }
+        Ok::<_, eyre::Report>(texts)
+    }
+    .await;
+    for temp_path in &temp_paths {
+        remove_temp_file(temp_path).await;
+    }
+    result
+}
+
+/// Run each plan in turn until one succeeds, returning what it wrote to stdout
+async fn read_to_memory(
+    track: &SubtitleTrack,
+    attempts: Vec<ExtractionPlan>,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Vec<u8>> {
+    let mut last_error = None;
+    for attempt in attempts {
+        if cancel.is_cancelled() {
+            break;
+        }
+        debug!("Running command `{}`", attempt.command_line());
+        let output = run_with_limits(attempt.command(), options.extract_timeout, cancel).await?;
+        if output.status.success() {
+            return Ok(output.stdout);
+        }
+        last_error = Some(String::from_utf8_lossy(&output.stderr).to_string());
+    }
+    bail!(
+        "Failed to read subtitle track {}: {}",
+        track,
+        last_error.unwrap_or_else(|| "cancelled".to_string())
+    )
+}
+
+/// Run one extraction attempt, returning the encoder used (if any) and removing the temp file on failure
+async fn run_extraction(
+    plan: &ExtractionPlan,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Option<String>> {
+    // Execute the command and handle errors, cleaning up the temp file if ffmpeg was killed or failed
+    debug!("Running command `{}`", plan.command_line());
+    let output = match run_with_limits(plan.command(), options.extract_timeout, cancel).await {
+        Ok(output) => output,
+        Err(e) => {
+            remove_temp_file(&plan.temp_path).await;
+            return Err(e);
+        }
+    };
     if !output.status.success() {
+        remove_temp_file(&plan.temp_path).await;
         let stderr = String::from_utf8_lossy(&output.stderr);
         bail!("Failed to extract subtitle track: {}", stderr);
     }
@@ -397,11 +1152,16 @@ pub async fn extract_subtitle_track(
     let stderr = String::from_utf8_lossy(&output.stderr);
     debug!("stdout: {}", stdout);
     debug!("stderr: {}", stderr);
+    Ok(plan.encoder.clone())
+}
 
-    // Rename the temporary file to the final output path
-    fs::rename(&temp_path, &output_path).await?;
-
-    Ok(Some(output_path))
+/// Best-effort removal of a partially written temp file
+async fn remove_temp_file(temp_path: &Path) {
+    if let Err(e) = fs::remove_file(temp_path).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("Failed to remove temp file {}: {}", temp_path.display(), e);
+        }
+    }
 }
 
 /// Replace invalid Windows path characters with underscores
@@ -417,3 +1177,17 @@ fn sanitize_to_windows_path_characters(segment: &str) -> String {
         })
         .collect()
 }
+
+/// Quote a command-line argument for a POSIX shell if it contains spaces or shell metacharacters
+fn quote_arg(arg: &str) -> String {
+    let needs_quotes = arg.is_empty()
+        || arg
+            .chars()
+            .any(|c| c.is_whitespace() || "\"'&|;<>()$`!*?[]{}#~\\".contains(c));
+    if needs_quotes {
+        // Nothing is special inside single quotes, so a quote ends them, is escaped, and reopens them
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    } else {
+        arg.to_string()
+    }
+}
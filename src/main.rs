@@ -1,25 +1,611 @@
 use clap::Parser;
+use clap::Subcommand;
 use eyre::bail;
+use eyre::eyre;
+use mkv_subtitle_extractor::audio_offset;
+use mkv_subtitle_extractor::bitmap;
+use mkv_subtitle_extractor::bitmap::BitmapFormat;
+use mkv_subtitle_extractor::check_ffmpeg;
+use mkv_subtitle_extractor::count_streams;
+use mkv_subtitle_extractor::cue_intervals;
+use mkv_subtitle_extractor::decode_audio_envelope;
+use mkv_subtitle_extractor::detect_forced_subsets;
+use mkv_subtitle_extractor::detect_speech;
+use mkv_subtitle_extractor::detect_track_languages;
+use mkv_subtitle_extractor::enumerate_subtitle_tracks;
+use mkv_subtitle_extractor::enumerate_subtitle_tracks_with_captions;
+use mkv_subtitle_extractor::export_anki_deck;
+use mkv_subtitle_extractor::export_cue_clips;
 use mkv_subtitle_extractor::extract_subtitle_track;
-use mkv_subtitle_extractor::pick_mkv_file;
+use mkv_subtitle_extractor::find_fonts;
+use mkv_subtitle_extractor::find_input_files_recursive;
+use mkv_subtitle_extractor::find_retiming;
+use mkv_subtitle_extractor::find_sidecars;
+use mkv_subtitle_extractor::format_srt_timestamp;
+use mkv_subtitle_extractor::language_matches;
+use mkv_subtitle_extractor::mkvpropedit_args;
+use mkv_subtitle_extractor::pgs;
+use mkv_subtitle_extractor::pick_input_file;
 use mkv_subtitle_extractor::pick_subtitle_tracks;
+use mkv_subtitle_extractor::plan_cue_clips;
+use mkv_subtitle_extractor::plan_mux;
+use mkv_subtitle_extractor::plan_strip;
+use mkv_subtitle_extractor::plan_subtitle_extraction;
+use mkv_subtitle_extractor::plan_tag_remux;
+use mkv_subtitle_extractor::plan_transplant;
+use mkv_subtitle_extractor::probe_streams;
+use mkv_subtitle_extractor::read_cues;
+use mkv_subtitle_extractor::read_subtitle_file;
+use mkv_subtitle_extractor::read_subtitle_track;
+use mkv_subtitle_extractor::run_remux;
+use mkv_subtitle_extractor::search_file;
+use mkv_subtitle_extractor::sidecar_from_path;
+use mkv_subtitle_extractor::subtitle_track_offset;
+use mkv_subtitle_extractor::tag_subtitle_tracks;
+use mkv_subtitle_extractor::tracks_to_drop;
+use mkv_subtitle_extractor::user_config_path;
+use mkv_subtitle_extractor::write_merged_subtitles;
+use mkv_subtitle_extractor::write_retimed_subtitle;
+use mkv_subtitle_extractor::AnkiOptions;
+use mkv_subtitle_extractor::AudioFormat;
+use mkv_subtitle_extractor::CancellationToken;
+use mkv_subtitle_extractor::ClipOptions;
+use mkv_subtitle_extractor::Config;
+use mkv_subtitle_extractor::CueClipOptions;
+use mkv_subtitle_extractor::FfmpegOptions;
+use mkv_subtitle_extractor::LoadedConfig;
+use mkv_subtitle_extractor::MergeFormat;
+use mkv_subtitle_extractor::OutputOptions;
+use mkv_subtitle_extractor::OverwritePolicy;
+use mkv_subtitle_extractor::Retiming;
+use mkv_subtitle_extractor::SelectionRules;
+use mkv_subtitle_extractor::SubtitleTrack;
+use mkv_subtitle_extractor::SyncMethod;
+use mkv_subtitle_extractor::TrackEdits;
+use mkv_subtitle_extractor::TrackFilter;
+use mkv_subtitle_extractor::ANKI_CARDS_FILE_NAME;
+use mkv_subtitle_extractor::ANKI_MEDIA_DIR_NAME;
+use mkv_subtitle_extractor::DEFAULT_MAX_OFFSET;
+use mkv_subtitle_extractor::FRAME_MS;
+use mkv_subtitle_extractor::LOCAL_CONFIG_FILE_NAME;
+use mkv_subtitle_extractor::RETIMABLE_EXTENSIONS;
+use regex::RegexBuilder;
+use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 use tokio::fs;
 use tracing::info;
+use tracing::warn;
 use tracing::Level;
 use tracing_subscriber::EnvFilter;
 
 /// Command-line arguments
+///
+/// Options left unset fall back to the config files (see `config show`), then to built-in defaults.
 #[derive(Parser, Debug)]
-#[command(version, about = "Extract subtitles from MKV files")]
+#[command(
+    version,
+    about = "Extract subtitles from MKV, MP4/MOV, WebM, AVI and MPEG-TS files"
+)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// If set, enable debug logging
-    #[arg(long)]
+    #[arg(long, global = true)]
     debug: bool,
 
-    /// Path to MKV to extract from
+    /// Path to the video file to extract from
     #[arg(long)]
     file: Option<PathBuf>,
+
+    /// Print the output path and ffmpeg command for each selected track without writing anything
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Languages to list first in the track picker, e.g. `--lang eng,jpn`
+    #[arg(long = "lang", value_delimiter = ',', global = true)]
+    languages: Option<Vec<String>>,
+
+    /// Ranked track filter, e.g. `--prefer "lang=eng text !sdh"`; repeat to add fallbacks
+    #[arg(long, global = true)]
+    prefer: Option<Vec<TrackFilter>>,
+
+    /// Track filter whose matches are always selected, e.g. `--always "lang=eng forced"`
+    #[arg(long, global = true)]
+    always: Option<Vec<TrackFilter>>,
+
+    /// Extract the tracks chosen by --prefer/--always without showing the picker
+    #[arg(long, global = true)]
+    auto_select: bool,
+
+    /// Compare the cue timings of same-language tracks to flag untagged forced subtitle tracks
+    #[arg(long, global = true)]
+    detect_forced: bool,
+
+    /// Detect the language of untagged text tracks from their cue text
+    #[arg(long, global = true)]
+    detect_lang: bool,
+
+    /// Output file name template, e.g. `{stem}.{lang}.{ext}`
+    #[arg(long, global = true)]
+    naming: Option<String>,
+
+    /// What to do when an output file already exists
+    #[arg(long, value_enum, global = true)]
+    overwrite: Option<OverwritePolicy>,
+
+    /// Write outputs to this directory instead of next to the input
+    #[arg(long, global = true)]
+    output_dir: Option<PathBuf>,
+
+    /// After extracting a PGS track, also render it to PNG images (see the `render` command)
+    #[arg(long)]
+    render_pgs: bool,
+
+    /// Convert PGS and DVD bitmap tracks to this format after extracting them
+    #[arg(long, value_enum)]
+    to: Option<BitmapFormat>,
+
+    /// Scale converted bitmap subtitles to this screen size, e.g. `720x576`
+    #[arg(long, value_parser = bitmap::parse_resolution)]
+    resolution: Option<(u16, u16)>,
+
+    /// Merge each extracted text track with this one (the N in `Stream #0:N`) into a single
+    /// bilingual file
+    #[arg(long)]
+    merge_with: Option<u32>,
+
+    /// The file written by --merge-with: ASS with the tracks at the bottom and top, or SRT with
+    /// stacked lines
+    #[arg(long, value_enum)]
+    merge_format: Option<MergeFormat>,
+
+    /// The ffmpeg binary to run
+    #[arg(long, global = true, env = "MKVSUBS_FFMPEG")]
+    ffmpeg: Option<PathBuf>,
+
+    /// The ffprobe binary to run
+    #[arg(long, global = true, env = "MKVSUBS_FFPROBE")]
+    ffprobe: Option<PathBuf>,
+
+    /// Seconds to wait for ffmpeg to list the tracks of a file (0 = no limit)
+    #[arg(long, global = true)]
+    probe_timeout: Option<u64>,
+
+    /// Seconds to wait for ffmpeg to extract a single track (0 = no limit)
+    #[arg(long, global = true)]
+    extract_timeout: Option<u64>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+
+    /// Render a PGS (.sup) subtitle file to PNG images with an index.csv of timings
+    Render(RenderArgs),
+
+    /// Convert a bitmap subtitle file between PGS (.sup) and VobSub (.idx/.sub)
+    Convert(ConvertArgs),
+
+    /// Summarize the display sets of a .sup file, or of the PGS tracks in a video file
+    Info(InfoArgs),
+
+    /// Add subtitle files to a video as tagged tracks of a new MKV
+    Mux(MuxArgs),
+
+    /// Remux a video without some of its subtitle tracks
+    Strip(StripArgs),
+
+    /// Set the language, title and flags of subtitle tracks in the file itself
+    Tag(TagArgs),
+
+    /// Copy the subtitle tracks of one release into another, shifted to line up with it
+    Transplant(TransplantArgs),
+
+    /// Retime a subtitle file to match the speech in a video's audio
+    Sync(SyncArgs),
+
+    /// Retime a subtitle file to match the cue timing of a reference subtitle, e.g. a translation
+    /// timed to another release
+    Align(AlignArgs),
+
+    /// Make Anki flashcards from the cues of a text track, with an audio clip for each
+    Anki(AnkiArgs),
+
+    /// Export the audio and/or a video frame of each cue of a text track
+    Clips(ClipsArgs),
+
+    /// Search the text subtitle tracks of every video under a directory for a line
+    Search(SearchArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct RenderArgs {
+    /// The .sup file to render
+    sup: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct ConvertArgs {
+    /// The .sup, .idx or .sub file to convert
+    input: PathBuf,
+
+    /// The format to write
+    #[arg(long, value_enum)]
+    to: BitmapFormat,
+
+    /// Scale the images to this screen size, e.g. `720x576`
+    #[arg(long, value_parser = bitmap::parse_resolution)]
+    resolution: Option<(u16, u16)>,
+
+    /// Where to write, defaults to the input path with the new extension
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct InfoArgs {
+    /// A .sup file, or a video file whose subtitle tracks are listed
+    file: PathBuf,
+
+    /// Only inspect this subtitle stream (the N in `Stream #0:N`)
+    #[arg(long)]
+    track: Option<u32>,
+}
+
+#[derive(clap::Args, Debug)]
+struct MuxArgs {
+    /// The video file to add subtitles to
+    video: PathBuf,
+
+    /// The .srt/.ass/.ssa/.sup/.vtt/.idx files to add, defaults to those next to the video
+    /// whose names start with its stem
+    subtitles: Vec<PathBuf>,
+
+    /// Attach the fonts in this directory, defaults to a `fonts` directory next to the video
+    #[arg(long)]
+    fonts: Option<PathBuf>,
+
+    /// Where to write, defaults to `{stem}.muxed.mkv` next to the video (or in --output-dir)
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct StripArgs {
+    /// The video file to strip
+    file: PathBuf,
+
+    /// Drop the subtitle tracks matching this filter, e.g. `--drop "lang=ger sdh"`; repeatable
+    #[arg(long)]
+    drop: Vec<TrackFilter>,
+
+    /// Keep only subtitle tracks in these languages, e.g. `--keep-lang eng,jpn`
+    #[arg(long, value_delimiter = ',')]
+    keep_lang: Vec<String>,
+
+    /// Where to write, defaults to `{stem}.stripped.{ext}` next to the video (or in --output-dir)
+    #[arg(long, conflicts_with = "in_place")]
+    output: Option<PathBuf>,
+
+    /// Replace the file itself, keeping the original as `{name}.bak`
+    #[arg(long)]
+    in_place: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct TagArgs {
+    /// The video file to edit
+    file: PathBuf,
+
+    /// The subtitle stream to edit (the N in `Stream #0:N`); repeatable
+    #[arg(long, required_unless_present = "select")]
+    track: Vec<u32>,
+
+    /// Edit every subtitle track matching this filter, e.g. `--select "lang=und"`
+    #[arg(long)]
+    select: Option<TrackFilter>,
+
+    /// The new language, e.g. `ger`, `de` or `pt-BR`
+    #[arg(long = "set-lang")]
+    lang: Option<String>,
+
+    /// The new title; pass an empty string to remove it
+    #[arg(long)]
+    title: Option<String>,
+
+    /// Set (`true`) or clear (`false`) the default flag
+    #[arg(long)]
+    default: Option<bool>,
+
+    /// Set (`true`) or clear (`false`) the forced flag
+    #[arg(long)]
+    forced: Option<bool>,
+
+    /// Set (`true`) or clear (`false`) the hearing impaired flag
+    #[arg(long)]
+    sdh: Option<bool>,
+
+    /// The mkvpropedit binary used to edit Matroska files in place
+    #[arg(long, env = "MKVSUBS_MKVPROPEDIT", default_value = "mkvpropedit")]
+    mkvpropedit: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct TransplantArgs {
+    /// The release to take the subtitles from; the selection options choose the tracks
+    from: PathBuf,
+
+    /// The release to add them to
+    to: PathBuf,
+
+    /// What to compare the releases by to find the offset
+    #[arg(long, value_enum, default_value_t = SyncMethod::Audio)]
+    sync: SyncMethod,
+
+    /// Shift by this many seconds instead of finding the offset, e.g. `--offset -1.5`
+    #[arg(long, allow_hyphen_values = true)]
+    offset: Option<f64>,
+
+    /// With `--sync subtitles`, compare against this subtitle stream of the destination (the N
+    /// in `Stream #0:N`) instead of one in the same language
+    #[arg(long)]
+    reference: Option<u32>,
+
+    /// Search offsets up to this many seconds either way
+    #[arg(long, default_value_t = DEFAULT_MAX_OFFSET.as_secs())]
+    max_offset: u64,
+
+    /// Where to write, defaults to `{stem}.transplanted.mkv` next to the destination (or in --output-dir)
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct SyncArgs {
+    /// The .srt, .ass or .ssa file to retime
+    subtitle: PathBuf,
+
+    /// The video whose audio the subtitles should match
+    video: PathBuf,
+
+    /// The audio stream to listen to, counting audio streams only (the N in `0:a:N`)
+    #[arg(long, default_value_t = 0)]
+    audio_track: u32,
+
+    /// Also correct subtitles that drift further out of sync over time, e.g. timed for a
+    /// different frame rate
+    #[arg(long)]
+    drift: bool,
+
+    /// Search offsets up to this many seconds either way
+    #[arg(long, default_value_t = DEFAULT_MAX_OFFSET.as_secs())]
+    max_offset: u64,
+
+    /// Where to write, defaults to `{stem}.synced.{ext}` next to the subtitle (or in --output-dir)
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct AlignArgs {
+    /// The .srt, .ass or .ssa file to retime
+    subtitle: PathBuf,
+
+    /// A .srt, .ass or .ssa file, or a video whose text or PGS track is the reference
+    reference: PathBuf,
+
+    /// The subtitle stream of the reference video to use (the N in `Stream #0:N`); otherwise
+    /// chosen with the selection options
+    #[arg(long)]
+    track: Option<u32>,
+
+    /// Also correct subtitles that drift further out of sync over time, e.g. timed for a
+    /// different frame rate
+    #[arg(long)]
+    drift: bool,
+
+    /// Search offsets up to this many seconds either way
+    #[arg(long, default_value_t = DEFAULT_MAX_OFFSET.as_secs())]
+    max_offset: u64,
+
+    /// Where to write, defaults to `{stem}.aligned.{ext}` next to the subtitle (or in --output-dir)
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct AnkiArgs {
+    /// The video to make cards from
+    file: PathBuf,
+
+    /// The text track for the front of the cards (the N in `Stream #0:N`); otherwise chosen
+    /// with the selection options
+    #[arg(long)]
+    track: Option<u32>,
+
+    /// A text track in another language for the back of the cards (the N in `Stream #0:N`)
+    #[arg(long)]
+    back: Option<u32>,
+
+    /// Add a frame from the middle of each cue to the back
+    #[arg(long)]
+    screenshots: bool,
+
+    /// The audio stream to cut clips from, counting audio streams only (the N in `0:a:N`)
+    #[arg(long, default_value_t = 0)]
+    audio_track: u32,
+
+    /// The format of the audio clips
+    #[arg(long, value_enum, default_value_t)]
+    audio_format: AudioFormat,
+
+    /// Milliseconds of audio kept before and after each cue
+    #[arg(long, default_value_t = 250)]
+    padding: u64,
+
+    /// The deck to import into, defaults to the video's file name
+    #[arg(long)]
+    deck: Option<String>,
+
+    /// The directory to write, defaults to `{stem}.anki` next to the video (or in --output-dir)
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ClipsArgs {
+    /// The video to cut clips from
+    file: PathBuf,
+
+    /// The text track whose cues are exported (the N in `Stream #0:N`); otherwise chosen with
+    /// the selection options
+    #[arg(long)]
+    track: Option<u32>,
+
+    /// Only export cues whose text matches this regular expression
+    #[arg(long)]
+    matching: Option<String>,
+
+    /// Match --matching without regard to case
+    #[arg(short = 'i', long)]
+    ignore_case: bool,
+
+    /// Export audio clips; without --audio or --screenshots both are exported
+    #[arg(long)]
+    audio: bool,
+
+    /// Export the frame in the middle of each cue
+    #[arg(long)]
+    screenshots: bool,
+
+    /// The audio stream to cut clips from, counting audio streams only (the N in `0:a:N`)
+    #[arg(long, default_value_t = 0)]
+    audio_track: u32,
+
+    /// The format of the audio clips
+    #[arg(long, value_enum, default_value_t)]
+    audio_format: AudioFormat,
+
+    /// Milliseconds of audio kept before and after each cue
+    #[arg(long, default_value_t = 0)]
+    padding: u64,
+
+    /// Scale screenshots to this height, keeping the aspect ratio
+    #[arg(long)]
+    screenshot_height: Option<u32>,
+
+    /// The directory to write, defaults to `{stem}.clips` next to the video (or in --output-dir)
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct SearchArgs {
+    /// The text to look for
+    pattern: String,
+
+    /// The directory searched, including its subdirectories
+    #[arg(default_value = ".")]
+    dir: PathBuf,
+
+    /// Treat the pattern as a regular expression
+    #[arg(short = 'e', long)]
+    regex: bool,
+
+    /// Match without regard to case
+    #[arg(short = 'i', long)]
+    ignore_case: bool,
+
+    /// Also search closed captions carried in the video, which is much slower
+    #[arg(long)]
+    closed_captions: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the effective configuration and the files it was read from
+    Show,
+}
+
+/// What every command needs, resolved from the flags and config files
+struct Context {
+    options: FfmpegOptions,
+    output: OutputOptions,
+    rules: SelectionRules,
+    dry_run: bool,
+    cancel: CancellationToken,
+}
+
+impl Command {
+    /// False for the commands that only read and write subtitle files
+    fn needs_ffmpeg(&self) -> bool {
+        match self {
+            Command::Config { .. } | Command::Render(_) | Command::Convert(_) => false,
+            Command::Info(cmd) => !is_sup(&cmd.file),
+            _ => true,
+        }
+    }
+}
+
+/// True for `.sup` files, which we read ourselves
+fn is_sup(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("sup"))
+}
+
+/// Convert a seconds argument to a timeout, treating 0 as "no limit"
+fn timeout_from_secs(secs: u64) -> Option<Duration> {
+    if secs == 0 {
+        None
+    } else {
+        Some(Duration::from_secs(secs))
+    }
+}
+
+/// Where a command writes when not told: `{stem}.{suffix}.{ext}` next to `input`, or in --output-dir
+fn default_output_path(
+    input: &Path,
+    suffix: &str,
+    ext: Option<&str>,
+    output: &OutputOptions,
+) -> PathBuf {
+    let mut name = format!(
+        "{}.{suffix}",
+        input.file_stem().unwrap_or_default().to_string_lossy()
+    );
+    if let Some(ext) = ext {
+        name = format!("{name}.{ext}");
+    }
+    match &output.output_dir {
+        Some(dir) => dir.join(name),
+        None => input.with_file_name(name),
+    }
+}
+
+/// Write `subtitle` retimed to `output_path`, or with --dry-run print what would be written
+async fn write_retimed(
+    subtitle: &Path,
+    output_path: &Path,
+    retiming: &Retiming,
+    dry_run: bool,
+    overwrite: OverwritePolicy,
+) -> eyre::Result<()> {
+    if dry_run {
+        println!("  retiming: {}", retiming);
+        println!("  output:   {}", output_path.display());
+        return Ok(());
+    }
+    if let Some(dir) = output_path.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    write_retimed_subtitle(subtitle, output_path, retiming, overwrite).await?;
+    Ok(())
 }
 
 #[tokio::main]
@@ -40,10 +626,894 @@ async fn main() -> eyre::Result<()> {
 
     info!("Ahoy!");
 
+    // Built-in defaults, then config files, then flags
+    let loaded = Config::load().await?;
+    let config = Config::built_in().merge(loaded.config.clone());
+
+    let defaults = FfmpegOptions::default();
+    let options = FfmpegOptions {
+        ffmpeg: args.ffmpeg.or(config.ffmpeg).unwrap_or(defaults.ffmpeg),
+        ffprobe: args.ffprobe.or(config.ffprobe).unwrap_or(defaults.ffprobe),
+        probe_timeout: match args.probe_timeout.or(config.probe_timeout) {
+            Some(secs) => timeout_from_secs(secs),
+            None => defaults.probe_timeout,
+        },
+        extract_timeout: match args.extract_timeout.or(config.extract_timeout) {
+            Some(secs) => timeout_from_secs(secs),
+            None => defaults.extract_timeout,
+        },
+    };
+
+    let render_dir = args.output_dir.clone();
+    let defaults = OutputOptions::default();
+    let output = OutputOptions {
+        output_dir: args.output_dir.or(config.output_dir),
+        naming_template: args
+            .naming
+            .or(config.naming)
+            .unwrap_or(defaults.naming_template),
+        overwrite: args
+            .overwrite
+            .or(config.overwrite)
+            .unwrap_or(defaults.overwrite),
+        convert_bitmaps: args.to,
+        resolution: args.resolution,
+    };
+    let merge_format = args
+        .merge_format
+        .or(config.merge_format)
+        .unwrap_or_default();
+    let rules = SelectionRules {
+        languages: args.languages.or(config.languages).unwrap_or_default(),
+        prefer: args.prefer.or(config.prefer).unwrap_or_default(),
+        always: args.always.or(config.always).unwrap_or_default(),
+        auto: args.auto_select || config.auto_select.unwrap_or_default(),
+        detect_forced: args.detect_forced || config.detect_forced.unwrap_or_default(),
+        detect_lang: args.detect_lang || config.detect_lang.unwrap_or_default(),
+    };
+
+    // Ctrl-C cancels the token, which kills any running ffmpeg and cleans up its temp file
+    let cancel = CancellationToken::new();
+    tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                warn!("Received Ctrl-C, cancelling");
+                cancel.cancel();
+            }
+        }
+    });
+
+    let ctx = Context {
+        options,
+        output,
+        rules,
+        dry_run: args.dry_run,
+        cancel,
+    };
+
+    // Fail early if ffmpeg is missing or too old
+    if args.command.as_ref().is_none_or(Command::needs_ffmpeg) {
+        check_ffmpeg(&ctx.options, &ctx.cancel).await?;
+    }
+
+    match &args.command {
+        Some(Command::Config {
+            action: ConfigCommand::Show,
+        }) => show_config(&loaded),
+        Some(Command::Render(cmd)) => render(cmd, render_dir, &ctx).await,
+        Some(Command::Convert(cmd)) => convert(cmd, &ctx).await,
+        Some(Command::Info(cmd)) => info(cmd, &ctx).await,
+        Some(Command::Mux(cmd)) => mux(cmd, &ctx).await,
+        Some(Command::Strip(cmd)) => strip(cmd, &ctx).await,
+        Some(Command::Tag(cmd)) => tag(cmd, &ctx).await,
+        Some(Command::Transplant(cmd)) => transplant(cmd, &ctx).await,
+        Some(Command::Sync(cmd)) => sync(cmd, &ctx).await,
+        Some(Command::Align(cmd)) => align(cmd, &ctx).await,
+        Some(Command::Anki(cmd)) => anki(cmd, &ctx).await,
+        Some(Command::Clips(cmd)) => clips(cmd, &ctx).await,
+        Some(Command::Search(cmd)) => search(cmd, &ctx).await,
+        None => {
+            extract(
+                args.file,
+                args.merge_with,
+                merge_format,
+                args.render_pgs,
+                &ctx,
+            )
+            .await
+        }
+    }
+}
+
+/// Print the effective configuration and the files it was read from
+fn show_config(loaded: &LoadedConfig) -> eyre::Result<()> {
+    let config = Config::built_in().merge(loaded.config.clone());
+    match user_config_path() {
+        Some(path) => println!("# user config: {}", path.display()),
+        None => println!("# user config: (no config directory on this platform)"),
+    }
+    println!("# directory config: {LOCAL_CONFIG_FILE_NAME}");
+    for source in &loaded.sources {
+        println!("# loaded: {}", source.display());
+    }
+    print!("{}", toml::to_string_pretty(&config)?);
+    Ok(())
+}
+
+async fn info(args: &InfoArgs, ctx: &Context) -> eyre::Result<()> {
+    let InfoArgs { file, track } = args;
+    if is_sup(file) {
+        let data = fs::read(file).await?;
+        let sets = pgs::parse_display_sets(&data)?;
+        print!("{}", pgs::summarize_display_sets(&sets));
+        return Ok(());
+    }
+    let tracks = enumerate_subtitle_tracks_with_captions(file, &ctx.options, &ctx.cancel).await?;
+    if let Some(index) = track {
+        if !tracks.iter().any(|t| t.stream_index == *index) {
+            bail!("No subtitle stream #0:{index} in {}", file.display());
+        }
+    }
+    for t in &tracks {
+        if track.is_some_and(|index| index != t.stream_index) {
+            continue;
+        }
+        println!("{t}");
+        if t.is_pgs() {
+            let data = read_subtitle_track(file, t, &ctx.options, &ctx.cancel).await?;
+            let sets = pgs::parse_display_sets(&data)?;
+            print!("{}", pgs::summarize_display_sets(&sets));
+        } else if track.is_some() {
+            println!("  (only PGS tracks can be summarized)");
+        }
+    }
+    Ok(())
+}
+
+async fn render(args: &RenderArgs, render_dir: Option<PathBuf>, ctx: &Context) -> eyre::Result<()> {
+    let RenderArgs { sup } = args;
+    // The images get a directory of their own, so an explicit --output-dir is used as is
+    let output_dir = render_dir.unwrap_or_else(|| sup.with_extension(""));
+    if ctx.dry_run {
+        println!("  output: {}", output_dir.display());
+        return Ok(());
+    }
+    pgs::render_sup_to_png(sup, &output_dir, ctx.output.overwrite).await?;
+    Ok(())
+}
+
+async fn convert(args: &ConvertArgs, ctx: &Context) -> eyre::Result<()> {
+    let ConvertArgs {
+        input,
+        to,
+        resolution,
+        output: destination,
+    } = args;
+    let output_path = destination.clone().unwrap_or_else(|| input.clone());
+    let same_format = match to {
+        BitmapFormat::Sup => input.extension().is_some_and(|ext| ext == "sup"),
+        BitmapFormat::Vobsub => input
+            .extension()
+            .is_some_and(|ext| ext == "idx" || ext == "sub"),
+    };
+    if same_format && output_path.with_extension("") == input.with_extension("") {
+        bail!(
+            "Converting {} would overwrite it, pass --output",
+            input.display()
+        );
+    }
+    if ctx.dry_run {
+        println!(
+            "  output: {}",
+            output_path.with_extension(to.ext()).display()
+        );
+        return Ok(());
+    }
+    bitmap::convert_bitmap_subtitles(
+        input,
+        &output_path,
+        *to,
+        *resolution,
+        None,
+        ctx.output.overwrite,
+    )
+    .await?;
+    Ok(())
+}
+
+async fn mux(args: &MuxArgs, ctx: &Context) -> eyre::Result<()> {
+    let MuxArgs {
+        video,
+        subtitles,
+        fonts,
+        output: output_file,
+    } = args;
+    let sidecars = if subtitles.is_empty() {
+        find_sidecars(video).await?
+    } else {
+        subtitles
+            .iter()
+            .map(|path| sidecar_from_path(video, path))
+            .collect()
+    };
+    if sidecars.is_empty() {
+        bail!("No subtitle files found next to {}", video.display());
+    }
+    // Fonts only matter to ASS subtitles
+    let fonts = if sidecars.iter().any(|x| x.is_ass()) || fonts.is_some() {
+        find_fonts(video, fonts.as_deref()).await?
+    } else {
+        Vec::new()
+    };
+    let output_path = output_file
+        .clone()
+        .unwrap_or_else(|| default_output_path(video, "muxed", Some("mkv"), &ctx.output));
+    let streams = probe_streams(video, &ctx.options, &ctx.cancel).await?;
+    let plan = plan_mux(
+        video,
+        count_streams(&streams, "Subtitle"),
+        count_streams(&streams, "Attachment"),
+        &sidecars,
+        &fonts,
+        &output_path,
+        &ctx.options,
+    );
+    for sidecar in &sidecars {
+        info!(
+            "Adding {} (language {}, title {:?}{}{}{})",
+            sidecar.path.display(),
+            sidecar.lang.as_deref().unwrap_or("unknown"),
+            sidecar.title.as_deref().unwrap_or_default(),
+            if sidecar.default { ", default" } else { "" },
+            if sidecar.forced { ", forced" } else { "" },
+            if sidecar.hearing_impaired {
+                ", hearing impaired"
+            } else {
+                ""
+            },
+        );
+    }
+    for font in &fonts {
+        info!("Attaching font {}", font.display());
+    }
+    if ctx.dry_run {
+        println!("  output:  {}", plan.output_path.display());
+        println!("  command: {}", plan.command_line());
+        return Ok(());
+    }
+    if let Some(dir) = output_path.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    run_remux(&plan, ctx.output.overwrite, &ctx.options, &ctx.cancel).await?;
+    Ok(())
+}
+
+async fn strip(args: &StripArgs, ctx: &Context) -> eyre::Result<()> {
+    let StripArgs {
+        file,
+        drop,
+        keep_lang,
+        output: output_file,
+        in_place,
+    } = args;
+    if drop.is_empty() && keep_lang.is_empty() {
+        bail!("Pass --drop <FILTER> or --keep-lang <CODES> to choose the tracks to remove");
+    }
+    let mut tracks = enumerate_subtitle_tracks(file, &ctx.options, &ctx.cancel).await?;
+    // Let `--drop forced` and `--keep-lang` see detected flags and languages too
+    if ctx.rules.detect_lang {
+        detect_track_languages(file, &mut tracks, &ctx.options, &ctx.cancel).await?;
+    }
+    if ctx.rules.detect_forced {
+        detect_forced_subsets(file, &mut tracks, &ctx.options, &ctx.cancel).await?;
+    }
+    let dropped = tracks_to_drop(&tracks, drop, keep_lang)
+        .into_iter()
+        .map(|i| &tracks[i])
+        .collect::<Vec<_>>();
+    for track in &tracks {
+        let verb = if dropped.iter().any(|x| x.stream_index == track.stream_index) {
+            "Dropping"
+        } else {
+            "Keeping"
+        };
+        info!("{} {}", verb, track);
+    }
+    if dropped.is_empty() {
+        info!("No subtitle tracks to drop in {}", file.display());
+        return Ok(());
+    }
+    let output_path = match (output_file, in_place) {
+        (_, true) => file.clone(),
+        (Some(x), false) => x.clone(),
+        (None, false) => default_output_path(
+            file,
+            "stripped",
+            file.extension().and_then(|x| x.to_str()),
+            &ctx.output,
+        ),
+    };
+    let plan = plan_strip(file, &dropped, &output_path, &ctx.options);
+    if ctx.dry_run {
+        println!("  output:  {}", plan.output_path.display());
+        if let Some(backup) = &plan.backup_path {
+            println!("  backup:  {}", backup.display());
+        }
+        println!("  command: {}", plan.command_line());
+        return Ok(());
+    }
+    if let Some(dir) = output_path.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    run_remux(&plan, ctx.output.overwrite, &ctx.options, &ctx.cancel).await?;
+    Ok(())
+}
+
+async fn tag(args: &TagArgs, ctx: &Context) -> eyre::Result<()> {
+    let TagArgs {
+        file,
+        track,
+        select,
+        lang,
+        title,
+        default,
+        forced,
+        sdh,
+        mkvpropedit,
+    } = args;
+    let edits = TrackEdits {
+        lang: lang.clone(),
+        title: title.clone(),
+        default: *default,
+        forced: *forced,
+        hearing_impaired: *sdh,
+    };
+    if edits.is_empty() {
+        bail!("Nothing to change, pass --set-lang, --title, --default, --forced or --sdh");
+    }
+    let tracks = enumerate_subtitle_tracks(file, &ctx.options, &ctx.cancel).await?;
+    for index in track {
+        if !tracks.iter().any(|t| t.stream_index == *index) {
+            bail!("No subtitle stream #0:{index} in {}", file.display());
+        }
+    }
+    let chosen = tracks
+        .iter()
+        .filter(|t| t.closed_captions_from.is_none())
+        .filter(|t| {
+            track.contains(&t.stream_index) || select.as_ref().is_some_and(|f| f.matches(t))
+        })
+        .collect::<Vec<_>>();
+    if chosen.is_empty() {
+        bail!("No subtitle tracks matched in {}", file.display());
+    }
+    for t in &chosen {
+        info!("Editing {}", t);
+    }
+    if ctx.dry_run {
+        println!(
+            "  with mkvpropedit: {}",
+            mkvpropedit_args(file, &chosen, &edits).join(" ")
+        );
+        println!(
+            "  or with ffmpeg:   {}",
+            plan_tag_remux(file, &chosen, &edits, &ctx.options).command_line()
+        );
+        return Ok(());
+    }
+    tag_subtitle_tracks(
+        file,
+        &chosen,
+        &edits,
+        mkvpropedit,
+        ctx.output.overwrite,
+        &ctx.options,
+        &ctx.cancel,
+    )
+    .await?;
+    Ok(())
+}
+
+async fn transplant(args: &TransplantArgs, ctx: &Context) -> eyre::Result<()> {
+    let TransplantArgs {
+        from,
+        to,
+        sync,
+        offset,
+        reference,
+        max_offset,
+        output: output_file,
+    } = args;
+    let chosen = pick_subtitle_tracks(from, &ctx.rules, &ctx.options, &ctx.cancel)
+        .await?
+        .into_iter()
+        .filter(|t| t.closed_captions_from.is_none())
+        .collect::<Vec<_>>();
+    if chosen.is_empty() {
+        bail!("No subtitle tracks to transplant from {}", from.display());
+    }
+    let max_offset_ms = *max_offset as i64 * 1000;
+    let offset_ms = match (offset, sync) {
+        (Some(secs), _) => (secs * 1000.0).round() as i64,
+        (None, SyncMethod::Audio) => {
+            let (offset_ms, score) =
+                audio_offset(to, from, max_offset_ms, &ctx.options, &ctx.cancel).await?;
+            // Below this the best lag is indistinguishable from noise
+            if score < 0.3 {
+                bail!(
+                    "The audio of {} and {} doesn't line up (correlation {:.2}), try --sync subtitles or --offset",
+                    from.display(),
+                    to.display(),
+                    score
+                );
+            }
+            offset_ms
+        }
+        (None, SyncMethod::Subtitles) => {
+            let readable = |t: &&SubtitleTrack| {
+                t.closed_captions_from.is_none() && (t.is_text() || t.is_pgs())
+            };
+            let same_language = |a: &SubtitleTrack, b: &SubtitleTrack| {
+                a.language()
+                    .zip(b.language())
+                    .is_some_and(|(a, b)| language_matches(a, b))
+            };
+            let destination = enumerate_subtitle_tracks(to, &ctx.options, &ctx.cancel).await?;
+            let pair = match reference {
+                Some(index) => {
+                    let Some(reference) = destination.iter().find(|t| t.stream_index == *index)
+                    else {
+                        bail!("No subtitle stream #0:{index} in {}", to.display());
+                    };
+                    chosen
+                        .iter()
+                        .filter(readable)
+                        .find(|t| same_language(t, reference))
+                        .or_else(|| chosen.iter().find(readable))
+                        .map(|candidate| (reference, candidate))
+                }
+                None => chosen.iter().filter(readable).find_map(|candidate| {
+                    destination
+                        .iter()
+                        .filter(readable)
+                        .find(|t| same_language(t, candidate))
+                        .map(|reference| (reference, candidate))
+                }),
+            };
+            let Some((reference, candidate)) = pair else {
+                bail!(
+                    "No text or PGS track in {} shares a language with the chosen tracks, pass --reference",
+                    to.display()
+                );
+            };
+            let alignment = subtitle_track_offset(
+                to,
+                reference,
+                from,
+                candidate,
+                max_offset_ms,
+                &ctx.options,
+                &ctx.cancel,
+            )
+            .await?;
+            // Different releases may split lines differently, but half should still overlap
+            if alignment.score < 0.5 {
+                bail!(
+                    "The cues of {} and {} don't line up ({:.0}% overlap), try --sync audio or --offset",
+                    candidate,
+                    reference,
+                    alignment.score * 100.0
+                );
+            }
+            alignment.retiming.offset_ms
+        }
+    };
+    let output_path = output_file
+        .clone()
+        .unwrap_or_else(|| default_output_path(to, "transplanted", Some("mkv"), &ctx.output));
+    let chosen = chosen.iter().collect::<Vec<_>>();
+    for track in &chosen {
+        info!("Transplanting {}", track);
+    }
+    info!("Shifting by {:+.3}s", offset_ms as f64 / 1000.0);
+    let plan = plan_transplant(to, from, &chosen, offset_ms, &output_path, &ctx.options);
+    if ctx.dry_run {
+        println!("  output:  {}", plan.output_path.display());
+        println!("  command: {}", plan.command_line());
+        return Ok(());
+    }
+    if let Some(dir) = output_path.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    run_remux(&plan, ctx.output.overwrite, &ctx.options, &ctx.cancel).await?;
+    Ok(())
+}
+
+async fn sync(args: &SyncArgs, ctx: &Context) -> eyre::Result<()> {
+    let SyncArgs {
+        subtitle,
+        video,
+        audio_track,
+        drift,
+        max_offset,
+        output: output_file,
+    } = args;
+    let cues = read_subtitle_file(subtitle).await?;
+    info!("Decoding the audio of {}", video.display());
+    let envelope =
+        decode_audio_envelope(video, *audio_track, None, &ctx.options, &ctx.cancel).await?;
+    let speech = detect_speech(&envelope);
+    info!(
+        "Found {} stretches of speech in {}s of audio",
+        speech.len(),
+        envelope.len() as i64 * FRAME_MS / 1000
+    );
+    let alignment = find_retiming(
+        &speech,
+        &cue_intervals(&cues),
+        *max_offset as i64 * 1000,
+        *drift,
+    );
+    info!(
+        "Best fit: {} ({:.0}% of the cue time is over speech)",
+        alignment.retiming,
+        alignment.score * 100.0
+    );
+    // Music and effects make speech detection rough, but a good fit still covers most cues
+    if alignment.score < 0.4 {
+        warn!("The subtitles fit the speech poorly, check the result or pass --drift");
+    }
+    let output_path = output_file.clone().unwrap_or_else(|| {
+        default_output_path(
+            subtitle,
+            "synced",
+            subtitle.extension().and_then(|x| x.to_str()),
+            &ctx.output,
+        )
+    });
+    write_retimed(
+        subtitle,
+        &output_path,
+        &alignment.retiming,
+        ctx.dry_run,
+        ctx.output.overwrite,
+    )
+    .await?;
+    Ok(())
+}
+
+async fn align(args: &AlignArgs, ctx: &Context) -> eyre::Result<()> {
+    let AlignArgs {
+        subtitle,
+        reference,
+        track,
+        drift,
+        max_offset,
+        output: output_file,
+    } = args;
+    let cues = read_subtitle_file(subtitle).await?;
+    let is_subtitle_file = reference.extension().is_some_and(|ext| {
+        RETIMABLE_EXTENSIONS
+            .iter()
+            .any(|x| ext.eq_ignore_ascii_case(x))
+    });
+    let reference_cues = if is_subtitle_file {
+        read_subtitle_file(reference).await?
+    } else {
+        let readable =
+            |t: &SubtitleTrack| t.closed_captions_from.is_none() && (t.is_text() || t.is_pgs());
+        let reference_track = match track {
+            Some(index) => enumerate_subtitle_tracks(reference, &ctx.options, &ctx.cancel)
+                .await?
+                .into_iter()
+                .find(|t| t.stream_index == *index),
+            None => pick_subtitle_tracks(reference, &ctx.rules, &ctx.options, &ctx.cancel)
+                .await?
+                .into_iter()
+                .find(readable),
+        };
+        let Some(reference_track) = reference_track else {
+            bail!(
+                "No text or PGS subtitle track to align to in {}",
+                reference.display()
+            );
+        };
+        if !readable(&reference_track) {
+            bail!(
+                "Only text and PGS tracks can be aligned to: {}",
+                reference_track
+            );
+        }
+        info!("Aligning to {}", reference_track);
+        read_cues(reference, &reference_track, &ctx.options, &ctx.cancel).await?
+    };
+    let alignment = find_retiming(
+        &cue_intervals(&reference_cues),
+        &cue_intervals(&cues),
+        *max_offset as i64 * 1000,
+        *drift,
+    );
+    info!(
+        "Best fit: {} ({:.0}% of the cue time overlaps the reference)",
+        alignment.retiming,
+        alignment.score * 100.0
+    );
+    // Translations split lines differently, but a good fit still overlaps for the most part
+    if alignment.score < 0.5 {
+        warn!("The subtitles fit the reference poorly, check the result or pass --drift");
+    }
+    let output_path = output_file.clone().unwrap_or_else(|| {
+        default_output_path(
+            subtitle,
+            "aligned",
+            subtitle.extension().and_then(|x| x.to_str()),
+            &ctx.output,
+        )
+    });
+    write_retimed(
+        subtitle,
+        &output_path,
+        &alignment.retiming,
+        ctx.dry_run,
+        ctx.output.overwrite,
+    )
+    .await?;
+    Ok(())
+}
+
+async fn anki(args: &AnkiArgs, ctx: &Context) -> eyre::Result<()> {
+    let AnkiArgs {
+        file,
+        track,
+        back,
+        screenshots,
+        audio_track,
+        audio_format,
+        padding,
+        deck,
+        output: output_dir,
+    } = args;
+    let tracks = enumerate_subtitle_tracks_with_captions(file, &ctx.options, &ctx.cancel).await?;
+    let by_index = |index: u32| {
+        tracks
+            .iter()
+            .find(|t| t.stream_index == index)
+            .cloned()
+            .ok_or_else(|| eyre!("No subtitle stream #0:{index} in {}", file.display()))
+    };
+    let front = match track {
+        Some(index) => by_index(*index)?,
+        None => {
+            let Some(front) = pick_subtitle_tracks(file, &ctx.rules, &ctx.options, &ctx.cancel)
+                .await?
+                .into_iter()
+                .find(|t| t.is_text())
+            else {
+                bail!(
+                    "No text subtitle track to make cards from in {}",
+                    file.display()
+                );
+            };
+            front
+        }
+    };
+    let back = back.map(by_index).transpose()?;
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    let output_dir = output_dir
+        .clone()
+        .unwrap_or_else(|| default_output_path(file, "anki", None, &ctx.output));
+    let anki_options = AnkiOptions {
+        deck: deck.clone().unwrap_or_else(|| stem.to_string()),
+        screenshots: *screenshots,
+        clips: ClipOptions {
+            audio_stream: *audio_track,
+            audio_format: *audio_format,
+            padding: Duration::from_millis(*padding),
+            // Small enough for phones, large enough to recognize the scene
+            screenshot_height: Some(480),
+        },
+    };
+    info!("Front: {}", front);
+    if let Some(back) = &back {
+        info!("Back: {}", back);
+    }
+    if ctx.dry_run {
+        println!("  deck:   {}", anki_options.deck);
+        println!(
+            "  output: {}",
+            output_dir.join(ANKI_CARDS_FILE_NAME).display()
+        );
+        println!(
+            "  media:  {}",
+            output_dir.join(ANKI_MEDIA_DIR_NAME).display()
+        );
+        return Ok(());
+    }
+    export_anki_deck(
+        file,
+        &front,
+        back.as_ref(),
+        &output_dir,
+        &anki_options,
+        ctx.output.overwrite,
+        &ctx.options,
+        &ctx.cancel,
+    )
+    .await?;
+    Ok(())
+}
+
+async fn clips(args: &ClipsArgs, ctx: &Context) -> eyre::Result<()> {
+    let ClipsArgs {
+        file,
+        track,
+        matching,
+        ignore_case,
+        audio,
+        screenshots,
+        audio_track,
+        audio_format,
+        padding,
+        screenshot_height,
+        output: output_dir,
+    } = args;
+    let filter = matching
+        .as_deref()
+        .map(|pattern| {
+            RegexBuilder::new(pattern)
+                .case_insensitive(*ignore_case)
+                .build()
+        })
+        .transpose()?;
+    let track = match track {
+        Some(index) => enumerate_subtitle_tracks_with_captions(file, &ctx.options, &ctx.cancel)
+            .await?
+            .into_iter()
+            .find(|t| t.stream_index == *index)
+            .ok_or_else(|| eyre!("No subtitle stream #0:{index} in {}", file.display()))?,
+        None => {
+            let Some(track) = pick_subtitle_tracks(file, &ctx.rules, &ctx.options, &ctx.cancel)
+                .await?
+                .into_iter()
+                .find(|t| t.is_text())
+            else {
+                bail!(
+                    "No text subtitle track to export cues from in {}",
+                    file.display()
+                );
+            };
+            track
+        }
+    };
+    let output_dir = output_dir
+        .clone()
+        .unwrap_or_else(|| default_output_path(file, "clips", None, &ctx.output));
+    let both = !audio && !screenshots;
+    let cue_clip_options = CueClipOptions {
+        filter,
+        audio: *audio || both,
+        screenshots: *screenshots || both,
+        clips: ClipOptions {
+            audio_stream: *audio_track,
+            audio_format: *audio_format,
+            padding: Duration::from_millis(*padding),
+            screenshot_height: *screenshot_height,
+        },
+    };
+    info!("Exporting cues of {}", track);
+    if ctx.dry_run {
+        let cues = read_cues(file, &track, &ctx.options, &ctx.cancel).await?;
+        let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+        for clip in plan_cue_clips(&stem, &cues, &output_dir, &cue_clip_options) {
+            println!("{:>5} {}", clip.index, clip.cue.text.replace('\n', " "));
+            for path in [&clip.request.audio, &clip.request.screenshot]
+                .into_iter()
+                .flatten()
+            {
+                println!("  output: {}", path.display());
+            }
+        }
+        return Ok(());
+    }
+    if let Some(count) = export_cue_clips(
+        file,
+        &track,
+        &output_dir,
+        &cue_clip_options,
+        ctx.output.overwrite,
+        &ctx.options,
+        &ctx.cancel,
+    )
+    .await?
+    {
+        info!("Exported {count} cues to {}", output_dir.display());
+    }
+    Ok(())
+}
+
+async fn search(args: &SearchArgs, ctx: &Context) -> eyre::Result<()> {
+    let SearchArgs {
+        pattern,
+        dir,
+        regex,
+        ignore_case,
+        closed_captions,
+    } = args;
+    let pattern = match regex {
+        true => pattern.clone(),
+        false => regex::escape(pattern),
+    };
+    let pattern = RegexBuilder::new(&pattern)
+        .case_insensitive(*ignore_case)
+        .build()?;
+    let files = find_input_files_recursive(dir).await?;
+    info!("Searching {} videos under {}", files.len(), dir.display());
+    if ctx.dry_run {
+        for file in &files {
+            println!("{}", file.display());
+        }
+        return Ok(());
+    }
+    let mut total = 0;
+    let mut matched_files = 0;
+    for file in &files {
+        if ctx.cancel.is_cancelled() {
+            break;
+        }
+        // One unreadable video shouldn't end the search
+        let results = match search_file(
+            file,
+            &pattern,
+            &ctx.rules.languages,
+            *closed_captions,
+            &ctx.options,
+            &ctx.cancel,
+        )
+        .await
+        {
+            Ok(results) => results,
+            Err(e) => {
+                warn!("Failed to search {}: {e}", file.display());
+                continue;
+            }
+        };
+        if !results.is_empty() {
+            matched_files += 1;
+        }
+        for (track, matches) in results {
+            let lang = track
+                .language()
+                .map(|x| format!(" {x}"))
+                .unwrap_or_default();
+            for found in matches {
+                println!(
+                    "{} [#{}{lang}] {}  {}",
+                    file.display(),
+                    track.stream_index,
+                    format_srt_timestamp(found.start),
+                    found.line
+                );
+                total += 1;
+            }
+        }
+    }
+    info!("Found {total} matching lines in {matched_files} videos");
+    Ok(())
+}
+
+/// Extract the chosen tracks of `file` (picked interactively if not given), or merge them with `merge_with`
+async fn extract(
+    file: Option<PathBuf>,
+    merge_with: Option<u32>,
+    merge_format: MergeFormat,
+    render_pgs: bool,
+    ctx: &Context,
+) -> eyre::Result<()> {
     // Get file path
-    let file_path = match args.file {
+    let file_path = match file {
         Some(x) => x,
-        None => pick_mkv_file().await?,
+        None => pick_input_file().await?,
     };
 
     info!("Extracting subtitles from {}", file_path.display());
@@ -52,11 +1522,99 @@ async fn main() -> eyre::Result<()> {
     }
 
     // Enumerate subtitle tracks
-    let tracks = pick_subtitle_tracks(&file_path).await?;
+    let tracks = pick_subtitle_tracks(&file_path, &ctx.rules, &ctx.options, &ctx.cancel).await?;
+
+    if let Some(index) = merge_with {
+        let Some(secondary) =
+            enumerate_subtitle_tracks_with_captions(&file_path, &ctx.options, &ctx.cancel)
+                .await?
+                .into_iter()
+                .find(|t| t.stream_index == index)
+        else {
+            bail!("No subtitle stream #0:{index} in {}", file_path.display());
+        };
+        if !secondary.is_text() {
+            bail!("Only text tracks can be merged: {}", secondary);
+        }
+        let (tracks, skipped): (Vec<_>, Vec<_>) = tracks
+            .iter()
+            .filter(|t| t.stream_index != index)
+            .partition(|t| t.is_text());
+        for track in skipped {
+            warn!("Skipping {} since only text tracks can be merged", track);
+        }
+        for track in tracks {
+            // e.g. `Movie.2.eng.merged.ass`
+            let output_path =
+                plan_subtitle_extraction(&file_path, track, &ctx.options, &ctx.output)?
+                    .output_path
+                    .with_extension(format!("merged.{}", merge_format.extension()));
+            if ctx.dry_run {
+                println!("{track}");
+                println!("  merged with: {secondary}");
+                println!("  output:      {}", output_path.display());
+                continue;
+            }
+            if let Some(dir) = output_path.parent() {
+                fs::create_dir_all(dir).await?;
+            }
+            write_merged_subtitles(
+                &file_path,
+                track,
+                &secondary,
+                merge_format,
+                &output_path,
+                ctx.output.overwrite,
+                &ctx.options,
+                &ctx.cancel,
+            )
+            .await?;
+        }
+        return Ok(());
+    }
+
+    if ctx.dry_run {
+        for track in tracks {
+            let plan = plan_subtitle_extraction(&file_path, &track, &ctx.options, &ctx.output)?;
+            let exists = if fs::try_exists(&plan.output_path).await? {
+                " (already exists)"
+            } else {
+                ""
+            };
+            println!("{track}");
+            println!("  output:  {}{exists}", plan.output_path.display());
+            println!("  command: {}", plan.command_line());
+            if let Some(fallback) = plan.transcode_fallback() {
+                println!("  if stream copy fails: {}", fallback.command_line());
+            }
+            println!(
+                "  then rename {} -> {}",
+                plan.temp_path.display(),
+                plan.output_path.display()
+            );
+            if let (Some(to), false) = (ctx.output.convert_bitmaps, track.is_text()) {
+                println!("  then convert to {:?}", to);
+            }
+        }
+        return Ok(());
+    }
 
     // Write subtitle tracks
     for track in tracks {
-        extract_subtitle_track(&file_path, &track).await?;
+        let extracted =
+            extract_subtitle_track(&file_path, &track, &ctx.options, &ctx.output, &ctx.cancel)
+                .await?;
+        if let Some(extracted) = extracted {
+            let is_sup = extracted
+                .output_path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("sup"));
+            if render_pgs && is_sup {
+                let output_dir = extracted.output_path.with_extension("");
+                pgs::render_sup_to_png(&extracted.output_path, &output_dir, ctx.output.overwrite)
+                    .await?;
+            }
+        }
     }
 
     Ok(())
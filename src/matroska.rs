@@ -0,0 +1,152 @@
+//! Just enough EBML to read the track headers of a Matroska file, for the fields ffmpeg doesn't
+//! show us, like `LanguageBCP47`.
+
+use eyre::bail;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::path::Path;
+
+const EBML_HEADER: u32 = 0x1A45_DFA3;
+const SEGMENT: u32 = 0x1853_8067;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_TYPE: u32 = 0x83;
+const NAME: u32 = 0x536E;
+const LANGUAGE: u32 = 0x0022_B59C;
+/// `LanguageIETF` in Matroska v4 drafts, renamed `LanguageBCP47`; the ID is the same
+const LANGUAGE_BCP47: u32 = 0x0022_B59D;
+
+/// `TrackType` of subtitle tracks
+const TRACK_TYPE_SUBTITLE: u64 = 0x11;
+
+/// Track headers larger than this are certainly not track headers
+const MAX_TRACKS_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The header fields of one Matroska subtitle track
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MatroskaTrack {
+    /// `Language`, an ISO 639-2 code; Matroska's default is `eng`.
+    pub language: Option<String>,
+
+    /// `LanguageBCP47`, e.g. `es-419`; overrides `language` when present.
+    pub language_bcp47: Option<String>,
+
+    /// `Name`, the track title.
+    pub name: Option<String>,
+}
+
+/// Read an element ID, keeping its length marker as Matroska specs write IDs
+fn read_id(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut first = [0u8; 1];
+    reader.read_exact(&mut first)?;
+    let len = first[0].leading_zeros() as usize + 1;
+    if len > 4 {
+        return Err(std::io::Error::other("invalid EBML element ID"));
+    }
+    let mut id = first[0] as u32;
+    for _ in 1..len {
+        reader.read_exact(&mut first)?;
+        id = id << 8 | first[0] as u32;
+    }
+    Ok(id)
+}
+
+/// Read an element size; `None` means "unknown", which live streams use for the segment
+fn read_size(reader: &mut impl Read) -> std::io::Result<Option<u64>> {
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    let len = byte[0].leading_zeros() as usize + 1;
+    if len > 8 {
+        return Err(std::io::Error::other("invalid EBML element size"));
+    }
+    let mut size = (byte[0] as u64) & (0xFF >> len);
+    let mut all_ones = size == 0xFF >> len;
+    for _ in 1..len {
+        reader.read_exact(&mut byte)?;
+        size = size << 8 | byte[0] as u64;
+        all_ones &= byte[0] == 0xFF;
+    }
+    Ok(if all_ones { None } else { Some(size) })
+}
+
+/// Walk the children of an element in `data`, yielding each ID with its payload
+fn children(mut data: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    std::iter::from_fn(move || {
+        let id = read_id(&mut data).ok()?;
+        let size = read_size(&mut data).ok()??;
+        let size = usize::try_from(size).ok()?.min(data.len());
+        let (payload, rest) = data.split_at(size);
+        data = rest;
+        Some((id, payload))
+    })
+}
+
+fn read_uint(payload: &[u8]) -> u64 {
+    payload.iter().fold(0, |acc, &b| acc << 8 | b as u64)
+}
+
+fn read_string(payload: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(payload);
+    let text = text.trim_end_matches('\0').trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// Parse the subtitle entries of a `Tracks` element payload, in file order
+fn parse_tracks(payload: &[u8]) -> Vec<MatroskaTrack> {
+    children(payload)
+        .filter(|(id, _)| *id == TRACK_ENTRY)
+        .filter_map(|(_, entry)| {
+            let mut track = MatroskaTrack::default();
+            let mut is_subtitle = false;
+            for (id, value) in children(entry) {
+                match id {
+                    TRACK_TYPE => is_subtitle = read_uint(value) == TRACK_TYPE_SUBTITLE,
+                    LANGUAGE => track.language = read_string(value),
+                    LANGUAGE_BCP47 => track.language_bcp47 = read_string(value),
+                    NAME => track.name = read_string(value),
+                    _ => {}
+                }
+            }
+            is_subtitle.then_some(track)
+        })
+        .collect()
+}
+
+/// Read the subtitle track headers of a Matroska stream, in the order ffmpeg numbers them.
+///
+/// Only the top level of the segment is walked, so clusters are skipped without being read.
+pub fn parse_matroska_tracks(mut reader: impl Read + Seek) -> eyre::Result<Vec<MatroskaTrack>> {
+    if read_id(&mut reader)? != EBML_HEADER {
+        bail!("Not a Matroska file");
+    }
+    let Some(header_size) = read_size(&mut reader)? else {
+        bail!("EBML header has an unknown size");
+    };
+    reader.seek(SeekFrom::Current(header_size as i64))?;
+    if read_id(&mut reader)? != SEGMENT {
+        bail!("No segment after the EBML header");
+    }
+    read_size(&mut reader)?;
+    loop {
+        let id = read_id(&mut reader)?;
+        let Some(size) = read_size(&mut reader)? else {
+            bail!("Reached an element of unknown size before the track headers");
+        };
+        if id == TRACKS {
+            if size > MAX_TRACKS_SIZE {
+                bail!("Track headers are implausibly large ({size} bytes)");
+            }
+            let mut payload = vec![0; size as usize];
+            reader.read_exact(&mut payload)?;
+            return Ok(parse_tracks(&payload));
+        }
+        reader.seek(SeekFrom::Current(size as i64))?;
+    }
+}
+
+/// Read the subtitle track headers of a Matroska file, see [`parse_matroska_tracks`]
+pub fn read_matroska_tracks(path: &Path) -> eyre::Result<Vec<MatroskaTrack>> {
+    let file = std::fs::File::open(path)?;
+    parse_matroska_tracks(std::io::BufReader::new(file))
+}
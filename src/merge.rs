@@ -0,0 +1,225 @@
+//! Bilingual subtitles: two text tracks merged into one file, for language learners.
+//!
+//! Each cue of the second track is paired with the cue of the first that it overlaps the most, and
+//! a pair is shown for as long as either is on screen.
+
+use crate::confirm_overwrite;
+use crate::format_ass_timestamp;
+use crate::read_cues;
+use crate::write_srt;
+use crate::Cue;
+use crate::FfmpegOptions;
+use crate::OverwritePolicy;
+use crate::SubtitleTrack;
+use clap::ValueEnum;
+use eyre::bail;
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::fs;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// The file a merged pair of tracks is written as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergeFormat {
+    /// ASS with a style per track, the first at the bottom and the second at the top
+    #[default]
+    Ass,
+    /// SRT with the second track's lines under the first's
+    Srt,
+}
+
+impl MergeFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            MergeFormat::Ass => "ass",
+            MergeFormat::Srt => "srt",
+        }
+    }
+}
+
+/// A cue of the first track with the cues of the second paired to it; either side may be empty
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergedCue {
+    pub start: Duration,
+    pub end: Duration,
+    pub primary: String,
+    pub secondary: String,
+}
+
+/// How long two cues are on screen together
+fn shared_time(a: &Cue, b: &Cue) -> Duration {
+    a.end.min(b.end).saturating_sub(a.start.max(b.start))
+}
+
+/// Pair each cue of `secondary` with the cue of `primary` it overlaps the most (see
+/// [`Cue::overlaps`]). Unpaired cues of either track are kept on their own.
+pub fn merge_cues(primary: &[Cue], secondary: &[Cue]) -> Vec<MergedCue> {
+    let mut paired: Vec<Vec<&Cue>> = vec![Vec::new(); primary.len()];
+    let mut merged = Vec::new();
+    for cue in secondary {
+        let best = primary
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.overlaps(cue))
+            .max_by_key(|(_, x)| shared_time(x, cue));
+        match best {
+            Some((i, _)) => paired[i].push(cue),
+            None => merged.push(MergedCue {
+                start: cue.start,
+                end: cue.end,
+                primary: String::new(),
+                secondary: cue.text.clone(),
+            }),
+        }
+    }
+    for (cue, paired) in primary.iter().zip(paired) {
+        merged.push(MergedCue {
+            start: paired
+                .iter()
+                .map(|x| x.start)
+                .fold(cue.start, Duration::min),
+            end: paired.iter().map(|x| x.end).fold(cue.end, Duration::max),
+            primary: cue.text.clone(),
+            secondary: paired
+                .iter()
+                .map(|x| x.text.as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        });
+    }
+    merged.sort_by_key(|x| (x.start, x.end));
+    merged
+}
+
+/// Write merged cues as SRT, the second track's lines under the first's
+pub fn write_merged_srt(cues: &[MergedCue]) -> String {
+    let cues: Vec<Cue> = cues
+        .iter()
+        .map(|cue| Cue {
+            start: cue.start,
+            end: cue.end,
+            text: [cue.primary.as_str(), cue.secondary.as_str()]
+                .into_iter()
+                .filter(|x| !x.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        })
+        .collect();
+    write_srt(&cues)
+}
+
+/// Convert SRT cue text to ASS: line breaks become `\N` and `<i>`/`<b>`/`<u>`/`<s>` become override
+/// tags. Other HTML-style tags, like `<font>`, are dropped.
+fn srt_text_to_ass(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open) = rest.find('<') {
+        out.push_str(&rest[..open]);
+        let Some(close) = rest[open..].find('>') else {
+            break;
+        };
+        let tag = rest[open + 1..open + close].trim().to_ascii_lowercase();
+        let (name, on) = match tag.strip_prefix('/') {
+            Some(name) => (name, 0),
+            None => (tag.as_str(), 1),
+        };
+        if matches!(name, "i" | "b" | "u" | "s") {
+            out.push_str(&format!("{{\\{name}{on}}}"));
+        }
+        rest = &rest[open + close + 1..];
+    }
+    out.push_str(rest);
+    out.replace("\r\n", "\n").replace('\n', "\\N")
+}
+
+/// Write merged cues as an ASS script titled `title`, with the first track in the `Bottom` style
+/// and the second in the `Top` style
+pub fn write_merged_ass(cues: &[MergedCue], title: &str) -> String {
+    let mut out = format!(
+        "[Script Info]\n\
+         Title: {title}\n\
+         ScriptType: v4.00+\n\
+         PlayResX: 1920\n\
+         PlayResY: 1080\n\
+         ScaledBorderAndShadow: yes\n\
+         \n\
+         [V4+ Styles]\n\
+         Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n\
+         Style: Bottom,Arial,64,&H00FFFFFF,&H000000FF,&H00000000,&H80000000,0,0,0,0,100,100,0,0,1,3,1,2,60,60,50,1\n\
+         Style: Top,Arial,56,&H0000FFFF,&H000000FF,&H00000000,&H80000000,0,0,0,0,100,100,0,0,1,3,1,8,60,60,50,1\n\
+         \n\
+         [Events]\n\
+         Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n"
+    );
+    for cue in cues {
+        for (style, text) in [("Bottom", &cue.primary), ("Top", &cue.secondary)] {
+            if text.is_empty() {
+                continue;
+            }
+            out.push_str(&format!(
+                "Dialogue: 0,{},{},{style},,0,0,0,,{}\n",
+                format_ass_timestamp(cue.start),
+                format_ass_timestamp(cue.end),
+                srt_text_to_ass(text)
+            ));
+        }
+    }
+    out
+}
+
+/// Read two text tracks of `path` and write them merged to `output_path`.
+///
+/// Returns `None` if the user chose to keep an existing output.
+#[allow(clippy::too_many_arguments)]
+pub async fn write_merged_subtitles(
+    path: &Path,
+    primary: &SubtitleTrack,
+    secondary: &SubtitleTrack,
+    format: MergeFormat,
+    output_path: &Path,
+    overwrite: OverwritePolicy,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Option<PathBuf>> {
+    for track in [primary, secondary] {
+        if !track.is_text() {
+            bail!("Only text tracks can be merged: {}", track);
+        }
+    }
+    if fs::try_exists(output_path).await? && !confirm_overwrite(output_path, overwrite)? {
+        info!("Skipping existing file: {}", output_path.display());
+        return Ok(None);
+    }
+    let merged = merge_cues(
+        &read_cues(path, primary, options, cancel).await?,
+        &read_cues(path, secondary, options, cancel).await?,
+    );
+    let text = match format {
+        MergeFormat::Srt => write_merged_srt(&merged),
+        MergeFormat::Ass => {
+            let label = |track: &SubtitleTrack| {
+                track
+                    .language()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("#{}", track.stream_index))
+            };
+            write_merged_ass(
+                &merged,
+                &format!("{} / {}", label(primary), label(secondary)),
+            )
+        }
+    };
+    fs::write(output_path, text).await?;
+    info!(
+        "Wrote {} with {} merged with {}",
+        output_path.display(),
+        primary,
+        secondary
+    );
+    Ok(Some(output_path.to_path_buf()))
+}
@@ -0,0 +1,146 @@
+use crate::bitmap::BitmapFormat;
+use crate::normalize_language;
+use crate::two_letter_code;
+use clap::ValueEnum;
+use eyre::bail;
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// The template that reproduces our original naming, e.g. "Blade Runner 2049.2.eng.English subs.srt",
+/// with `.forced` added for forced tracks
+pub const DEFAULT_NAMING_TEMPLATE: &str = "{stem}.{index}.{lang}.{title}.{forced}.{ext}";
+
+/// What to do when an output file already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverwritePolicy {
+    /// Prompt (via FZF) for each existing file
+    #[default]
+    Ask,
+    /// Replace existing files without asking
+    Always,
+    /// Leave existing files alone and skip the track
+    Skip,
+}
+
+/// Where and how extracted files are written
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    /// Write outputs here instead of next to the input file.
+    pub output_dir: Option<PathBuf>,
+
+    /// File name template, see [`render_output_name`].
+    pub naming_template: String,
+
+    /// What to do when the output file already exists.
+    pub overwrite: OverwritePolicy,
+
+    /// Convert PGS and DVD bitmap tracks to this format after extracting them.
+    pub convert_bitmaps: Option<BitmapFormat>,
+
+    /// The screen size converted bitmap subtitles are scaled to, e.g. `(720, 576)`.
+    pub resolution: Option<(u16, u16)>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            output_dir: None,
+            naming_template: DEFAULT_NAMING_TEMPLATE.to_string(),
+            overwrite: OverwritePolicy::default(),
+            convert_bitmaps: None,
+            resolution: None,
+        }
+    }
+}
+
+/// The values available to a naming template
+#[derive(Debug, Clone, Default)]
+pub struct NameParts {
+    /// `{stem}`: the input file name without its extension.
+    pub stem: String,
+
+    /// `{index}`: the subtitle stream index.
+    pub index: u32,
+
+    /// `{lang}`: the track language as tagged, if any. `{lang2}` and `{lang3}` give its ISO 639-1
+    /// and 639-2/B forms.
+    pub lang: Option<String>,
+
+    /// `{title}`: the sanitized track title, if any.
+    pub title: Option<String>,
+
+    /// `{format}`: the codec name, e.g. `subrip`.
+    pub format: String,
+
+    /// `{forced}`: `forced` if the track is tagged or detected as forced, otherwise empty.
+    pub forced: bool,
+
+    /// `{ext}`: the output extension without the dot.
+    pub ext: String,
+}
+
+impl NameParts {
+    fn lookup(&self, key: &str) -> eyre::Result<String> {
+        Ok(match key {
+            "stem" => self.stem.clone(),
+            "index" => self.index.to_string(),
+            "lang" => self.lang.clone().unwrap_or_default(),
+            "lang2" => self
+                .lang
+                .as_deref()
+                .map(two_letter_code)
+                .unwrap_or_default(),
+            "lang3" => self
+                .lang
+                .as_deref()
+                .map(normalize_language)
+                .unwrap_or_default(),
+            "title" => self.title.clone().unwrap_or_default(),
+            "format" => self.format.clone(),
+            "forced" => if self.forced { "forced" } else { "" }.to_string(),
+            "ext" => self.ext.clone(),
+            other => bail!("Unknown naming placeholder {{{other}}}"),
+        })
+    }
+}
+
+/// Fill in a naming template such as `{stem}.{index}.{lang}.{title}.{ext}`.
+///
+/// A placeholder that is empty also drops the separator (`.`, `_`, `-` or space) in front of it,
+/// so a track without a language becomes `Movie.2.srt` rather than `Movie.2..srt`.
+/// If the template has no `{ext}`, the extension is appended.
+pub fn render_output_name(template: &str, parts: &NameParts) -> eyre::Result<String> {
+    let mut out = String::new();
+    let mut rest = template;
+    let mut saw_ext = false;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let close = match after.find('}') {
+            Some(x) => x,
+            None => bail!("Unclosed '{{' in naming template: {template}"),
+        };
+        let key = after[..close].trim();
+        saw_ext |= key == "ext";
+        let value = parts.lookup(key)?;
+        if value.is_empty() {
+            if out.ends_with(['.', '_', '-', ' ']) {
+                out.pop();
+            }
+        } else {
+            out.push_str(&value);
+        }
+        rest = &after[close + 1..];
+    }
+    out.push_str(rest);
+    if !saw_ext {
+        out.push('.');
+        out.push_str(&parts.ext);
+    }
+    if out.trim_matches('.').is_empty() {
+        bail!("Naming template {template:?} produced an empty file name");
+    }
+    Ok(out)
+}
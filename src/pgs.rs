@@ -0,0 +1,1029 @@
+//! Presentation Graphic Stream (Blu-ray `.sup`) parsing and rendering.
+//!
+//! A `.sup` file is a sequence of segments, each starting with `PG`, a PTS/DTS and a type:
+//! PCS (composition), WDS (windows), PDS (palette), ODS (RLE bitmap) and END.
+//! A display set runs from a PCS to the following END.
+
+use crate::confirm_overwrite;
+use crate::OverwritePolicy;
+use eyre::bail;
+use eyre::eyre;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use tokio::fs;
+use tracing::info;
+use tracing::warn;
+
+const SEGMENT_PDS: u8 = 0x14;
+const SEGMENT_ODS: u8 = 0x15;
+const SEGMENT_PCS: u8 = 0x16;
+const SEGMENT_WDS: u8 = 0x17;
+const SEGMENT_END: u8 = 0x80;
+
+/// PTS/DTS values tick at 90kHz
+pub const PTS_PER_SECOND: u64 = 90_000;
+
+/// Convert a 90kHz timestamp to a duration
+pub fn pts_to_duration(pts: u64) -> Duration {
+    Duration::from_micros(pts * 1_000_000 / PTS_PER_SECOND)
+}
+
+/// Whether a display set starts a new epoch, refreshes everything, or updates the current screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompositionState {
+    Normal,
+    AcquisitionPoint,
+    EpochStart,
+}
+
+impl CompositionState {
+    fn from_byte(byte: u8) -> Self {
+        match byte & 0xC0 {
+            0x80 => CompositionState::EpochStart,
+            0x40 => CompositionState::AcquisitionPoint,
+            _ => CompositionState::Normal,
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            CompositionState::Normal => 0x00,
+            CompositionState::AcquisitionPoint => 0x40,
+            CompositionState::EpochStart => 0x80,
+        }
+    }
+}
+
+/// One object placed on screen by a PCS
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompositionObject {
+    pub object_id: u16,
+    pub window_id: u8,
+    pub x: u16,
+    pub y: u16,
+    /// Must be shown even when subtitles are off.
+    pub forced: bool,
+    /// `(x, y, width, height)` within the object, if only part of it is shown.
+    pub crop: Option<(u16, u16, u16, u16)>,
+}
+
+/// The presentation composition segment that opens every display set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Composition {
+    /// The video width the subtitle was authored for.
+    pub width: u16,
+    /// The video height the subtitle was authored for.
+    pub height: u16,
+    pub frame_rate: u8,
+    pub number: u16,
+    pub state: CompositionState,
+    /// Only the palette changed since the previous display set.
+    pub palette_update: bool,
+    pub palette_id: u8,
+    pub objects: Vec<CompositionObject>,
+}
+
+/// A window (screen area that objects are drawn into)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Window {
+    pub id: u8,
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// A palette entry in the Blu-ray YCbCr form
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PaletteEntry {
+    pub y: u8,
+    pub cr: u8,
+    pub cb: u8,
+    pub alpha: u8,
+}
+
+/// A palette definition segment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub id: u8,
+    pub version: u8,
+    pub entries: Vec<(u8, PaletteEntry)>,
+}
+
+/// A complete object (reassembled from one or more ODS fragments)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectDefinition {
+    pub id: u16,
+    pub version: u8,
+    pub width: u16,
+    pub height: u16,
+    /// Run-length encoded palette indices, see [`decode_rle`].
+    pub rle: Vec<u8>,
+}
+
+/// Everything between a PCS and its END segment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplaySet {
+    /// Presentation time in 90kHz ticks.
+    pub pts: u64,
+    /// Decoding time in 90kHz ticks.
+    pub dts: u64,
+    pub composition: Composition,
+    pub windows: Vec<Window>,
+    pub palettes: Vec<Palette>,
+    pub objects: Vec<ObjectDefinition>,
+}
+
+/// A little big-endian cursor over segment payloads
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn bytes(&mut self, n: usize) -> eyre::Result<&'a [u8]> {
+        if self.remaining() < n {
+            bail!(
+                "Unexpected end of PGS data at offset {} (wanted {} more bytes)",
+                self.pos,
+                n
+            );
+        }
+        let out = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(out)
+    }
+
+    fn u8(&mut self) -> eyre::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> eyre::Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> eyre::Result<u32> {
+        let b = self.bytes(3)?;
+        Ok(u32::from_be_bytes([0, b[0], b[1], b[2]]))
+    }
+
+    fn u32(&mut self) -> eyre::Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+fn parse_composition(payload: &[u8]) -> eyre::Result<Composition> {
+    let mut r = Reader::new(payload);
+    let width = r.u16()?;
+    let height = r.u16()?;
+    let frame_rate = r.u8()?;
+    let number = r.u16()?;
+    let state = CompositionState::from_byte(r.u8()?);
+    let palette_update = r.u8()? & 0x80 != 0;
+    let palette_id = r.u8()?;
+    let count = r.u8()?;
+    let mut objects = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let object_id = r.u16()?;
+        let window_id = r.u8()?;
+        let flags = r.u8()?;
+        let x = r.u16()?;
+        let y = r.u16()?;
+        // Same flag layout as ffmpeg's pgssubdec: 0x80 = cropped, 0x40 = forced
+        let crop = if flags & 0x80 != 0 {
+            Some((r.u16()?, r.u16()?, r.u16()?, r.u16()?))
+        } else {
+            None
+        };
+        objects.push(CompositionObject {
+            object_id,
+            window_id,
+            x,
+            y,
+            forced: flags & 0x40 != 0,
+            crop,
+        });
+    }
+    Ok(Composition {
+        width,
+        height,
+        frame_rate,
+        number,
+        state,
+        palette_update,
+        palette_id,
+        objects,
+    })
+}
+
+fn parse_windows(payload: &[u8]) -> eyre::Result<Vec<Window>> {
+    let mut r = Reader::new(payload);
+    let count = r.u8()?;
+    (0..count)
+        .map(|_| {
+            Ok(Window {
+                id: r.u8()?,
+                x: r.u16()?,
+                y: r.u16()?,
+                width: r.u16()?,
+                height: r.u16()?,
+            })
+        })
+        .collect()
+}
+
+fn parse_palette(payload: &[u8]) -> eyre::Result<Palette> {
+    let mut r = Reader::new(payload);
+    let id = r.u8()?;
+    let version = r.u8()?;
+    let mut entries = Vec::new();
+    while r.remaining() >= 5 {
+        let index = r.u8()?;
+        entries.push((
+            index,
+            PaletteEntry {
+                y: r.u8()?,
+                cr: r.u8()?,
+                cb: r.u8()?,
+                alpha: r.u8()?,
+            },
+        ));
+    }
+    Ok(Palette {
+        id,
+        version,
+        entries,
+    })
+}
+
+/// Split a `.sup` byte stream into display sets
+pub fn parse_display_sets(data: &[u8]) -> eyre::Result<Vec<DisplaySet>> {
+    let mut r = Reader::new(data);
+    let mut result = Vec::new();
+    let mut current: Option<DisplaySet> = None;
+    // ODS fragments being reassembled: id -> (definition, expected rle length)
+    let mut pending: HashMap<u16, (ObjectDefinition, usize)> = HashMap::new();
+
+    while r.remaining() > 0 {
+        let offset = r.pos;
+        let magic = r.bytes(2)?;
+        if magic != b"PG" {
+            bail!("Bad PGS segment magic at offset {offset}: {magic:02x?}");
+        }
+        let pts = r.u32()? as u64;
+        let dts = r.u32()? as u64;
+        let kind = r.u8()?;
+        let size = r.u16()? as usize;
+        let payload = r.bytes(size)?;
+
+        match kind {
+            SEGMENT_PCS => {
+                if let Some(unfinished) = current.take() {
+                    warn!("Display set at pts {} has no END segment", unfinished.pts);
+                    result.push(unfinished);
+                }
+                current = Some(DisplaySet {
+                    pts,
+                    dts,
+                    composition: parse_composition(payload)?,
+                    windows: Vec::new(),
+                    palettes: Vec::new(),
+                    objects: Vec::new(),
+                });
+            }
+            SEGMENT_END => match current.take() {
+                Some(set) => result.push(set),
+                None => warn!("END segment without a display set at offset {offset}"),
+            },
+            SEGMENT_WDS | SEGMENT_PDS | SEGMENT_ODS => {
+                let set = current.as_mut().ok_or_else(|| {
+                    eyre!("Segment {kind:#04x} outside a display set at offset {offset}")
+                })?;
+                match kind {
+                    SEGMENT_WDS => set.windows.extend(parse_windows(payload)?),
+                    SEGMENT_PDS => set.palettes.push(parse_palette(payload)?),
+                    _ => {
+                        let mut p = Reader::new(payload);
+                        let id = p.u16()?;
+                        let version = p.u8()?;
+                        let sequence = p.u8()?;
+                        let first = sequence & 0x80 != 0;
+                        let last = sequence & 0x40 != 0;
+                        if first {
+                            // The length counts the width and height that follow
+                            let length = (p.u24()? as usize).saturating_sub(4);
+                            let width = p.u16()?;
+                            let height = p.u16()?;
+                            let object = ObjectDefinition {
+                                id,
+                                version,
+                                width,
+                                height,
+                                rle: Vec::with_capacity(length),
+                            };
+                            pending.insert(id, (object, length));
+                        }
+                        let rest = p.bytes(p.remaining())?;
+                        let (object, _) = pending.get_mut(&id).ok_or_else(|| {
+                            eyre!("ODS continuation for object {id} without a first fragment")
+                        })?;
+                        object.rle.extend_from_slice(rest);
+                        if last {
+                            let (object, length) = pending
+                                .remove(&id)
+                                .ok_or_else(|| eyre!("Object {id} vanished while reassembling"))?;
+                            if object.rle.len() != length {
+                                warn!(
+                                    "Object {} has {} bytes of RLE data, header said {}",
+                                    id,
+                                    object.rle.len(),
+                                    length
+                                );
+                            }
+                            set.objects.push(object);
+                        }
+                    }
+                }
+            }
+            other => warn!("Skipping unknown PGS segment type {other:#04x} at offset {offset}"),
+        }
+    }
+    if let Some(unfinished) = current.take() {
+        warn!(
+            "Last display set at pts {} has no END segment",
+            unfinished.pts
+        );
+        result.push(unfinished);
+    }
+    Ok(result)
+}
+
+/// Decode PGS run-length encoded data into one palette index per pixel
+pub fn decode_rle(rle: &[u8], width: u16, height: u16) -> eyre::Result<Vec<u8>> {
+    let (width, height) = (width as usize, height as usize);
+    let mut pixels = vec![0u8; width * height];
+    let mut r = Reader::new(rle);
+    let (mut x, mut y) = (0usize, 0usize);
+    while r.remaining() > 0 && y < height {
+        let (len, color) = match r.u8()? {
+            0 => {
+                let flags = r.u8()?;
+                if flags == 0 {
+                    // End of line
+                    x = 0;
+                    y += 1;
+                    continue;
+                }
+                let short = (flags & 0x3F) as usize;
+                let len = if flags & 0x40 != 0 {
+                    (short << 8) | r.u8()? as usize
+                } else {
+                    short
+                };
+                let color = if flags & 0x80 != 0 { r.u8()? } else { 0 };
+                (len, color)
+            }
+            color => (1, color),
+        };
+        let end = (x + len).min(width);
+        pixels[y * width + x..y * width + end].fill(color);
+        x = end;
+    }
+    Ok(pixels)
+}
+
+/// Convert a limited-range YCbCr palette entry to RGBA, using BT.709 for HD and BT.601 for SD
+pub fn ycbcr_to_rgba(entry: PaletteEntry, bt709: bool) -> [u8; 4] {
+    let y = (entry.y as f32 - 16.0) * 255.0 / 219.0;
+    let cb = (entry.cb as f32 - 128.0) * 255.0 / 224.0;
+    let cr = (entry.cr as f32 - 128.0) * 255.0 / 224.0;
+    let (r, g, b) = if bt709 {
+        (
+            y + 1.5748 * cr,
+            y - 0.1873 * cb - 0.4681 * cr,
+            y + 1.8556 * cb,
+        )
+    } else {
+        (
+            y + 1.402 * cr,
+            y - 0.344_136 * cb - 0.714_136 * cr,
+            y + 1.772 * cb,
+        )
+    };
+    let clamp = |v: f32| v.round().clamp(0.0, 255.0) as u8;
+    [clamp(r), clamp(g), clamp(b), entry.alpha]
+}
+
+/// One on-screen subtitle image and when it is shown
+#[derive(Debug, Clone)]
+pub struct RenderedSubtitle {
+    /// Presentation time in 90kHz ticks.
+    pub start: u64,
+    /// When the next display set replaces or clears this one, if any.
+    pub end: Option<u64>,
+    /// Position of the image on the `screen_width` x `screen_height` screen.
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+    pub screen_width: u16,
+    pub screen_height: u16,
+    /// Any of the objects shown is flagged forced.
+    pub forced: bool,
+    /// Row-major RGBA pixels.
+    pub rgba: Vec<u8>,
+}
+
+/// Render every display set that shows something, keeping palettes and objects across each epoch
+pub fn render_display_sets(sets: &[DisplaySet]) -> eyre::Result<Vec<RenderedSubtitle>> {
+    let mut palettes: HashMap<u8, [PaletteEntry; 256]> = HashMap::new();
+    let mut objects: HashMap<u16, ObjectDefinition> = HashMap::new();
+    let mut result: Vec<RenderedSubtitle> = Vec::new();
+
+    for set in sets {
+        // The previous image stays up until the next display set
+        if let Some(previous) = result.last_mut() {
+            if previous.end.is_none() {
+                previous.end = Some(set.pts);
+            }
+        }
+
+        if set.composition.state == CompositionState::EpochStart {
+            palettes.clear();
+            objects.clear();
+        }
+        for palette in &set.palettes {
+            let entries = palettes
+                .entry(palette.id)
+                .or_insert([PaletteEntry::default(); 256]);
+            for (index, entry) in &palette.entries {
+                entries[*index as usize] = *entry;
+            }
+        }
+        for object in &set.objects {
+            objects.insert(object.id, object.clone());
+        }
+
+        if set.composition.objects.is_empty() {
+            continue;
+        }
+        let Some(palette) = palettes.get(&set.composition.palette_id) else {
+            warn!(
+                "Display set at pts {} uses undefined palette {}",
+                set.pts, set.composition.palette_id
+            );
+            continue;
+        };
+        let bt709 = set.composition.height > 576;
+        let colors: Vec<[u8; 4]> = palette.iter().map(|e| ycbcr_to_rgba(*e, bt709)).collect();
+
+        // Work out which part of each object is visible and where it lands on screen
+        let mut placed = Vec::new();
+        for comp in &set.composition.objects {
+            let Some(object) = objects.get(&comp.object_id) else {
+                warn!(
+                    "Display set at pts {} uses undefined object {}",
+                    set.pts, comp.object_id
+                );
+                continue;
+            };
+            let (cx, cy, cw, ch) = comp.crop.unwrap_or((0, 0, object.width, object.height));
+            let cw = cw.min(object.width.saturating_sub(cx));
+            let ch = ch.min(object.height.saturating_sub(cy));
+            placed.push((comp, object, cx, cy, cw, ch));
+        }
+        if placed.is_empty() {
+            continue;
+        }
+        let left = placed.iter().map(|p| p.0.x).min().unwrap_or(0);
+        let top = placed.iter().map(|p| p.0.y).min().unwrap_or(0);
+        // Widened, since positions and sizes read from the file can add up past u16
+        let right = placed
+            .iter()
+            .map(|p| p.0.x as u32 + p.4 as u32)
+            .max()
+            .unwrap_or(0);
+        let bottom = placed
+            .iter()
+            .map(|p| p.0.y as u32 + p.5 as u32)
+            .max()
+            .unwrap_or(0);
+        if right > u16::MAX as u32 || bottom > u16::MAX as u32 {
+            warn!(
+                "Display set at pts {} places an object past the edge of any screen, skipping it",
+                set.pts
+            );
+            continue;
+        }
+        let (width, height) = (right as u16 - left, bottom as u16 - top);
+        if width == 0 || height == 0 {
+            warn!(
+                "Display set at pts {} shows an empty image, skipping it",
+                set.pts
+            );
+            continue;
+        }
+
+        let mut rgba = vec![0u8; width as usize * height as usize * 4];
+        for (comp, object, cx, cy, cw, ch) in &placed {
+            let indices = decode_rle(&object.rle, object.width, object.height)?;
+            for row in 0..*ch as usize {
+                for col in 0..*cw as usize {
+                    let src = (*cy as usize + row) * object.width as usize + *cx as usize + col;
+                    let color = colors[indices[src] as usize];
+                    if color[3] == 0 {
+                        continue;
+                    }
+                    let dx = (comp.x - left) as usize + col;
+                    let dy = (comp.y - top) as usize + row;
+                    let dst = (dy * width as usize + dx) * 4;
+                    rgba[dst..dst + 4].copy_from_slice(&color);
+                }
+            }
+        }
+
+        result.push(RenderedSubtitle {
+            start: set.pts,
+            end: None,
+            x: left,
+            y: top,
+            width,
+            height,
+            screen_width: set.composition.width,
+            screen_height: set.composition.height,
+            forced: placed.iter().any(|p| p.0.forced),
+            rgba,
+        });
+    }
+    Ok(result)
+}
+
+/// Write one segment with its `PG` header
+fn write_segment(out: &mut Vec<u8>, pts: u64, dts: u64, kind: u8, payload: &[u8]) {
+    out.extend_from_slice(b"PG");
+    out.extend_from_slice(&(pts as u32).to_be_bytes());
+    out.extend_from_slice(&(dts as u32).to_be_bytes());
+    out.push(kind);
+    out.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// Serialize display sets back into `.sup` bytes
+pub fn write_display_sets(sets: &[DisplaySet]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for set in sets {
+        let c = &set.composition;
+        let mut pcs = Vec::new();
+        pcs.extend_from_slice(&c.width.to_be_bytes());
+        pcs.extend_from_slice(&c.height.to_be_bytes());
+        pcs.push(c.frame_rate);
+        pcs.extend_from_slice(&c.number.to_be_bytes());
+        pcs.push(c.state.to_byte());
+        pcs.push(if c.palette_update { 0x80 } else { 0x00 });
+        pcs.push(c.palette_id);
+        pcs.push(c.objects.len() as u8);
+        for o in &c.objects {
+            pcs.extend_from_slice(&o.object_id.to_be_bytes());
+            pcs.push(o.window_id);
+            let mut flags = 0u8;
+            if o.crop.is_some() {
+                flags |= 0x80;
+            }
+            if o.forced {
+                flags |= 0x40;
+            }
+            pcs.push(flags);
+            pcs.extend_from_slice(&o.x.to_be_bytes());
+            pcs.extend_from_slice(&o.y.to_be_bytes());
+            if let Some((x, y, w, h)) = o.crop {
+                for v in [x, y, w, h] {
+                    pcs.extend_from_slice(&v.to_be_bytes());
+                }
+            }
+        }
+        write_segment(&mut out, set.pts, set.dts, SEGMENT_PCS, &pcs);
+
+        if !set.windows.is_empty() {
+            let mut wds = vec![set.windows.len() as u8];
+            for w in &set.windows {
+                wds.push(w.id);
+                for v in [w.x, w.y, w.width, w.height] {
+                    wds.extend_from_slice(&v.to_be_bytes());
+                }
+            }
+            write_segment(&mut out, set.pts, set.dts, SEGMENT_WDS, &wds);
+        }
+
+        for p in &set.palettes {
+            let mut pds = vec![p.id, p.version];
+            for (index, e) in &p.entries {
+                pds.extend_from_slice(&[*index, e.y, e.cr, e.cb, e.alpha]);
+            }
+            write_segment(&mut out, set.pts, set.dts, SEGMENT_PDS, &pds);
+        }
+
+        for o in &set.objects {
+            // Segments hold at most 65535 bytes, so large objects are split into fragments
+            const FIRST_HEADER: usize = 4 + 3 + 4;
+            const MAX_PAYLOAD: usize = 0xFFFF;
+            let mut rest = o.rle.as_slice();
+            let mut first = true;
+            loop {
+                let header = if first { FIRST_HEADER } else { 4 };
+                let take = rest.len().min(MAX_PAYLOAD - header);
+                let (chunk, remaining) = rest.split_at(take);
+                let last = remaining.is_empty();
+                let mut ods = Vec::with_capacity(header + chunk.len());
+                ods.extend_from_slice(&o.id.to_be_bytes());
+                ods.push(o.version);
+                ods.push(if first { 0x80 } else { 0 } | if last { 0x40 } else { 0 });
+                if first {
+                    let length = (o.rle.len() + 4) as u32;
+                    ods.extend_from_slice(&length.to_be_bytes()[1..]);
+                    ods.extend_from_slice(&o.width.to_be_bytes());
+                    ods.extend_from_slice(&o.height.to_be_bytes());
+                }
+                ods.extend_from_slice(chunk);
+                write_segment(&mut out, set.pts, set.dts, SEGMENT_ODS, &ods);
+                rest = remaining;
+                first = false;
+                if last {
+                    break;
+                }
+            }
+        }
+
+        write_segment(&mut out, set.pts, set.dts, SEGMENT_END, &[]);
+    }
+    out
+}
+
+/// Run-length encode one palette index per pixel in the PGS format
+pub fn encode_rle(pixels: &[u8], width: u16, height: u16) -> Vec<u8> {
+    let width = width as usize;
+    let mut out = Vec::new();
+    for row in pixels.chunks(width).take(height as usize) {
+        let mut x = 0;
+        while x < row.len() {
+            let color = row[x];
+            let mut len = 1;
+            while x + len < row.len() && row[x + len] == color && len < 0x3FFF {
+                len += 1;
+            }
+            match (color, len) {
+                (0, len) if len < 64 => out.extend_from_slice(&[0, len as u8]),
+                (0, len) => out.extend_from_slice(&[0, 0x40 | (len >> 8) as u8, len as u8]),
+                (color, len) if len < 3 => out.extend(std::iter::repeat_n(color, len)),
+                (color, len) if len < 64 => out.extend_from_slice(&[0, 0x80 | len as u8, color]),
+                (color, len) => {
+                    out.extend_from_slice(&[0, 0xC0 | (len >> 8) as u8, len as u8, color])
+                }
+            }
+            x += len;
+        }
+        // End of line
+        out.extend_from_slice(&[0, 0]);
+    }
+    out
+}
+
+/// Convert RGBA to a limited-range YCbCr palette entry, the inverse of [`ycbcr_to_rgba`]
+pub fn rgba_to_ycbcr(rgba: [u8; 4], bt709: bool) -> PaletteEntry {
+    let [r, g, b, alpha] = rgba.map(|v| v as f32);
+    let (kr, kb) = if bt709 {
+        (0.2126, 0.0722)
+    } else {
+        (0.299, 0.114)
+    };
+    let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+    let cb = (b - y) / (2.0 * (1.0 - kb));
+    let cr = (r - y) / (2.0 * (1.0 - kr));
+    let clamp = |v: f32| v.round().clamp(0.0, 255.0) as u8;
+    PaletteEntry {
+        y: clamp(16.0 + y * 219.0 / 255.0),
+        cr: clamp(128.0 + cr * 224.0 / 255.0),
+        cb: clamp(128.0 + cb * 224.0 / 255.0),
+        alpha: alpha as u8,
+    }
+}
+
+/// Build display sets showing each image as its own epoch, with a clearing display set at its end.
+///
+/// Each image is reduced to at most 255 colours plus transparency.
+pub fn encode_display_sets(images: &[RenderedSubtitle]) -> Vec<DisplaySet> {
+    let mut sets = Vec::new();
+    let mut number = 0u16;
+    let mut next_number = || {
+        number = number.wrapping_add(1);
+        number
+    };
+    for (i, image) in images.iter().enumerate() {
+        let bt709 = image.screen_height > 576;
+        let (colors, indices) = crate::bitmap::quantize(&image.rgba, 256);
+        let window = Window {
+            id: 0,
+            x: image.x,
+            y: image.y,
+            width: image.width,
+            height: image.height,
+        };
+        let composition = |number: u16, state: CompositionState, shown: bool| Composition {
+            width: image.screen_width,
+            height: image.screen_height,
+            frame_rate: 0x10,
+            number,
+            state,
+            palette_update: false,
+            palette_id: 0,
+            objects: if shown {
+                vec![CompositionObject {
+                    object_id: 0,
+                    window_id: 0,
+                    x: image.x,
+                    y: image.y,
+                    forced: image.forced,
+                    crop: None,
+                }]
+            } else {
+                Vec::new()
+            },
+        };
+        sets.push(DisplaySet {
+            pts: image.start,
+            dts: 0,
+            composition: composition(next_number(), CompositionState::EpochStart, true),
+            windows: vec![window.clone()],
+            palettes: vec![Palette {
+                id: 0,
+                version: 0,
+                entries: colors
+                    .iter()
+                    .enumerate()
+                    .map(|(index, color)| (index as u8, rgba_to_ycbcr(*color, bt709)))
+                    .collect(),
+            }],
+            objects: vec![ObjectDefinition {
+                id: 0,
+                version: 0,
+                width: image.width,
+                height: image.height,
+                rle: encode_rle(&indices, image.width, image.height),
+            }],
+        });
+
+        // No need to clear the screen if the next image replaces this one
+        let replaced = images
+            .get(i + 1)
+            .is_some_and(|next| image.end.is_some_and(|end| next.start <= end));
+        if let (Some(end), false) = (image.end, replaced) {
+            sets.push(DisplaySet {
+                pts: end,
+                dts: 0,
+                composition: composition(next_number(), CompositionState::Normal, false),
+                windows: vec![window],
+                palettes: Vec::new(),
+                objects: Vec::new(),
+            });
+        }
+    }
+    sets
+}
+
+/// Format a 90kHz timestamp as `HH:MM:SS.mmm`
+pub fn format_pts(pts: u64) -> String {
+    let ms = pts_to_duration(pts).as_millis();
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Encode RGBA pixels as a PNG
+pub fn encode_png(rgba: &[u8], width: u16, height: u16) -> eyre::Result<Vec<u8>> {
+    if width == 0 || height == 0 {
+        bail!("Can't encode an empty {width}x{height} image as PNG");
+    }
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    writer.finish()?;
+    Ok(out)
+}
+
+/// Render a `.sup` file to numbered PNGs plus an `index.csv` timing file in `out_dir`.
+///
+/// Returns how many images were written, or `None` if the user chose to keep an existing render.
+pub async fn render_sup_to_png(
+    sup_path: &Path,
+    out_dir: &Path,
+    overwrite: OverwritePolicy,
+) -> eyre::Result<Option<usize>> {
+    let index_path = out_dir.join("index.csv");
+    if fs::try_exists(&index_path).await? && !confirm_overwrite(&index_path, overwrite)? {
+        info!("Skipping existing file: {}", index_path.display());
+        return Ok(None);
+    }
+    info!("Rendering {} to {}", sup_path.display(), out_dir.display());
+    let data = fs::read(sup_path).await?;
+    let sets = parse_display_sets(&data)?;
+    let images = render_display_sets(&sets)?;
+    fs::create_dir_all(out_dir).await?;
+
+    let mut index =
+        String::from("index,start,end,x,y,width,height,screen_width,screen_height,forced,file\n");
+    for (i, image) in images.iter().enumerate() {
+        let file_name = format!("{:04}.png", i + 1);
+        let png = encode_png(&image.rgba, image.width, image.height)?;
+        fs::write(out_dir.join(&file_name), png).await?;
+        index.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{},{}\n",
+            i + 1,
+            format_pts(image.start),
+            image.end.map(format_pts).unwrap_or_default(),
+            image.x,
+            image.y,
+            image.width,
+            image.height,
+            image.screen_width,
+            image.screen_height,
+            image.forced,
+            file_name
+        ));
+    }
+    fs::write(&index_path, index).await?;
+    info!(
+        "Rendered {} images from {} display sets",
+        images.len(),
+        sets.len()
+    );
+    Ok(Some(images.len()))
+}
+
+/// One display set that puts something on screen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventSummary {
+    /// Presentation time in 90kHz ticks.
+    pub start: u64,
+    /// The next display set's time, if any.
+    pub end: Option<u64>,
+    /// How many objects are composed.
+    pub objects: usize,
+    /// Any composed object is flagged forced.
+    pub forced: bool,
+}
+
+/// An overview of a `.sup` stream for the `info` command
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SupSummary {
+    pub display_sets: usize,
+    /// The time of every epoch start.
+    pub epoch_starts: Vec<u64>,
+    pub events: Vec<EventSummary>,
+    /// Every distinct composition (video) size, e.g. 1920x1080.
+    pub composition_sizes: Vec<(u16, u16)>,
+    /// How many palette definition segments there are.
+    pub palette_definitions: usize,
+    /// How many distinct palette ids are used.
+    pub palette_ids: usize,
+    /// Timing and structure problems worth a look.
+    pub issues: Vec<String>,
+}
+
+impl SupSummary {
+    /// How many events show at least one forced object
+    pub fn forced_events(&self) -> usize {
+        self.events.iter().filter(|e| e.forced).count()
+    }
+}
+
+/// Summarize parsed display sets without decoding any bitmaps
+pub fn summarize_display_sets(sets: &[DisplaySet]) -> SupSummary {
+    let mut summary = SupSummary {
+        display_sets: sets.len(),
+        ..Default::default()
+    };
+    let mut palette_ids = std::collections::BTreeSet::new();
+    for (i, set) in sets.iter().enumerate() {
+        let c = &set.composition;
+        if c.state == CompositionState::EpochStart {
+            summary.epoch_starts.push(set.pts);
+        }
+        if !summary.composition_sizes.contains(&(c.width, c.height)) {
+            summary.composition_sizes.push((c.width, c.height));
+        }
+        summary.palette_definitions += set.palettes.len();
+        palette_ids.extend(set.palettes.iter().map(|p| p.id));
+
+        if let Some(previous) = i.checked_sub(1).map(|j| &sets[j]) {
+            if set.pts < previous.pts {
+                summary.issues.push(format!(
+                    "Display set {} at {} is earlier than the one before it at {}",
+                    i + 1,
+                    format_pts(set.pts),
+                    format_pts(previous.pts)
+                ));
+            }
+        } else if c.state != CompositionState::EpochStart {
+            summary
+                .issues
+                .push("The first display set is not an epoch start".to_string());
+        }
+
+        if let Some(event) = summary.events.last_mut() {
+            if event.end.is_none() {
+                event.end = Some(set.pts);
+            }
+        }
+        if !c.objects.is_empty() {
+            summary.events.push(EventSummary {
+                start: set.pts,
+                end: None,
+                objects: c.objects.len(),
+                forced: c.objects.iter().any(|o| o.forced),
+            });
+        }
+    }
+    summary.palette_ids = palette_ids.len();
+    if summary.events.last().is_some_and(|e| e.end.is_none()) {
+        summary
+            .issues
+            .push("The last event is never cleared".to_string());
+    }
+    for (i, event) in summary.events.iter().enumerate() {
+        if event.end.is_some_and(|end| end <= event.start) {
+            summary
+                .issues
+                .push(format!("Event {} has no duration", i + 1));
+        }
+    }
+    summary
+}
+
+impl std::fmt::Display for SupSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sizes = self
+            .composition_sizes
+            .iter()
+            .map(|(w, h)| format!("{w}x{h}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(f, "Display sets:      {}", self.display_sets)?;
+        writeln!(f, "Events:            {}", self.events.len())?;
+        writeln!(
+            f,
+            "Forced events:     {}{}",
+            self.forced_events(),
+            if !self.events.is_empty() && self.forced_events() == self.events.len() {
+                " (forced-only track)"
+            } else {
+                ""
+            }
+        )?;
+        writeln!(f, "Composition sizes: {sizes}")?;
+        writeln!(
+            f,
+            "Palettes:          {} definitions, {} ids",
+            self.palette_definitions, self.palette_ids
+        )?;
+        writeln!(f, "Epochs:            {}", self.epoch_starts.len())?;
+        for (i, pts) in self.epoch_starts.iter().enumerate() {
+            writeln!(f, "  epoch {:>4} at {}", i + 1, format_pts(*pts))?;
+        }
+        writeln!(f, "Events:")?;
+        for (i, event) in self.events.iter().enumerate() {
+            writeln!(
+                f,
+                "  {:>5}  {} --> {}  {} object(s){}",
+                i + 1,
+                format_pts(event.start),
+                event.end.map(format_pts).unwrap_or_else(|| "?".repeat(12)),
+                event.objects,
+                if event.forced { "  forced" } else { "" }
+            )?;
+        }
+        if !self.issues.is_empty() {
+            writeln!(f, "Issues:")?;
+            for issue in &self.issues {
+                writeln!(f, "  - {issue}")?;
+            }
+        }
+        Ok(())
+    }
+}
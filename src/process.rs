@@ -0,0 +1,147 @@
+use eyre::bail;
+use std::future::Future;
+use std::path::PathBuf;
+use std::process::Output;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::process::Child;
+use tokio::process::ChildStdout;
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+use tracing::warn;
+
+/// Which ffmpeg binaries to run and the limits applied to the child processes we spawn
+#[derive(Debug, Clone)]
+pub struct FfmpegOptions {
+    /// The `ffmpeg` binary, either a bare name looked up in PATH or a full path.
+    pub ffmpeg: PathBuf,
+
+    /// The `ffprobe` binary, either a bare name looked up in PATH or a full path.
+    pub ffprobe: PathBuf,
+
+    /// How long `ffmpeg -i` may take to report the streams of a file.
+    pub probe_timeout: Option<Duration>,
+
+    /// How long a single track extraction may take.
+    pub extract_timeout: Option<Duration>,
+}
+
+impl Default for FfmpegOptions {
+    fn default() -> Self {
+        Self {
+            ffmpeg: PathBuf::from("ffmpeg"),
+            ffprobe: PathBuf::from("ffprobe"),
+            probe_timeout: Some(Duration::from_secs(60)),
+            extract_timeout: Some(Duration::from_secs(30 * 60)),
+        }
+    }
+}
+
+/// Spawn `cmd` with piped output, killed if it is dropped, returning it with its program name
+fn spawn_limited(mut cmd: Command, cancel: &CancellationToken) -> eyre::Result<(Child, String)> {
+    let program = cmd.as_std().get_program().to_string_lossy().to_string();
+    cmd.stdin(Stdio::null());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    // Dropping the child (e.g. when the select below picks another branch) kills it
+    cmd.kill_on_drop(true);
+
+    if cancel.is_cancelled() {
+        bail!("Cancelled before starting {program}");
+    }
+
+    let child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            bail!(
+                "Could not find `{program}`. Install FFmpeg from https://ffmpeg.org/download.html, \
+                 or point --ffmpeg/--ffprobe (or MKVSUBS_FFMPEG/MKVSUBS_FFPROBE) at your build"
+            );
+        }
+        Err(e) => return Err(e.into()),
+    };
+    debug!("Spawned {} (pid {:?})", program, child.id());
+    Ok((child, program))
+}
+
+/// Wait for `work`, giving up if it times out or `cancel` fires
+async fn within_limits<T>(
+    work: impl Future<Output = eyre::Result<T>>,
+    program: &str,
+    timeout: Option<Duration>,
+    cancel: &CancellationToken,
+) -> eyre::Result<T> {
+    let deadline = async {
+        match timeout {
+            Some(t) => tokio::time::sleep(t).await,
+            None => std::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        result = work => result,
+        _ = cancel.cancelled() => {
+            warn!("Cancelled, killing {}", program);
+            bail!("Cancelled while running {program}");
+        }
+        _ = deadline => {
+            warn!("Timed out, killing {}", program);
+            bail!("{program} timed out after {:?}", timeout.unwrap_or_default());
+        }
+    }
+}
+
+/// Run a command to completion, killing the child if it times out or `cancel` fires
+pub async fn run_with_limits(
+    cmd: Command,
+    timeout: Option<Duration>,
+    cancel: &CancellationToken,
+) -> eyre::Result<Output> {
+    let (child, program) = spawn_limited(cmd, cancel)?;
+    within_limits(
+        async { Ok(child.wait_with_output().await?) },
+        &program,
+        timeout,
+        cancel,
+    )
+    .await
+}
+
+/// Run a command like [`run_with_limits`], handing its stdout to `consume` as it is written
+/// instead of collecting it.
+///
+/// Stderr is collected at the same time, so a process logging a lot can't fill the pipe and stall.
+/// The returned output has an empty `stdout`.
+pub async fn stream_with_limits<T, F, Fut>(
+    cmd: Command,
+    timeout: Option<Duration>,
+    cancel: &CancellationToken,
+    consume: F,
+) -> eyre::Result<(Output, T)>
+where
+    F: FnOnce(ChildStdout) -> Fut,
+    Fut: Future<Output = eyre::Result<T>>,
+{
+    let (mut child, program) = spawn_limited(cmd, cancel)?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let work = async {
+        let read_stderr = async {
+            let mut buf = Vec::new();
+            stderr.read_to_end(&mut buf).await?;
+            eyre::Ok(buf)
+        };
+        // Returning early drops the child, which kills it
+        let (value, stderr) = tokio::try_join!(consume(stdout), read_stderr)?;
+        let status = child.wait().await?;
+        let output = Output {
+            status,
+            stdout: Vec::new(),
+            stderr,
+        };
+        Ok((output, value))
+    };
+    within_limits(work, &program, timeout, cancel).await
+}
@@ -0,0 +1,510 @@
+use crate::confirm_overwrite;
+use crate::find_language;
+use crate::language_matches;
+use crate::normalize_language;
+use crate::quote_arg;
+use crate::remove_temp_file;
+use crate::run_with_limits;
+use crate::FfmpegOptions;
+use crate::LanguageTag;
+use crate::OverwritePolicy;
+use crate::SubtitleTrack;
+use crate::TrackFilter;
+use eyre::bail;
+use itertools::Itertools;
+use std::path::Path;
+use std::path::PathBuf;
+use tokio::fs;
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+use tracing::info;
+
+/// Subtitle files that can be muxed into a Matroska file as they are
+pub const SIDECAR_EXTENSIONS: &[&str] = &["srt", "ass", "ssa", "sup", "vtt", "idx"];
+
+/// Font files attached alongside ASS subtitles, with the MIME type Matroska players look for
+const FONT_MIME_TYPES: &[(&str, &str)] = &[
+    ("ttf", "application/x-truetype-font"),
+    ("ttc", "application/x-truetype-font"),
+    ("otf", "application/vnd.ms-opentype"),
+];
+
+/// Directories next to a video that usually hold the fonts of its ASS subtitles
+const FONT_DIRS: &[&str] = &["fonts", "Fonts", "attachments", "Attachments"];
+
+/// A subtitle file to mux, with the metadata read from its name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sidecar {
+    /// The subtitle file.
+    pub path: PathBuf,
+
+    /// The stream index from an extracted name such as `Movie.2.eng.srt`, used for ordering.
+    pub index: Option<u32>,
+
+    /// The language, e.g. `eng` or `pt-BR`.
+    pub lang: Option<String>,
+
+    /// Whatever is left of the name, e.g. `English subs` or `SDH`.
+    pub title: Option<String>,
+
+    /// `default` appears in the name.
+    pub default: bool,
+
+    /// `forced` appears in the name.
+    pub forced: bool,
+
+    /// `sdh`, `cc` or `hi` appears in the name.
+    pub hearing_impaired: bool,
+}
+
+impl Sidecar {
+    /// True for ASS/SSA subtitles, which may need fonts attached
+    pub fn is_ass(&self) -> bool {
+        has_extension(&self.path, &["ass", "ssa"])
+    }
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .is_some_and(|ext| extensions.iter().any(|x| ext.eq_ignore_ascii_case(x)))
+}
+
+/// True if `token` is a language code rather than part of a title, e.g. `eng`, `de` or `pt-BR`
+fn is_language_token(token: &str) -> bool {
+    let tag = LanguageTag::parse(token);
+    (2..=3).contains(&tag.primary.len()) && find_language(token).is_some()
+}
+
+/// Read the metadata of a subtitle file named the way we name extracted tracks.
+///
+/// `file_name` must be `video_stem` followed by `.`-separated parts and a subtitle extension, e.g.
+/// `Movie.2.eng.English subs.forced.srt`: the first number is the index, the first language code
+/// is the language, `default`/`forced`/`sdh`/`cc`/`hi` set flags, and the rest is the title.
+pub fn parse_sidecar_name(video_stem: &str, file_name: &str) -> Option<Sidecar> {
+    let (name, ext) = file_name.rsplit_once('.')?;
+    if !SIDECAR_EXTENSIONS
+        .iter()
+        .any(|x| ext.eq_ignore_ascii_case(x))
+    {
+        return None;
+    }
+    let rest = name.strip_prefix(video_stem)?;
+    if !rest.is_empty() && !rest.starts_with('.') {
+        return None;
+    }
+    let mut sidecar = Sidecar::default();
+    let mut title = Vec::new();
+    for token in rest.split('.').filter(|x| !x.is_empty()) {
+        let lower = token.to_ascii_lowercase();
+        match lower.as_str() {
+            _ if sidecar.index.is_none() && token.parse::<u32>().is_ok() => {
+                sidecar.index = token.parse().ok();
+            }
+            _ if sidecar.lang.is_none() && is_language_token(token) => {
+                sidecar.lang = Some(token.to_string());
+            }
+            "default" => sidecar.default = true,
+            "forced" => sidecar.forced = true,
+            "hi" => sidecar.hearing_impaired = true,
+            // Extracted SDH tracks keep "SDH" as their title
+            "sdh" | "cc" => {
+                sidecar.hearing_impaired = true;
+                title.push(token);
+            }
+            _ => title.push(token),
+        }
+    }
+    if !title.is_empty() {
+        sidecar.title = Some(title.join("."));
+    }
+    Some(sidecar)
+}
+
+/// Read the metadata of a subtitle file given on the command line.
+///
+/// Names that don't start with the video's stem are read from their own first part instead, so
+/// `subs.eng.forced.srt` is still English and forced.
+pub fn sidecar_from_path(video: &Path, path: &Path) -> Sidecar {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let video_stem = video.file_stem().unwrap_or_default().to_string_lossy();
+    let own_stem = file_name.split('.').next().unwrap_or_default();
+    let sidecar = parse_sidecar_name(&video_stem, &file_name)
+        .or_else(|| parse_sidecar_name(own_stem, &file_name))
+        .unwrap_or_default();
+    Sidecar {
+        path: path.to_path_buf(),
+        ..sidecar
+    }
+}
+
+/// Find the subtitle files next to `video` whose names start with its stem, in index order
+pub async fn find_sidecars(video: &Path) -> eyre::Result<Vec<Sidecar>> {
+    let dir = video.parent().unwrap_or(Path::new("."));
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let video_stem = video.file_stem().unwrap_or_default().to_string_lossy();
+    let mut sidecars = Vec::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if let Some(sidecar) = parse_sidecar_name(&video_stem, &file_name) {
+            sidecars.push(Sidecar { path, ..sidecar });
+        }
+    }
+    sidecars.sort_by(|a, b| {
+        a.index
+            .unwrap_or(u32::MAX)
+            .cmp(&b.index.unwrap_or(u32::MAX))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    Ok(sidecars)
+}
+
+/// Find font files in `dir`, or in a `fonts`-like directory next to `video` if `dir` is `None`
+pub async fn find_fonts(video: &Path, dir: Option<&Path>) -> eyre::Result<Vec<PathBuf>> {
+    let dirs: Vec<PathBuf> = match dir {
+        Some(dir) => vec![dir.to_path_buf()],
+        None => {
+            let parent = video.parent().unwrap_or(Path::new(""));
+            FONT_DIRS.iter().map(|name| parent.join(name)).collect()
+        }
+    };
+    let mut fonts = Vec::new();
+    for dir in dirs {
+        if !fs::try_exists(&dir).await? {
+            continue;
+        }
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let is_font = FONT_MIME_TYPES
+                .iter()
+                .any(|(ext, _)| has_extension(&path, &[ext]));
+            if is_font && !fonts.contains(&path) {
+                fonts.push(path);
+            }
+        }
+        // "fonts" and "Fonts" are the same directory on case-insensitive file systems
+        if !fonts.is_empty() {
+            break;
+        }
+    }
+    fonts.sort();
+    Ok(fonts)
+}
+
+/// A remux worked out without touching the disk: ffmpeg writes `temp_path`, which is then renamed
+#[derive(Debug, Clone)]
+pub struct RemuxPlan {
+    /// The ffmpeg binary.
+    pub program: PathBuf,
+
+    /// The arguments passed to ffmpeg.
+    pub args: Vec<String>,
+
+    /// Where the finished file ends up.
+    pub output_path: PathBuf,
+
+    /// Where ffmpeg writes before we rename to `output_path`.
+    pub temp_path: PathBuf,
+
+    /// When `output_path` is the input being replaced, where the original is kept.
+    pub backup_path: Option<PathBuf>,
+}
+
+impl RemuxPlan {
+    /// Build the ffmpeg command this plan describes
+    pub fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.program);
+        cmd.args(&self.args);
+        cmd
+    }
+
+    /// The ffmpeg command as it could be pasted into a shell
+    pub fn command_line(&self) -> String {
+        std::iter::once(self.program.to_string_lossy().to_string())
+            .chain(self.args.iter().cloned())
+            .map(|arg| quote_arg(&arg))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// A path as an ffmpeg argument; `./` keeps names like `-x.mkv` or `a:b.mkv` from being read as options or protocols
+pub(crate) fn path_arg(path: &Path) -> String {
+    if path.is_absolute() || path.starts_with(".") {
+        path.to_string_lossy().to_string()
+    } else {
+        format!("./{}", path.display())
+    }
+}
+
+/// The file ffmpeg writes before it is renamed to `output_path`, e.g. `Movie.muxed.tmp.mkv`
+pub(crate) fn temp_path_for(output_path: &Path) -> PathBuf {
+    let stem = output_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let ext = output_path
+        .extension()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_else(|| "mkv".to_string());
+    output_path.with_file_name(format!("{stem}.tmp.{ext}"))
+}
+
+/// Count the streams of each kind in the output of `ffmpeg -i`, e.g. `Subtitle` or `Attachment`
+pub fn count_streams(ffmpeg_output: &str, kind: &str) -> usize {
+    let marker = format!(": {kind}:");
+    ffmpeg_output
+        .lines()
+        .filter(|line| line.trim_start().starts_with("Stream #0:") && line.contains(&marker))
+        .count()
+}
+
+/// Run `ffmpeg -i` and return its stream listing
+pub async fn probe_streams(
+    path: &Path,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<String> {
+    let mut cmd = Command::new(&options.ffmpeg);
+    cmd.arg("-i").arg(path);
+    let output = run_with_limits(cmd, options.probe_timeout, cancel).await?;
+    // Without an output file ffmpeg exits with an error, but the listing is still on stderr
+    Ok(String::from_utf8_lossy(&output.stderr).to_string())
+}
+
+/// Work out the ffmpeg command that copies `video` and adds `sidecars` as tagged subtitle tracks.
+///
+/// `existing_subtitles` and `existing_attachments` are the stream counts of `video`, since the new
+/// streams are numbered after them.
+pub fn plan_mux(
+    video: &Path,
+    existing_subtitles: usize,
+    existing_attachments: usize,
+    sidecars: &[Sidecar],
+    fonts: &[PathBuf],
+    output_path: &Path,
+    options: &FfmpegOptions,
+) -> RemuxPlan {
+    let temp_path = temp_path_for(output_path);
+    // The temp file is ours, so a leftover from an interrupted run is simply replaced
+    let mut args = vec!["-y".to_string(), "-i".to_string(), path_arg(video)];
+    for sidecar in sidecars {
+        args.extend(["-i".to_string(), path_arg(&sidecar.path)]);
+    }
+    for i in 0..=sidecars.len() {
+        args.extend(["-map".to_string(), i.to_string()]);
+    }
+    args.extend(["-c".to_string(), "copy".to_string()]);
+    for (i, sidecar) in sidecars.iter().enumerate() {
+        let stream = existing_subtitles + i;
+        if let Some(lang) = &sidecar.lang {
+            args.extend([
+                format!("-metadata:s:s:{stream}"),
+                format!("language={}", normalize_language(lang)),
+            ]);
+        }
+        if let Some(title) = &sidecar.title {
+            args.extend([format!("-metadata:s:s:{stream}"), format!("title={title}")]);
+        }
+        let flags: Vec<&str> = [
+            (sidecar.default, "default"),
+            (sidecar.forced, "forced"),
+            (sidecar.hearing_impaired, "hearing_impaired"),
+        ]
+        .into_iter()
+        .filter_map(|(set, name)| set.then_some(name))
+        .collect();
+        let disposition = if flags.is_empty() {
+            "0".to_string()
+        } else {
+            flags.join("+")
+        };
+        args.extend([format!("-disposition:s:{stream}"), disposition]);
+    }
+    for (i, font) in fonts.iter().enumerate() {
+        let mime = FONT_MIME_TYPES
+            .iter()
+            .find(|(ext, _)| has_extension(font, &[ext]))
+            .map_or("application/octet-stream", |(_, mime)| mime);
+        args.extend([
+            "-attach".to_string(),
+            path_arg(font),
+            format!("-metadata:s:t:{}", existing_attachments + i),
+            format!("mimetype={mime}"),
+        ]);
+    }
+    args.extend([
+        "-f".to_string(),
+        "matroska".to_string(),
+        path_arg(&temp_path),
+    ]);
+    RemuxPlan {
+        program: options.ffmpeg.clone(),
+        args,
+        output_path: output_path.to_path_buf(),
+        temp_path,
+        backup_path: None,
+    }
+}
+
+/// Positions in `tracks` of the subtitle tracks to drop: those matching any of `drop` and, if
+/// `keep_langs` isn't empty, those in none of those languages (untagged tracks included).
+///
+/// Closed-caption pseudo-tracks live inside the video stream and are never dropped.
+pub fn tracks_to_drop(
+    tracks: &[SubtitleTrack],
+    drop: &[TrackFilter],
+    keep_langs: &[String],
+) -> Vec<usize> {
+    tracks
+        .iter()
+        .positions(|track| {
+            let unwanted_lang = !keep_langs.is_empty()
+                && !track
+                    .language()
+                    .is_some_and(|lang| keep_langs.iter().any(|x| language_matches(x, lang)));
+            track.closed_captions_from.is_none()
+                && (unwanted_lang || drop.iter().any(|filter| filter.matches(track)))
+        })
+        .collect()
+}
+
+/// The path the original is kept at when replacing a file in place, e.g. `Movie.mkv.bak`
+pub fn backup_path_for(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".bak");
+    path.with_file_name(name)
+}
+
+/// Work out the ffmpeg command that stream copies `input` without the subtitle tracks `drop`.
+///
+/// If `output_path` is `input`, the original is kept at [`backup_path_for`].
+pub fn plan_strip(
+    input: &Path,
+    drop: &[&SubtitleTrack],
+    output_path: &Path,
+    options: &FfmpegOptions,
+) -> RemuxPlan {
+    let temp_path = temp_path_for(output_path);
+    let mut args = vec![
+        "-y".to_string(),
+        "-i".to_string(),
+        path_arg(input),
+        "-map".to_string(),
+        "0".to_string(),
+    ];
+    for track in drop {
+        // A negative map removes streams the previous maps added
+        args.extend(["-map".to_string(), format!("-0:s:{}", track.stream_index)]);
+    }
+    args.extend(["-c".to_string(), "copy".to_string(), path_arg(&temp_path)]);
+    RemuxPlan {
+        program: options.ffmpeg.clone(),
+        args,
+        output_path: output_path.to_path_buf(),
+        temp_path,
+        backup_path: (output_path == input).then(|| backup_path_for(input)),
+    }
+}
+
+/// Work out the ffmpeg command that copies `to` and adds the subtitle `tracks` of `from`, shifted by
+/// `offset_ms` (positive delays them).
+///
+/// The tracks keep their metadata and dispositions. If any is ASS, the attachments of `from` come
+/// along too, since they usually hold its fonts.
+pub fn plan_transplant(
+    to: &Path,
+    from: &Path,
+    tracks: &[&SubtitleTrack],
+    offset_ms: i64,
+    output_path: &Path,
+    options: &FfmpegOptions,
+) -> RemuxPlan {
+    let temp_path = temp_path_for(output_path);
+    let mut args = vec![
+        "-y".to_string(),
+        "-i".to_string(),
+        path_arg(to),
+        // Shifts every timestamp of the next input
+        "-itsoffset".to_string(),
+        format!("{:.3}", offset_ms as f64 / 1000.0),
+        "-i".to_string(),
+        path_arg(from),
+        "-map".to_string(),
+        "0".to_string(),
+    ];
+    for track in tracks {
+        args.extend(["-map".to_string(), format!("1:s:{}", track.stream_index)]);
+    }
+    if tracks
+        .iter()
+        .any(|track| matches!(track.format.as_str(), "ass" | "ssa"))
+    {
+        // `?` so a file without attachments isn't an error
+        args.extend(["-map".to_string(), "1:t?".to_string()]);
+    }
+    args.extend([
+        "-c".to_string(),
+        "copy".to_string(),
+        "-f".to_string(),
+        "matroska".to_string(),
+        path_arg(&temp_path),
+    ]);
+    RemuxPlan {
+        program: options.ffmpeg.clone(),
+        args,
+        output_path: output_path.to_path_buf(),
+        temp_path,
+        backup_path: None,
+    }
+}
+
+/// Run a remux to its temp file, then move it into place.
+///
+/// With a `backup_path`, the original is linked (or copied) there first, and the temp file then
+/// replaces it in a single rename, so the input path never points at a partial file.
+/// Returns `None` if the user chose to keep an existing output or backup.
+pub async fn run_remux(
+    plan: &RemuxPlan,
+    overwrite: OverwritePolicy,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Option<PathBuf>> {
+    let existing = plan.backup_path.as_ref().unwrap_or(&plan.output_path);
+    if fs::try_exists(existing).await? && !confirm_overwrite(existing, overwrite)? {
+        info!("Skipping existing file: {}", existing.display());
+        return Ok(None);
+    }
+    debug!("Running command `{}`", plan.command_line());
+    let result = run_with_limits(plan.command(), options.extract_timeout, cancel).await;
+    let failure = match &result {
+        Ok(output) if output.status.success() => None,
+        Ok(output) => Some(String::from_utf8_lossy(&output.stderr).to_string()),
+        Err(_) => Some(String::new()),
+    };
+    if let Some(stderr) = failure {
+        remove_temp_file(&plan.temp_path).await;
+        result?;
+        bail!("Failed to remux {}: {}", plan.output_path.display(), stderr);
+    }
+    if let Some(backup) = &plan.backup_path {
+        if fs::try_exists(backup).await? {
+            fs::remove_file(backup).await?;
+        }
+        if let Err(e) = fs::hard_link(&plan.output_path, backup).await {
+            debug!("Could not hard link the backup ({}), copying instead", e);
+            fs::copy(&plan.output_path, backup).await?;
+        }
+        info!("Kept the original as {}", backup.display());
+    }
+    fs::rename(&plan.temp_path, &plan.output_path).await?;
+    info!("Wrote {}", plan.output_path.display());
+    Ok(Some(plan.output_path.clone()))
+}
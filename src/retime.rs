@@ -0,0 +1,169 @@
+//! Rewriting the timestamps of subtitle files, leaving everything else as it was.
+//!
+//! Files are handled as bytes, since subtitles are often in a legacy encoding like cp1252 and only
+//! the ASCII timestamps need to change.
+
+use crate::confirm_overwrite;
+use crate::format_ass_timestamp;
+use crate::format_srt_timestamp;
+use crate::parse_ass;
+use crate::parse_ass_timestamp;
+use crate::parse_srt;
+use crate::parse_srt_timestamp;
+use crate::Cue;
+use crate::OverwritePolicy;
+use crate::Retiming;
+use eyre::bail;
+use std::path::Path;
+use std::path::PathBuf;
+use tokio::fs;
+use tracing::info;
+
+/// Subtitle files whose timing can be read and rewritten
+pub const RETIMABLE_EXTENSIONS: &[&str] = &["srt", "ass", "ssa"];
+
+fn is_ass(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("ass") || ext.eq_ignore_ascii_case("ssa"))
+}
+
+fn check_retimable(path: &Path) -> eyre::Result<()> {
+    let retimable = path.extension().is_some_and(|ext| {
+        RETIMABLE_EXTENSIONS
+            .iter()
+            .any(|x| ext.eq_ignore_ascii_case(x))
+    });
+    if !retimable {
+        bail!("Expected a .srt, .ass or .ssa file, got {}", path.display());
+    }
+    Ok(())
+}
+
+/// Decode subtitle text as UTF-8, or as Latin-1 if it isn't, so no byte is lost. Only the timing
+/// is read from it, so a cp1252 file decoded as Latin-1 is good enough.
+fn decode_text(data: &[u8]) -> String {
+    match std::str::from_utf8(data) {
+        Ok(text) => text.to_string(),
+        Err(_) => data.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// Read the cues of a `.srt`, `.ass` or `.ssa` file
+pub async fn read_subtitle_file(path: &Path) -> eyre::Result<Vec<Cue>> {
+    check_retimable(path)?;
+    let text = decode_text(&fs::read(path).await?);
+    let cues = if is_ass(path) {
+        parse_ass(&text)
+    } else {
+        parse_srt(&text)
+    };
+    if cues.is_empty() {
+        bail!("No cues found in {}", path.display());
+    }
+    Ok(cues)
+}
+
+/// Apply `edit` to each line of `data` without its line ending, keeping everything it leaves alone
+/// byte for byte
+fn map_lines(data: &[u8], edit: impl Fn(&[u8]) -> Option<Vec<u8>>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for line in data.split_inclusive(|&b| b == b'\n') {
+        let content_len = line.len()
+            - line
+                .iter()
+                .rev()
+                .take_while(|&&b| b == b'\r' || b == b'\n')
+                .count();
+        match edit(&line[..content_len]) {
+            Some(edited) => {
+                out.extend_from_slice(&edited);
+                out.extend_from_slice(&line[content_len..]);
+            }
+            None => out.extend_from_slice(line),
+        }
+    }
+    out
+}
+
+/// Retime the timing lines of SRT data; cues moved before zero are clamped to it
+pub fn retime_srt(data: &[u8], retiming: &Retiming) -> Vec<u8> {
+    map_lines(data, |line| {
+        // Timing lines are ASCII, so anything that isn't UTF-8 is text and left alone
+        let line = std::str::from_utf8(line).ok()?;
+        let (start, end) = line.split_once("-->")?;
+        let end = end.trim_start();
+        // Keep position hints after the end, e.g. `X1:100 X2:200`
+        let end_token = end.split_whitespace().next()?;
+        let start = parse_srt_timestamp(start)?;
+        let end_time = parse_srt_timestamp(end_token)?;
+        Some(
+            format!(
+                "{} --> {}{}",
+                format_srt_timestamp(retiming.apply(start)),
+                format_srt_timestamp(retiming.apply(end_time)),
+                &end[end_token.len()..]
+            )
+            .into_bytes(),
+        )
+    })
+}
+
+/// Retime the `Dialogue:` and `Comment:` events of an ASS/SSA script; events moved before zero are
+/// clamped to it. Only the Start and End fields change, the rest of each line is copied as it was.
+pub fn retime_ass(data: &[u8], retiming: &Retiming) -> Vec<u8> {
+    map_lines(data, |line| {
+        let colon = line.iter().position(|&b| b == b':')?;
+        if &line[..colon] != b"Dialogue" && &line[..colon] != b"Comment" {
+            return None;
+        }
+        // Layer, Start and End come first, so their bounds are the first three commas
+        let commas: Vec<usize> = line
+            .iter()
+            .enumerate()
+            .skip(colon)
+            .filter(|(_, &b)| b == b',')
+            .map(|(i, _)| i)
+            .take(3)
+            .collect();
+        let [layer_end, start_end, end_end] = commas[..] else {
+            return None;
+        };
+        let retime = |field: &[u8]| {
+            let time = parse_ass_timestamp(std::str::from_utf8(field).ok()?)?;
+            Some(format_ass_timestamp(retiming.apply(time)))
+        };
+        let start = retime(&line[layer_end + 1..start_end])?;
+        let end = retime(&line[start_end + 1..end_end])?;
+        let mut out = line[..=layer_end].to_vec();
+        out.extend_from_slice(start.as_bytes());
+        out.push(b',');
+        out.extend_from_slice(end.as_bytes());
+        out.extend_from_slice(&line[end_end..]);
+        Some(out)
+    })
+}
+
+/// Write `input` retimed to `output`, in the same format.
+///
+/// Returns `None` if the user chose to keep an existing output.
+pub async fn write_retimed_subtitle(
+    input: &Path,
+    output: &Path,
+    retiming: &Retiming,
+    overwrite: OverwritePolicy,
+) -> eyre::Result<Option<PathBuf>> {
+    check_retimable(input)?;
+    let data = fs::read(input).await?;
+    let retimed = if is_ass(input) {
+        retime_ass(&data, retiming)
+    } else {
+        retime_srt(&data, retiming)
+    };
+    if fs::try_exists(output).await? && !confirm_overwrite(output, overwrite)? {
+        info!("Skipping existing file: {}", output.display());
+        return Ok(None);
+    }
+    fs::write(output, retimed).await?;
+    info!("Wrote {} retimed by {}", output.display(), retiming);
+    Ok(Some(output.to_path_buf()))
+}
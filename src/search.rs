@@ -0,0 +1,147 @@
+//! Searching the dialogue of every video under a directory, without writing anything to disk.
+
+use crate::enumerate_subtitle_tracks;
+use crate::enumerate_subtitle_tracks_with_captions;
+use crate::is_supported_input;
+use crate::langdetect::strip_markup;
+use crate::language_matches;
+use crate::parse_srt;
+use crate::read_cues;
+use crate::read_subtitle_texts;
+use crate::Cue;
+use crate::FfmpegOptions;
+use crate::SubtitleTrack;
+use eyre::bail;
+use regex::Regex;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::fs;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+use tracing::warn;
+
+/// A line of a cue that matched a search
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchMatch {
+    /// When the cue starts.
+    pub start: Duration,
+
+    /// The matching line, without markup.
+    pub line: String,
+}
+
+/// Gather all supported video files (see [`crate::INPUT_EXTENSIONS`]) under `dir`, including its
+/// subdirectories, sorted by path. Directories that can't be read are skipped with a warning.
+pub async fn find_input_files_recursive(dir: &Path) -> eyre::Result<Vec<PathBuf>> {
+    if !fs::metadata(dir).await?.is_dir() {
+        bail!("{} is not a directory", dir.display());
+    }
+    let mut pending = vec![dir.to_path_buf()];
+    let mut found = Vec::new();
+    while let Some(dir) = pending.pop() {
+        // One unreadable directory shouldn't end the search
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Failed to read {}: {e}", dir.display());
+                continue;
+            }
+        };
+        loop {
+            let entry = match entries.next_entry().await {
+                Ok(Some(entry)) => entry,
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Failed to read {}: {e}", dir.display());
+                    break;
+                }
+            };
+            let Ok(file_type) = entry.file_type().await else {
+                continue;
+            };
+            let path = entry.path();
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if file_type.is_file() && is_supported_input(&path) {
+                found.push(path);
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// The lines of `cues` matching `pattern`, each line checked on its own with its markup removed
+pub fn search_cues(cues: &[Cue], pattern: &Regex) -> Vec<SearchMatch> {
+    cues.iter()
+        .flat_map(|cue| {
+            strip_markup(&cue.text)
+                .lines()
+                .map(str::trim)
+                .filter(|line| pattern.is_match(line))
+                .map(|line| SearchMatch {
+                    start: cue.start,
+                    line: line.to_string(),
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Search the text tracks of `path`, or only those in one of `languages` if any are given.
+///
+/// Closed captions are only searched if `closed_captions` is set, since reading them decodes the video.
+/// Returns each searched track with its matches, skipping tracks without any.
+pub async fn search_file(
+    path: &Path,
+    pattern: &Regex,
+    languages: &[String],
+    closed_captions: bool,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Vec<(SubtitleTrack, Vec<SearchMatch>)>> {
+    let mut embedded = Vec::new();
+    let mut captions = Vec::new();
+    let tracks = match closed_captions {
+        true => enumerate_subtitle_tracks_with_captions(path, options, cancel).await?,
+        false => enumerate_subtitle_tracks(path, options, cancel).await?,
+    };
+    for track in tracks {
+        if !track.is_text() {
+            debug!("Skipping {} since it has no text", track);
+            continue;
+        }
+        let wanted = languages.is_empty()
+            || track
+                .language()
+                .is_some_and(|lang| languages.iter().any(|x| language_matches(x, lang)));
+        if !wanted {
+            continue;
+        }
+        match track.closed_captions_from {
+            Some(_) => captions.push(track),
+            None => embedded.push(track),
+        }
+    }
+
+    // One ffmpeg run reads every embedded track; captions each need their own
+    let mut tracks = Vec::new();
+    let texts = read_subtitle_texts(path, &embedded, options, cancel).await?;
+    for (track, text) in embedded.into_iter().zip(texts) {
+        tracks.push((track, parse_srt(&text)));
+    }
+    for track in captions {
+        let cues = read_cues(path, &track, options, cancel).await?;
+        tracks.push((track, cues));
+    }
+
+    let mut results = Vec::new();
+    for (track, cues) in tracks {
+        let matches = search_cues(&cues, pattern);
+        if !matches.is_empty() {
+            results.push((track, matches));
+        }
+    }
+    Ok(results)
+}
@@ -0,0 +1,205 @@
+use crate::language_matches;
+use crate::SubtitleTrack;
+use eyre::bail;
+use itertools::Itertools;
+use serde::Deserialize;
+use serde::Serialize;
+use std::str::FromStr;
+
+/// One condition inside a [`TrackFilter`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Term {
+    /// `*`, matches every track
+    Any,
+    /// `lang=eng|en`, matching any code for the same language
+    Lang(Vec<String>),
+    /// `codec=subrip|ass`
+    Codec(Vec<String>),
+    /// `title~commentary`, a case-insensitive substring match
+    TitleContains(String),
+    /// `text` / `bitmap`
+    Text(bool),
+    /// `forced` / `!forced`, tagged or detected
+    Forced(bool),
+    /// `sdh` / `!sdh`
+    Sdh(bool),
+    /// `default` / `!default`
+    Default(bool),
+}
+
+impl Term {
+    fn matches(&self, track: &SubtitleTrack) -> bool {
+        match self {
+            Term::Any => true,
+            Term::Lang(langs) => track
+                .language()
+                .is_some_and(|lang| langs.iter().any(|x| language_matches(x, lang))),
+            Term::Codec(codecs) => codecs.iter().any(|x| x.eq_ignore_ascii_case(&track.format)),
+            Term::TitleContains(needle) => track
+                .title
+                .as_ref()
+                .is_some_and(|title| title.to_lowercase().contains(&needle.to_lowercase())),
+            Term::Text(want) => track.is_text() == *want,
+            Term::Forced(want) => track.is_forced() == *want,
+            Term::Sdh(want) => track.is_sdh() == *want,
+            Term::Default(want) => track.default == *want,
+        }
+    }
+}
+
+impl std::fmt::Display for Term {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flag = |f: &mut std::fmt::Formatter<'_>, want: bool, name: &str| {
+            if want {
+                write!(f, "{name}")
+            } else {
+                write!(f, "!{name}")
+            }
+        };
+        match self {
+            Term::Any => write!(f, "*"),
+            Term::Lang(langs) => write!(f, "lang={}", langs.join("|")),
+            Term::Codec(codecs) => write!(f, "codec={}", codecs.join("|")),
+            Term::TitleContains(needle) => write!(f, "title~{needle}"),
+            Term::Text(true) => write!(f, "text"),
+            Term::Text(false) => write!(f, "bitmap"),
+            Term::Forced(want) => flag(f, *want, "forced"),
+            Term::Sdh(want) => flag(f, *want, "sdh"),
+            Term::Default(want) => flag(f, *want, "default"),
+        }
+    }
+}
+
+/// A space-separated list of conditions that must all hold, e.g. `lang=eng text !sdh`.
+///
+/// Conditions:
+/// - `lang=eng|en`: the track language is one of these
+/// - `codec=subrip|ass`: the codec is one of these
+/// - `title~commentary`: the title contains this text (case-insensitive)
+/// - `text` / `bitmap`: text-based or image-based subtitles
+/// - `forced`, `sdh`, `default`: disposition flags, negated with a leading `!`
+/// - `*`: anything
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TrackFilter {
+    terms: Vec<Term>,
+}
+
+impl TrackFilter {
+    /// True if every condition holds for `track`
+    pub fn matches(&self, track: &SubtitleTrack) -> bool {
+        self.terms.iter().all(|term| term.matches(track))
+    }
+}
+
+impl FromStr for TrackFilter {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut terms = Vec::new();
+        for word in s.split_whitespace() {
+            let (negated, word) = match word.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, word),
+            };
+            let term = if let Some(value) = word.strip_prefix("lang=") {
+                Term::Lang(value.split('|').map(str::to_string).collect())
+            } else if let Some(value) = word.strip_prefix("codec=") {
+                Term::Codec(value.split('|').map(str::to_string).collect())
+            } else if let Some(value) = word.strip_prefix("title~") {
+                Term::TitleContains(value.to_string())
+            } else {
+                match word {
+                    "*" => Term::Any,
+                    "text" => Term::Text(!negated),
+                    "bitmap" => Term::Text(negated),
+                    "forced" => Term::Forced(!negated),
+                    "sdh" => Term::Sdh(!negated),
+                    "default" => Term::Default(!negated),
+                    other => bail!("Unknown condition {other:?} in track filter {s:?}"),
+                }
+            };
+            if negated
+                && matches!(
+                    term,
+                    Term::Any | Term::Lang(_) | Term::Codec(_) | Term::TitleContains(_)
+                )
+            {
+                bail!("Only flags can be negated with '!', found {word:?} in track filter {s:?}");
+            }
+            terms.push(term);
+        }
+        if terms.is_empty() {
+            bail!("Empty track filter, use `*` to match every track");
+        }
+        Ok(TrackFilter { terms })
+    }
+}
+
+impl TryFrom<String> for TrackFilter {
+    type Error = eyre::Report;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<TrackFilter> for String {
+    fn from(value: TrackFilter) -> Self {
+        value.to_string()
+    }
+}
+
+impl std::fmt::Display for TrackFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.terms.iter().join(" "))
+    }
+}
+
+/// How tracks are ordered and pre-selected before (or instead of) the picker
+#[derive(Debug, Clone, Default)]
+pub struct SelectionRules {
+    /// Languages listed first in the picker, in this order.
+    pub languages: Vec<String>,
+
+    /// Ranked filters; the first track matching the first filter that matches anything is selected.
+    pub prefer: Vec<TrackFilter>,
+
+    /// Every track matching any of these filters is selected in addition.
+    pub always: Vec<TrackFilter>,
+
+    /// Skip the picker when the rules select at least one track.
+    pub auto: bool,
+
+    /// Read same-language tracks to flag untagged forced subsets before selecting.
+    pub detect_forced: bool,
+
+    /// Read untagged text tracks and detect their language before selecting.
+    pub detect_lang: bool,
+}
+
+impl SelectionRules {
+    /// True if there are no `prefer` or `always` filters
+    pub fn is_empty(&self) -> bool {
+        self.prefer.is_empty() && self.always.is_empty()
+    }
+
+    /// Return the positions in `tracks` of the tracks these rules select, in track order
+    pub fn select(&self, tracks: &[SubtitleTrack]) -> Vec<usize> {
+        let mut chosen = Vec::new();
+        // The first ranked filter with any match wins, and only its first match is taken
+        if let Some(i) = self
+            .prefer
+            .iter()
+            .find_map(|filter| tracks.iter().position(|t| filter.matches(t)))
+        {
+            chosen.push(i);
+        }
+        for filter in &self.always {
+            chosen.extend(tracks.iter().positions(|t| filter.matches(t)));
+        }
+        chosen.sort();
+        chosen.dedup();
+        chosen
+    }
+}
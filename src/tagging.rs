@@ -0,0 +1,216 @@
+use crate::backup_path_for;
+use crate::normalize_language;
+use crate::remux::path_arg;
+use crate::remux::temp_path_for;
+use crate::run_remux;
+use crate::run_with_limits;
+use crate::two_letter_code;
+use crate::FfmpegOptions;
+use crate::LanguageTag;
+use crate::OverwritePolicy;
+use crate::RemuxPlan;
+use crate::SubtitleTrack;
+use eyre::bail;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+use tracing::info;
+use tracing::warn;
+
+/// Files mkvpropedit can edit
+const MATROSKA_EXTENSIONS: &[&str] = &["mkv", "mka", "mks", "webm"];
+
+/// The metadata to change on a subtitle track; `None` leaves a field as it is
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackEdits {
+    /// The new language, e.g. `ger`, `de` or `pt-BR`.
+    pub lang: Option<String>,
+
+    /// The new title; an empty string removes it.
+    pub title: Option<String>,
+
+    /// Set or clear the default flag.
+    pub default: Option<bool>,
+
+    /// Set or clear the forced flag.
+    pub forced: Option<bool>,
+
+    /// Set or clear the hearing impaired flag.
+    pub hearing_impaired: Option<bool>,
+}
+
+impl TrackEdits {
+    /// True if nothing would change
+    pub fn is_empty(&self) -> bool {
+        *self == TrackEdits::default()
+    }
+}
+
+/// The mkvpropedit arguments applying `edits` to each of `tracks`.
+///
+/// mkvpropedit numbers subtitle tracks from 1 (`track:s1`), we number them from 0.
+pub fn mkvpropedit_args(path: &Path, tracks: &[&SubtitleTrack], edits: &TrackEdits) -> Vec<String> {
+    // A leading `./` keeps a file named like an option from being read as one
+    let mut args = vec![path_arg(path)];
+    for track in tracks {
+        args.extend([
+            "--edit".to_string(),
+            format!("track:s{}", track.stream_index + 1),
+        ]);
+        let mut set = Vec::new();
+        if let Some(lang) = &edits.lang {
+            set.push(format!("language={}", normalize_language(lang)));
+            // Players prefer the BCP-47 field, so an old value there would hide the new language
+            set.push(format!("language-ietf={}", two_letter_code(lang)));
+        }
+        match edits.title.as_deref() {
+            Some("") => args.extend(["--delete".to_string(), "name".to_string()]),
+            Some(title) => set.push(format!("name={title}")),
+            None => {}
+        }
+        for (value, property) in [
+            (edits.default, "flag-default"),
+            (edits.forced, "flag-forced"),
+            (edits.hearing_impaired, "flag-hearing-impaired"),
+        ] {
+            if let Some(value) = value {
+                set.push(format!("{property}={}", u8::from(value)));
+            }
+        }
+        for property in set {
+            args.extend(["--set".to_string(), property]);
+        }
+    }
+    args
+}
+
+/// Work out the ffmpeg remux that applies `edits` to each of `tracks`, replacing `input` and
+/// keeping the original at [`backup_path_for`].
+///
+/// ffmpeg sets a stream's dispositions all at once, so the result keeps the track's default,
+/// forced and hearing impaired flags with `edits` applied, and drops any others.
+pub fn plan_tag_remux(
+    input: &Path,
+    tracks: &[&SubtitleTrack],
+    edits: &TrackEdits,
+    options: &FfmpegOptions,
+) -> RemuxPlan {
+    let temp_path = temp_path_for(input);
+    let mut args = vec![
+        "-y".to_string(),
+        "-i".to_string(),
+        path_arg(input),
+        "-map".to_string(),
+        "0".to_string(),
+        "-c".to_string(),
+        "copy".to_string(),
+    ];
+    for track in tracks {
+        let stream = track.stream_index;
+        if let Some(lang) = &edits.lang {
+            args.extend([
+                format!("-metadata:s:s:{stream}"),
+                format!("language={}", normalize_language(lang)),
+            ]);
+        }
+        if let Some(title) = &edits.title {
+            args.extend([format!("-metadata:s:s:{stream}"), format!("title={title}")]);
+        }
+        let flags: Vec<&str> = [
+            (edits.default.unwrap_or(track.default), "default"),
+            (edits.forced.unwrap_or(track.forced), "forced"),
+            (
+                edits.hearing_impaired.unwrap_or(track.hearing_impaired),
+                "hearing_impaired",
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(set, name)| set.then_some(name))
+        .collect();
+        let disposition = if flags.is_empty() {
+            "0".to_string()
+        } else {
+            flags.join("+")
+        };
+        args.extend([format!("-disposition:s:{stream}"), disposition]);
+    }
+    args.push(path_arg(&temp_path));
+    RemuxPlan {
+        program: options.ffmpeg.clone(),
+        args,
+        output_path: input.to_path_buf(),
+        temp_path,
+        backup_path: Some(backup_path_for(input)),
+    }
+}
+
+/// True if `program --version` runs
+async fn is_available(program: &Path) -> bool {
+    Command::new(program)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await
+        .is_ok_and(|status| status.success())
+}
+
+/// Apply `edits` to `tracks` in the file itself.
+///
+/// Matroska files are edited in place by mkvpropedit when it is installed, which only rewrites the
+/// track headers. Otherwise the file is remuxed by ffmpeg, see [`plan_tag_remux`].
+pub async fn tag_subtitle_tracks(
+    path: &Path,
+    tracks: &[&SubtitleTrack],
+    edits: &TrackEdits,
+    mkvpropedit: &Path,
+    overwrite: OverwritePolicy,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<Option<PathBuf>> {
+    let is_matroska = path.extension().is_some_and(|ext| {
+        MATROSKA_EXTENSIONS
+            .iter()
+            .any(|x| ext.eq_ignore_ascii_case(x))
+    });
+    if is_matroska && is_available(mkvpropedit).await {
+        let mut cmd = Command::new(mkvpropedit);
+        cmd.args(mkvpropedit_args(path, tracks, edits));
+        debug!(
+            "Running {} {:?}",
+            mkvpropedit.display(),
+            cmd.as_std().get_args()
+        );
+        let output = run_with_limits(cmd, options.extract_timeout, cancel).await?;
+        if !output.status.success() {
+            bail!(
+                "mkvpropedit failed: {}",
+                String::from_utf8_lossy(&output.stdout)
+            );
+        }
+        info!("Edited {} in place with mkvpropedit", path.display());
+        return Ok(Some(path.to_path_buf()));
+    }
+    if is_matroska {
+        warn!(
+            "{} not found, remuxing with ffmpeg instead",
+            mkvpropedit.display()
+        );
+    }
+    // ffmpeg only writes the 3-letter language, so `pt-BR` is saved as `por`
+    if let Some(lang) = &edits.lang {
+        if !LanguageTag::parse(lang).subtags.is_empty() {
+            warn!(
+                "The region or script of {} needs mkvpropedit, it will be saved as {}",
+                lang,
+                normalize_language(lang)
+            );
+        }
+    }
+    let plan = plan_tag_remux(path, tracks, edits, options);
+    run_remux(&plan, overwrite, options, cancel).await
+}
@@ -0,0 +1,159 @@
+use crate::run_with_limits;
+use crate::FfmpegOptions;
+use eyre::bail;
+use eyre::eyre;
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+use tracing::info;
+use tracing::warn;
+
+/// Oldest libavformat we support; 58 ships with FFmpeg 4.0, the first release with the `sup` muxer
+pub const MIN_LIBAVFORMAT_MAJOR: u32 = 58;
+
+/// Oldest FFmpeg release we support, used when the libavformat line is missing
+pub const MIN_FFMPEG_MAJOR: u32 = 4;
+
+/// What we learned from `ffmpeg -version`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FfmpegVersion {
+    /// Everything after `ffmpeg version`, e.g. `6.1.1-3ubuntu5` or `2024-12-19-git-494c961379-full_build-www.gyan.dev`.
+    pub raw: String,
+
+    /// The release number, if the build has one. Git snapshots (`N-113000-g...`, dated builds) do not.
+    pub release: Option<(u32, u32)>,
+
+    /// The libavformat version from the `libavformat  61.  7.100 / 61.  7.100` line.
+    pub libavformat: Option<(u32, u32, u32)>,
+}
+
+impl FfmpegVersion {
+    /// Bail with a helpful message if this build is older than we support
+    pub fn ensure_supported(&self) -> eyre::Result<()> {
+        if let Some((major, _, _)) = self.libavformat {
+            if major < MIN_LIBAVFORMAT_MAJOR {
+                bail!(
+                    "ffmpeg {} is too old (libavformat {major}), FFmpeg {MIN_FFMPEG_MAJOR}.0 or newer is required",
+                    self.raw
+                );
+            }
+        } else if let Some((major, _)) = self.release {
+            if major < MIN_FFMPEG_MAJOR {
+                bail!(
+                    "ffmpeg {} is too old, FFmpeg {MIN_FFMPEG_MAJOR}.0 or newer is required",
+                    self.raw
+                );
+            }
+        } else {
+            warn!(
+                "Could not determine the version of ffmpeg {}, continuing anyway",
+                self.raw
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Parse the output of `ffmpeg -version`
+pub fn parse_ffmpeg_version(output: &str) -> Option<FfmpegVersion> {
+    let mut lines = output.lines();
+
+    // e.g. "ffmpeg version 6.1.1-3ubuntu5 Copyright (c) 2000-2023 the FFmpeg developers"
+    let first = lines.next()?.trim();
+    let after = first
+        .strip_prefix("ffmpeg version ")
+        .or_else(|| first.strip_prefix("ffprobe version "))?;
+    let raw = after.split_whitespace().next()?.to_string();
+
+    // Release builds look like "6.1.1", "n7.0", "4.4.2-0ubuntu0.22.04.1"
+    let numeric = raw.strip_prefix('n').unwrap_or(&raw);
+    let mut parts = numeric.split(|c: char| !c.is_ascii_digit());
+    let release = match (parts.next(), parts.next()) {
+        // Dated git builds ("2024-12-19-git-...") would otherwise parse as version 2024
+        (Some(major), Some(minor)) if major.len() < 4 && !major.is_empty() => {
+            Some((major.parse().ok()?, minor.parse().unwrap_or(0)))
+        }
+        _ => None,
+    };
+
+    // e.g. "libavformat    60. 16.100 / 60. 16.100"
+    let libavformat = lines
+        .find_map(|line| line.trim_start().strip_prefix("libavformat"))
+        .and_then(|rest| {
+            let current = rest.split('/').next()?;
+            let nums: Vec<u32> = current
+                .split('.')
+                .map(|x| x.trim().parse::<u32>())
+                .collect::<Result<_, _>>()
+                .ok()?;
+            match nums.as_slice() {
+                [major, minor, micro] => Some((*major, *minor, *micro)),
+                _ => None,
+            }
+        });
+
+    Some(FfmpegVersion {
+        raw,
+        release,
+        libavformat,
+    })
+}
+
+/// Run `ffmpeg -version` and make sure the binary exists and is new enough
+pub async fn check_ffmpeg(
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<FfmpegVersion> {
+    debug!("Running command `{} -version`", options.ffmpeg.display());
+    let mut cmd = Command::new(&options.ffmpeg);
+    cmd.arg("-version");
+    let output = run_with_limits(cmd, options.probe_timeout, cancel).await?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() {
+        bail!(
+            "`{} -version` failed: {}",
+            options.ffmpeg.display(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let version = parse_ffmpeg_version(&stdout).ok_or_else(|| {
+        eyre!(
+            "`{}` does not look like ffmpeg, `-version` printed: {}",
+            options.ffmpeg.display(),
+            stdout.lines().next().unwrap_or_default()
+        )
+    })?;
+    version.ensure_supported()?;
+    info!("Using ffmpeg {}", version.raw);
+    check_ffprobe(options, cancel).await?;
+    Ok(version)
+}
+
+/// Run `ffprobe -version` and warn if it's unusable.
+///
+/// Only closed caption detection and VobSub palettes need ffprobe, so a missing one isn't fatal.
+async fn check_ffprobe(options: &FfmpegOptions, cancel: &CancellationToken) -> eyre::Result<()> {
+    debug!("Running command `{} -version`", options.ffprobe.display());
+    let mut cmd = Command::new(&options.ffprobe);
+    cmd.arg("-version");
+    let problem = match run_with_limits(cmd, options.probe_timeout, cancel).await {
+        Err(e) if cancel.is_cancelled() => return Err(e),
+        Err(e) => e.to_string(),
+        Ok(output) if !output.status.success() => {
+            String::from_utf8_lossy(&output.stderr).to_string()
+        }
+        Ok(output) => match parse_ffmpeg_version(&String::from_utf8_lossy(&output.stdout)) {
+            Some(version) => {
+                debug!("Using ffprobe {}", version.raw);
+                return Ok(());
+            }
+            None => "`-version` doesn't look like ffprobe's".to_string(),
+        },
+    };
+    warn!(
+        "ffprobe (`{}`) is not usable, so closed captions won't be found and VobSub tracks get grey palettes: {}",
+        options.ffprobe.display(),
+        problem.trim()
+    );
+    Ok(())
+}
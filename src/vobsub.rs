@@ -0,0 +1,779 @@
+//! DVD VobSub (`.idx` + `.sub`) reading and writing.
+//!
+//! The `.idx` is a text file holding the screen size, a 16 colour RGB palette and the timestamp and
+//! file offset of every subtitle. The `.sub` is an MPEG program stream whose private stream 1
+//! packets carry subpicture units (SPUs): 2-bit interlaced RLE bitmaps plus control sequences that
+//! choose 4 of the palette colours, their transparency, the screen area and the display times.
+
+use crate::bitmap::mean_color;
+use crate::bitmap::median_cut;
+use crate::bitmap::quantize;
+use crate::find_language;
+use crate::pgs::RenderedSubtitle;
+use crate::pgs::PTS_PER_SECOND;
+use crate::run_with_limits;
+use crate::FfmpegOptions;
+use crate::LanguageTag;
+use crate::SubtitleTrack;
+use eyre::bail;
+use std::path::Path;
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+use tracing::warn;
+
+/// Program stream packs are always this size in a `.sub`
+const PACK_SIZE: usize = 2048;
+
+/// SPU control sequence delays tick at 90kHz / 1024
+const DELAY_TICKS: u64 = 1024;
+
+/// How long the last subtitle stays up when nothing says when it ends
+const DEFAULT_DURATION: u64 = 5 * PTS_PER_SECOND;
+
+/// The screen size and palette from a `.idx` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdxHeader {
+    pub width: u16,
+    pub height: u16,
+    /// RGB colours that SPUs pick from.
+    pub palette: [[u8; 3]; 16],
+    /// The language of the first `id:` line, e.g. `en`.
+    pub lang: Option<String>,
+}
+
+impl Default for IdxHeader {
+    fn default() -> Self {
+        // A grey ramp, so text stays readable when a track has no palette
+        let mut palette = [[0u8; 3]; 16];
+        for (i, color) in palette.iter_mut().enumerate() {
+            *color = [(i * 17) as u8; 3];
+        }
+        Self {
+            width: 720,
+            height: 480,
+            palette,
+            lang: None,
+        }
+    }
+}
+
+/// Parse the `size:`, `palette:` and `id:` lines of a `.idx` file (or of a DVD track's codec private data)
+pub fn parse_idx(text: &str) -> eyre::Result<IdxHeader> {
+    let mut header = IdxHeader::default();
+    let mut has_palette = false;
+    for line in text.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "size" => {
+                let (width, height) = crate::bitmap::parse_resolution(value)?;
+                header.width = width;
+                header.height = height;
+            }
+            "palette" => {
+                for (i, color) in value.split(',').take(16).enumerate() {
+                    let rgb = u32::from_str_radix(color.trim(), 16)?;
+                    header.palette[i] = [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8];
+                }
+                has_palette = true;
+            }
+            "id" if header.lang.is_none() => {
+                let lang = value.split(',').next().unwrap_or_default().trim();
+                if !lang.is_empty() && lang != "--" {
+                    header.lang = Some(lang.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    if !has_palette {
+        warn!("No palette in VobSub header, using greys");
+    }
+    Ok(header)
+}
+
+/// One subpicture unit reassembled from its program stream packets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpuPacket {
+    /// Presentation time in 90kHz ticks.
+    pub pts: u64,
+    /// Byte offset of the pack the SPU starts in, as written in the `.idx`.
+    pub filepos: usize,
+    pub data: Vec<u8>,
+}
+
+fn read_timestamp(b: &[u8]) -> u64 {
+    ((b[0] as u64 >> 1) & 0x07) << 30
+        | (b[1] as u64) << 22
+        | (b[2] as u64 >> 1) << 15
+        | (b[3] as u64) << 7
+        | (b[4] as u64 >> 1)
+}
+
+fn write_timestamp(pts: u64) -> [u8; 5] {
+    [
+        0x21 | ((pts >> 29) & 0x0E) as u8,
+        (pts >> 22) as u8,
+        ((pts >> 14) & 0xFE) as u8 | 1,
+        (pts >> 7) as u8,
+        ((pts << 1) & 0xFE) as u8 | 1,
+    ]
+}
+
+/// The payload and PTS of an MPEG-1 or MPEG-2 PES packet body
+fn pes_payload(body: &[u8]) -> Option<(Option<u64>, &[u8])> {
+    if body.first()? & 0xC0 == 0x80 {
+        // MPEG-2: flags, header length, then the optional fields
+        let flags = *body.get(1)?;
+        let header_len = *body.get(2)? as usize;
+        let pts = match flags & 0x80 {
+            0 => None,
+            _ => Some(read_timestamp(body.get(3..8)?)),
+        };
+        return Some((pts, body.get(3 + header_len..)?));
+    }
+    // MPEG-1: stuffing, an optional buffer size, then PTS/DTS
+    let mut i = 0;
+    while *body.get(i)? == 0xFF {
+        i += 1;
+    }
+    if body[i] & 0xC0 == 0x40 {
+        i += 2;
+    }
+    match body.get(i)? >> 4 {
+        0x2 => Some((
+            Some(read_timestamp(body.get(i..i + 5)?)),
+            body.get(i + 5..)?,
+        )),
+        0x3 => Some((
+            Some(read_timestamp(body.get(i..i + 5)?)),
+            body.get(i + 10..)?,
+        )),
+        _ => Some((None, body.get(i + 1..)?)),
+    }
+}
+
+/// Split a `.sub` program stream into SPUs, keeping only the first subtitle stream found
+pub fn parse_sub(data: &[u8]) -> eyre::Result<Vec<SpuPacket>> {
+    let mut packets: Vec<SpuPacket> = Vec::new();
+    let mut current: Option<SpuPacket> = None;
+    let mut stream = None;
+    let mut pack_start = 0;
+    let mut pos = 0;
+    while pos + 4 <= data.len() {
+        if data[pos..pos + 3] != [0, 0, 1] {
+            // Resynchronise on the next start code
+            pos += 1;
+            continue;
+        }
+        let code = data[pos + 3];
+        match code {
+            0xBA => {
+                pack_start = pos;
+                pos += match data.get(pos + 4) {
+                    // MPEG-2 pack header, plus stuffing
+                    Some(b) if b & 0xC0 == 0x40 => {
+                        14 + data.get(pos + 13).map(|b| (b & 0x07) as usize).unwrap_or(0)
+                    }
+                    _ => 12,
+                };
+            }
+            0xB9 => pos += 4,
+            0xBB.. => {
+                let Some(len) = data.get(pos + 4..pos + 6) else {
+                    break;
+                };
+                let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+                let body = &data[(pos + 6).min(data.len())..(pos + 6 + len).min(data.len())];
+                pos += 6 + len;
+                if code != 0xBD {
+                    continue;
+                }
+                let Some((pts, payload)) = pes_payload(body) else {
+                    continue;
+                };
+                let Some((&substream, payload)) = payload.split_first() else {
+                    continue;
+                };
+                if !(0x20..0x40).contains(&substream)
+                    || *stream.get_or_insert(substream) != substream
+                {
+                    continue;
+                }
+                match (&mut current, pts) {
+                    (Some(spu), None) => spu.data.extend_from_slice(payload),
+                    (_, Some(pts)) => {
+                        if current.is_some() {
+                            warn!("Incomplete subpicture before pts {}, dropping it", pts);
+                        }
+                        current = Some(SpuPacket {
+                            pts,
+                            filepos: pack_start,
+                            data: payload.to_vec(),
+                        });
+                    }
+                    (None, None) => debug!("Subpicture data without a start at {}", pos),
+                }
+                if let Some(spu) = &mut current {
+                    let size = match spu.data.get(..2) {
+                        Some(b) => u16::from_be_bytes([b[0], b[1]]) as usize,
+                        None => usize::MAX,
+                    };
+                    if spu.data.len() >= size {
+                        spu.data.truncate(size);
+                        packets.extend(current.take());
+                    }
+                }
+            }
+            _ => pos += 4,
+        }
+    }
+    if current.is_some() {
+        warn!("The .sub ends in the middle of a subpicture");
+    }
+    Ok(packets)
+}
+
+/// Reads the 4-bit units of SPU RLE data
+struct Nibbles<'a> {
+    data: &'a [u8],
+    /// Position in nibbles.
+    pos: usize,
+}
+
+impl Nibbles<'_> {
+    fn next(&mut self) -> u16 {
+        let byte = self.data.get(self.pos / 2).copied().unwrap_or(0);
+        let nibble = if self.pos.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0x0F
+        };
+        self.pos += 1;
+        nibble as u16
+    }
+
+    fn align(&mut self) {
+        self.pos += self.pos % 2;
+    }
+}
+
+/// Decode one line of SPU RLE into 2-bit colour slots
+fn decode_spu_line(nibbles: &mut Nibbles, line: &mut [u8]) {
+    let mut x = 0;
+    while x < line.len() {
+        // Runs are coded in 4, 8, 12 or 16 bits: a run length then a 2-bit colour
+        let mut v = nibbles.next();
+        if v < 0x4 {
+            v = v << 4 | nibbles.next();
+            if v < 0x10 {
+                v = v << 4 | nibbles.next();
+                if v < 0x40 {
+                    v = v << 4 | nibbles.next();
+                }
+            }
+        }
+        let color = (v & 0x3) as u8;
+        let run = match (v >> 2) as usize {
+            // Zero means "to the end of the line"
+            0 => line.len() - x,
+            run => run.min(line.len() - x),
+        };
+        line[x..x + run].fill(color);
+        x += run;
+    }
+    nibbles.align();
+}
+
+/// Decode one SPU shown at `pts` into an image on the `header` screen.
+///
+/// Returns `None` for SPUs that never start displaying.
+pub fn decode_spu(
+    spu: &[u8],
+    pts: u64,
+    header: &IdxHeader,
+) -> eyre::Result<Option<RenderedSubtitle>> {
+    let u16_at = |i: usize| -> eyre::Result<u16> {
+        match spu.get(i..i + 2) {
+            Some(b) => Ok(u16::from_be_bytes([b[0], b[1]])),
+            None => bail!("Subpicture at pts {} is truncated", pts),
+        }
+    };
+    let mut start = None;
+    let mut stop = None;
+    let mut forced = false;
+    let mut colors = [0u8; 4];
+    let mut alpha = [0u8; 4];
+    let mut area = None;
+    let mut fields = None;
+
+    // Walk the control sequences
+    let mut offset = u16_at(2)? as usize;
+    loop {
+        let delay = u16_at(offset)? as u64 * DELAY_TICKS;
+        let next = u16_at(offset + 2)? as usize;
+        let mut i = offset + 4;
+        loop {
+            let Some(&command) = spu.get(i) else {
+                bail!(
+                    "Subpicture at pts {} has an unterminated control sequence",
+                    pts
+                );
+            };
+            let arg_len = match command {
+                0x03 | 0x04 => 2,
+                0x05 => 6,
+                0x06 => 4,
+                _ => 0,
+            };
+            let Some(a) = spu.get(i + 1..i + 1 + arg_len) else {
+                bail!("Subpicture at pts {} is truncated", pts);
+            };
+            i += 1 + arg_len;
+            match command {
+                0x00 => {
+                    forced = true;
+                    start = start.or(Some(delay));
+                }
+                0x01 => start = start.or(Some(delay)),
+                0x02 => stop = Some(delay),
+                0x03 => colors = [a[1] & 0x0F, a[1] >> 4, a[0] & 0x0F, a[0] >> 4],
+                0x04 => alpha = [a[1] & 0x0F, a[1] >> 4, a[0] & 0x0F, a[0] >> 4],
+                0x05 => {
+                    let x1 = (a[0] as u16) << 4 | (a[1] as u16) >> 4;
+                    let x2 = (a[1] as u16 & 0x0F) << 8 | a[2] as u16;
+                    let y1 = (a[3] as u16) << 4 | (a[4] as u16) >> 4;
+                    let y2 = (a[4] as u16 & 0x0F) << 8 | a[5] as u16;
+                    area = Some((x1, y1, x2, y2));
+                }
+                0x06 => {
+                    fields = Some((
+                        u16::from_be_bytes([a[0], a[1]]) as usize,
+                        u16::from_be_bytes([a[2], a[3]]) as usize,
+                    ))
+                }
+                0xFF => break,
+                other => bail!("Unknown subpicture command 0x{:02x} at pts {}", other, pts),
+            }
+        }
+        if next <= offset {
+            break;
+        }
+        offset = next;
+    }
+
+    let (Some(start), Some((x1, y1, x2, y2)), Some((top, bottom))) = (start, area, fields) else {
+        return Ok(None);
+    };
+    if x2 < x1 || y2 < y1 {
+        return Ok(None);
+    }
+    let (width, height) = (x2 - x1 + 1, y2 - y1 + 1);
+
+    // Even lines come from the top field, odd lines from the bottom field
+    let mut slots = vec![0u8; width as usize * height as usize];
+    let mut top = Nibbles {
+        data: spu,
+        pos: top * 2,
+    };
+    let mut bottom = Nibbles {
+        data: spu,
+        pos: bottom * 2,
+    };
+    for (y, line) in slots.chunks_mut(width as usize).enumerate() {
+        let field = if y % 2 == 0 { &mut top } else { &mut bottom };
+        decode_spu_line(field, line);
+    }
+
+    let mut rgba = Vec::with_capacity(slots.len() * 4);
+    for slot in slots {
+        let [r, g, b] = header.palette[colors[slot as usize] as usize];
+        rgba.extend_from_slice(&[r, g, b, alpha[slot as usize] * 17]);
+    }
+    Ok(Some(RenderedSubtitle {
+        start: pts + start,
+        end: stop.map(|stop| pts + stop),
+        x: x1,
+        y: y1,
+        width,
+        height,
+        screen_width: header.width,
+        screen_height: header.height,
+        forced,
+        rgba,
+    }))
+}
+
+/// Decode every SPU in a `.sub` file
+pub fn decode_sub(header: &IdxHeader, data: &[u8]) -> eyre::Result<Vec<RenderedSubtitle>> {
+    let mut images = Vec::new();
+    for packet in parse_sub(data)? {
+        images.extend(decode_spu(&packet.data, packet.pts, header)?);
+    }
+    Ok(images)
+}
+
+/// Writes 4-bit units for SPU RLE data
+#[derive(Default)]
+struct NibbleWriter {
+    data: Vec<u8>,
+    half: bool,
+}
+
+impl NibbleWriter {
+    fn push(&mut self, nibble: u8) {
+        if self.half {
+            *self.data.last_mut().unwrap_or(&mut 0) |= nibble & 0x0F;
+        } else {
+            self.data.push(nibble << 4);
+        }
+        self.half = !self.half;
+    }
+
+    fn push_code(&mut self, code: u16, nibbles: u32) {
+        for i in (0..nibbles).rev() {
+            self.push((code >> (i * 4)) as u8 & 0x0F);
+        }
+    }
+
+    fn align(&mut self) {
+        self.half = false;
+    }
+}
+
+/// Encode the lines `first, first + 2, ...` of 2-bit colour slots as SPU RLE
+fn encode_spu_field(slots: &[u8], width: usize, first: usize) -> Vec<u8> {
+    let mut out = NibbleWriter::default();
+    for line in slots.chunks(width).skip(first).step_by(2) {
+        let mut x = 0;
+        while x < line.len() {
+            let color = line[x];
+            let mut run = 1;
+            while x + run < line.len() && line[x + run] == color {
+                run += 1;
+            }
+            x += run;
+            if x == line.len() && run > 255 {
+                // Fill to the end of the line
+                out.push_code(color as u16, 4);
+                continue;
+            }
+            while run > 0 {
+                let chunk = run.min(255);
+                let code = (chunk as u16) << 2 | color as u16;
+                let nibbles = match chunk {
+                    0..4 => 1,
+                    4..16 => 2,
+                    16..64 => 3,
+                    _ => 4,
+                };
+                out.push_code(code, nibbles);
+                run -= chunk;
+            }
+        }
+        out.align();
+    }
+    out.data
+}
+
+/// Build an SPU showing `slots` (one 2-bit colour slot per pixel) for `duration` ticks
+fn encode_spu(
+    image: &RenderedSubtitle,
+    slots: &[u8],
+    colors: [u8; 4],
+    alpha: [u8; 4],
+    duration: u64,
+) -> eyre::Result<Vec<u8>> {
+    if image.width == 0 || image.height == 0 {
+        bail!(
+            "Subtitle at {} is an empty {}x{} image",
+            crate::pgs::format_pts(image.start),
+            image.width,
+            image.height
+        );
+    }
+    let width = image.width as usize;
+    let mut spu = vec![0u8; 4];
+    let top = spu.len();
+    spu.extend(encode_spu_field(slots, width, 0));
+    let bottom = spu.len();
+    spu.extend(encode_spu_field(slots, width, 1));
+
+    let first = spu.len();
+    // The display control sequence is 24 bytes and the stop sequence 6
+    let second = first + 24;
+    if second + 6 > u16::MAX as usize {
+        bail!(
+            "Subtitle at {} is too detailed for VobSub ({} bytes), try a smaller --resolution",
+            crate::pgs::format_pts(image.start),
+            second + 6
+        );
+    }
+    let (x1, y1) = (image.x as u32, image.y as u32);
+    let (x2, y2) = (x1 + image.width as u32 - 1, y1 + image.height as u32 - 1);
+    // Coordinates are 12 bits
+    if x2 > 0xFFF || y2 > 0xFFF {
+        bail!(
+            "Subtitle at {} reaches ({x2}, {y2}), past the largest VobSub position of 4095",
+            crate::pgs::format_pts(image.start)
+        );
+    }
+    let nibble_pair = |v: [u8; 4]| [v[3] << 4 | v[2], v[1] << 4 | v[0]];
+    spu.extend_from_slice(&0u16.to_be_bytes());
+    spu.extend_from_slice(&(second as u16).to_be_bytes());
+    spu.push(if image.forced { 0x00 } else { 0x01 });
+    spu.push(0x03);
+    spu.extend_from_slice(&nibble_pair(colors));
+    spu.push(0x04);
+    spu.extend_from_slice(&nibble_pair(alpha));
+    spu.extend_from_slice(&[
+        0x05,
+        (x1 >> 4) as u8,
+        ((x1 & 0x0F) << 4) as u8 | (x2 >> 8) as u8,
+        x2 as u8,
+        (y1 >> 4) as u8,
+        ((y1 & 0x0F) << 4) as u8 | (y2 >> 8) as u8,
+        y2 as u8,
+    ]);
+    spu.push(0x06);
+    spu.extend_from_slice(&(top as u16).to_be_bytes());
+    spu.extend_from_slice(&(bottom as u16).to_be_bytes());
+    spu.push(0xFF);
+
+    // The stop sequence points at itself to end the chain
+    let delay = (duration / DELAY_TICKS).min(u16::MAX as u64) as u16;
+    spu.extend_from_slice(&delay.to_be_bytes());
+    spu.extend_from_slice(&(second as u16).to_be_bytes());
+    spu.extend_from_slice(&[0x02, 0xFF]);
+
+    let size = (spu.len() as u16).to_be_bytes();
+    spu[0..2].copy_from_slice(&size);
+    spu[2..4].copy_from_slice(&(first as u16).to_be_bytes());
+    Ok(spu)
+}
+
+/// Append an SPU to a program stream as 2048-byte packs of private stream 1
+fn write_spu_packs(out: &mut Vec<u8>, pts: u64, spu: &[u8]) {
+    let mut rest = spu;
+    let mut first = true;
+    while !rest.is_empty() {
+        // MPEG-2 pack header with the SCR set to the PTS
+        out.extend_from_slice(&[0, 0, 1, 0xBA]);
+        out.extend_from_slice(&[
+            0x44 | ((pts >> 27) & 0x38) as u8 | ((pts >> 28) & 0x03) as u8,
+            (pts >> 20) as u8,
+            0x04 | ((pts >> 12) & 0xF8) as u8 | ((pts >> 13) & 0x03) as u8,
+            (pts >> 5) as u8,
+            0x04 | ((pts << 3) & 0xF8) as u8,
+            0x01,
+        ]);
+        out.extend_from_slice(&[0x01, 0x89, 0xC3, 0xF8]);
+
+        let pes_header = if first { 15 } else { 10 };
+        let room = PACK_SIZE - 14 - pes_header;
+        let take = rest.len().min(room);
+        let leftover = room - take;
+        // Small gaps are stuffed inside the PES header, larger ones get a padding packet
+        let stuffing = if leftover < 6 { leftover } else { 0 };
+        let header_len = if first { 5 } else { 0 } + stuffing;
+        let pes_len = 3 + header_len + 1 + take;
+        out.extend_from_slice(&[0, 0, 1, 0xBD]);
+        out.extend_from_slice(&(pes_len as u16).to_be_bytes());
+        out.extend_from_slice(&[0x81, if first { 0x80 } else { 0x00 }, header_len as u8]);
+        if first {
+            out.extend_from_slice(&write_timestamp(pts));
+        }
+        out.extend(std::iter::repeat_n(0xFF, stuffing));
+        out.push(0x20);
+        out.extend_from_slice(&rest[..take]);
+        if leftover >= 6 {
+            out.extend_from_slice(&[0, 0, 1, 0xBE]);
+            out.extend_from_slice(&((leftover - 6) as u16).to_be_bytes());
+            out.extend(std::iter::repeat_n(0xFF, leftover - 6));
+        }
+        rest = &rest[take..];
+        first = false;
+    }
+}
+
+/// Format a 90kHz timestamp the way `.idx` files do, `HH:MM:SS:mmm`
+fn format_idx_timestamp(pts: u64) -> String {
+    let ms = pts * 1000 / PTS_PER_SECOND;
+    format!(
+        "{:02}:{:02}:{:02}:{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Write the `.idx` text for SPUs already in a `.sub`
+pub fn write_idx(header: &IdxHeader, packets: &[SpuPacket], lang: Option<&str>) -> String {
+    let palette = header
+        .palette
+        .iter()
+        .map(|[r, g, b]| format!("{r:02x}{g:02x}{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut idx = String::from("# VobSub index file, v7 (do not modify this line!)\n");
+    idx.push_str(&format!("size: {}x{}\n", header.width, header.height));
+    idx.push_str(&format!("palette: {palette}\n\n"));
+    idx.push_str("langidx: 0\n");
+    // The `id:` line takes a two-letter code, so `eng` and `en-US` are both written as `en`
+    let id = lang.or(header.lang.as_deref()).map(|lang| {
+        match find_language(lang).and_then(|x| x.alpha2) {
+            Some(alpha2) => alpha2.to_string(),
+            None => LanguageTag::parse(lang).primary,
+        }
+    });
+    idx.push_str(&format!(
+        "id: {}, index: 0\n",
+        id.as_deref().unwrap_or("--")
+    ));
+    for packet in packets {
+        idx.push_str(&format!(
+            "timestamp: {}, filepos: {:09x}\n",
+            format_idx_timestamp(packet.pts),
+            packet.filepos
+        ));
+    }
+    idx
+}
+
+/// Encode images as VobSub, returning the `.idx` text and the `.sub` bytes.
+///
+/// Every image is reduced to transparency plus 3 colours, and the colours of all images share one
+/// 16 colour palette.
+pub fn encode_vobsub(
+    images: &[RenderedSubtitle],
+    lang: Option<&str>,
+) -> eyre::Result<(String, Vec<u8>)> {
+    // Reduce each image to 4 colour slots, slot 0 being transparent
+    let quantized: Vec<(Vec<[u8; 4]>, Vec<u8>)> = images
+        .iter()
+        .map(|image| quantize(&image.rgba, 4))
+        .collect();
+
+    // Build the shared palette from every slot colour, weighted by how many pixels use it
+    let mut weighted: Vec<([u8; 4], u32)> = Vec::new();
+    for (colors, slots) in &quantized {
+        for (slot, color) in colors.iter().enumerate().skip(1) {
+            let count = slots.iter().filter(|&&s| s as usize == slot).count() as u32;
+            weighted.push(([color[0], color[1], color[2], 255], count.max(1)));
+        }
+    }
+    let mut header = IdxHeader {
+        width: images.first().map(|i| i.screen_width).unwrap_or(720),
+        height: images.first().map(|i| i.screen_height).unwrap_or(480),
+        lang: lang.map(str::to_string),
+        ..Default::default()
+    };
+    for (i, group) in median_cut(&weighted, 16).iter().enumerate() {
+        let [r, g, b, _] = mean_color(&weighted, group);
+        header.palette[i] = [r, g, b];
+    }
+    let nearest = |color: [u8; 4]| {
+        (0..16)
+            .min_by_key(|&i| {
+                let p = header.palette[i];
+                (0..3)
+                    .map(|c| (p[c] as i32 - color[c] as i32).pow(2))
+                    .sum::<i32>()
+            })
+            .unwrap_or(0) as u8
+    };
+
+    let mut sub = Vec::new();
+    let mut packets = Vec::new();
+    for (i, (image, (colors, slots))) in images.iter().zip(&quantized).enumerate() {
+        let mut slot_colors = [0u8; 4];
+        let mut slot_alpha = [0u8; 4];
+        for (slot, color) in colors.iter().enumerate().skip(1) {
+            slot_colors[slot] = nearest(*color);
+            slot_alpha[slot] = ((color[3] as u32 + 8) / 17) as u8;
+        }
+        // An image without an end lasts until the next one, or a few seconds if it is the last
+        let end = image
+            .end
+            .or_else(|| images.get(i + 1).map(|next| next.start))
+            .unwrap_or(image.start + DEFAULT_DURATION);
+        let spu = encode_spu(
+            image,
+            slots,
+            slot_colors,
+            slot_alpha,
+            end.saturating_sub(image.start),
+        )?;
+        packets.push(SpuPacket {
+            pts: image.start,
+            filepos: sub.len(),
+            data: Vec::new(),
+        });
+        write_spu_packs(&mut sub, image.start, &spu);
+    }
+    Ok((write_idx(&header, &packets, lang), sub))
+}
+
+/// Parse the extradata hex dump printed by `ffprobe -show_streams -show_data`
+pub fn parse_extradata_dump(output: &str) -> Option<Vec<u8>> {
+    let mut lines = output
+        .lines()
+        .skip_while(|line| !line.starts_with("extradata="));
+    lines.next()?;
+    let mut data = Vec::new();
+    for line in lines {
+        // e.g. "00000000: 7369 7a65 3a20 3732 3078 3438 300a 6f72  size: 720x480.or"
+        let Some(rest) = line
+            .get(10..)
+            .filter(|_| line.as_bytes().get(8) == Some(&b':'))
+        else {
+            break;
+        };
+        let hex: String = rest
+            .split("  ")
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+        for pair in hex.as_bytes().chunks(2) {
+            data.push(u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?);
+        }
+    }
+    (!data.is_empty()).then_some(data)
+}
+
+/// Read the `.idx` header (size and palette) that Matroska stores as a DVD track's codec private data
+pub async fn probe_idx_header(
+    path: &Path,
+    track: &SubtitleTrack,
+    options: &FfmpegOptions,
+    cancel: &CancellationToken,
+) -> eyre::Result<IdxHeader> {
+    // Example: ffprobe -v error -select_streams s:2 -show_streams -show_data input.mkv
+    let mut cmd = Command::new(&options.ffprobe);
+    cmd.args(["-v", "error", "-select_streams"]);
+    cmd.arg(format!("s:{}", track.stream_index));
+    cmd.args(["-show_streams", "-show_data"]);
+    cmd.arg(path);
+    let output = run_with_limits(cmd, options.probe_timeout, cancel).await?;
+    if !output.status.success() {
+        bail!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    debug!("stdout: {}", stdout);
+    match parse_extradata_dump(&stdout) {
+        Some(extradata) => parse_idx(&String::from_utf8_lossy(&extradata)),
+        None => {
+            warn!("{} has no VobSub palette, using greys", track);
+            Ok(IdxHeader::default())
+        }
+    }
+}
@@ -0,0 +1,10 @@
+use mkv_subtitle_extractor::Cue;
+use std::time::Duration;
+
+pub fn cue(start_ms: u64, end_ms: u64, text: &str) -> Cue {
+    Cue {
+        start: Duration::from_millis(start_ms),
+        end: Duration::from_millis(end_ms),
+        text: text.to_string(),
+    }
+}
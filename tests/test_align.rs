@@ -0,0 +1,209 @@
+mod common;
+
+use common::cue;
+use mkv_subtitle_extractor::cue_intervals;
+use mkv_subtitle_extractor::detect_speech;
+use mkv_subtitle_extractor::envelope_offset;
+use mkv_subtitle_extractor::find_offset;
+use mkv_subtitle_extractor::find_retiming;
+use mkv_subtitle_extractor::format_ass_timestamp;
+use mkv_subtitle_extractor::parse_ass;
+use mkv_subtitle_extractor::parse_subtitle_tracks;
+use mkv_subtitle_extractor::plan_transplant;
+use mkv_subtitle_extractor::retime_ass;
+use mkv_subtitle_extractor::retime_srt;
+use mkv_subtitle_extractor::Cue;
+use mkv_subtitle_extractor::FfmpegOptions;
+use mkv_subtitle_extractor::Retiming;
+use std::path::Path;
+use std::time::Duration;
+
+/// Irregularly spaced cues, so only one offset lines them up
+fn cues() -> Vec<Cue> {
+    let mut t = 5_000;
+    (0..60u64)
+        .map(|i| {
+            let length = 800 + (i * 7919) % 2500;
+            let gap = 300 + (i * 104_729) % 4000;
+            let cue = cue(t, t + length, "line");
+            t += length + gap;
+            cue
+        })
+        .collect()
+}
+
+#[test]
+fn test_find_offset() {
+    let reference = cues();
+    let shifted = Retiming {
+        offset_ms: -3_450,
+        scale: 1.0,
+    }
+    .apply_cues(&reference);
+    let alignment = find_offset(&cue_intervals(&reference), &cue_intervals(&shifted), 60_000);
+    assert_eq!(alignment.retiming.offset_ms, 3_450);
+    assert!(alignment.score > 0.99);
+    assert_eq!(alignment.retiming.to_string(), "+3.450s");
+}
+
+#[test]
+fn test_retiming_apply_cues() {
+    let retiming = Retiming {
+        offset_ms: -1_000,
+        scale: 2.0,
+    };
+    // Ends before zero once retimed, so it's dropped
+    let cues = retiming.apply_cues(&[
+        cue(100, 400, "line"),
+        cue(400, 1_000, "line"),
+        cue(2_000, 2_500, "line"),
+    ]);
+    assert_eq!(cues, vec![cue(0, 1_000, "line"), cue(3_000, 4_000, "line")]);
+}
+
+#[test]
+fn test_envelope_offset() {
+    // Bursts of loudness of pseudo-random length, like speech
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut reference = Vec::new();
+    let mut loud = false;
+    while reference.len() < 6_000 {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        loud = !loud;
+        let loudness = if loud { 0.3 } else { 0.01 };
+        reference.extend(std::iter::repeat_n(loudness, 20 + (state % 150) as usize));
+    }
+    // The candidate starts 2.5s (250 frames) into the reference
+    let candidate = reference[250..].to_vec();
+    let (offset_ms, score) = envelope_offset(&reference, &candidate, 60_000);
+    assert_eq!(offset_ms, 2_500);
+    assert!(score > 0.9);
+}
+
+#[test]
+fn test_plan_transplant() -> eyre::Result<()> {
+    let tracks = parse_subtitle_tracks(
+        "  Stream #0:2(eng): Subtitle: subrip\n  Stream #0:3(jpn): Subtitle: ass",
+    )?;
+    let plan = plan_transplant(
+        Path::new("new.mkv"),
+        Path::new("old.mkv"),
+        &[&tracks[0], &tracks[1]],
+        -1_500,
+        Path::new("new.transplanted.mkv"),
+        &FfmpegOptions::default(),
+    );
+    assert_eq!(
+        plan.args,
+        [
+            "-y",
+            "-i",
+            "./new.mkv",
+            "-itsoffset",
+            "-1.500",
+            "-i",
+            "./old.mkv",
+            "-map",
+            "0",
+            "-map",
+            "1:s:0",
+            "-map",
+            "1:s:1",
+            "-map",
+            "1:t?",
+            "-c",
+            "copy",
+            "-f",
+            "matroska",
+            "./new.transplanted.tmp.mkv",
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_find_retiming_with_drift() {
+    let reference = cues();
+    // Timed for 23.976 fps but played at 25, and 2s late
+    let drifted = Retiming {
+        offset_ms: 2_000,
+        scale: 25.0 / (24000.0 / 1001.0),
+    }
+    .apply_cues(&reference);
+    let (reference, drifted) = (cue_intervals(&reference), cue_intervals(&drifted));
+    assert!(find_retiming(&reference, &drifted, 60_000, false).score < 0.9);
+    let alignment = find_retiming(&reference, &drifted, 60_000, true);
+    assert!(alignment.score > 0.98, "{alignment:?}");
+    assert!((alignment.retiming.scale - (24000.0 / 1001.0) / 25.0).abs() < 1e-4);
+}
+
+#[test]
+fn test_detect_speech() {
+    let mut envelope = vec![0.001; 100];
+    envelope.extend(vec![0.2; 150]);
+    // A pause between words is bridged
+    envelope.extend(vec![0.001; 10]);
+    envelope.extend(vec![0.2; 100]);
+    envelope.extend(vec![0.001; 200]);
+    // A bang is too short to be speech
+    envelope.extend(vec![0.5; 5]);
+    envelope.extend(vec![0.001; 100]);
+    let speech = detect_speech(&envelope);
+    assert_eq!(speech.len(), 1);
+    let (start, end) = speech[0];
+    assert!(
+        (start - 1_000).abs() <= 30 && (end - 3_600).abs() <= 30,
+        "{speech:?}"
+    );
+}
+
+#[test]
+fn test_retime_text() {
+    let retiming = Retiming {
+        offset_ms: 1_250,
+        scale: 1.0,
+    };
+    // "Café" in cp1252, which isn't UTF-8 and must come through untouched
+    let srt = b"1\r\n00:00:01,000 --> 00:00:02,500 X1:10\r\n<i>Caf\xe9</i>\r\n\r\n";
+    assert_eq!(
+        retime_srt(srt, &retiming),
+        b"1\r\n00:00:02,250 --> 00:00:03,750 X1:10\r\n<i>Caf\xe9</i>\r\n\r\n"
+    );
+    // The prefix keeps its spacing
+    let ass = b"[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\nDialogue:0,0:00:01.00,0:00:02.50,Default,,0,0,0,,Caf\xe9, {\\i1}there\n";
+    assert_eq!(
+        retime_ass(ass, &retiming),
+        b"[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\nDialogue:0,0:00:02.25,0:00:03.75,Default,,0,0,0,,Caf\xe9, {\\i1}there\n"
+    );
+}
+
+#[test]
+fn test_align_translation() {
+    let reference = cues();
+    // A translation in ASS that splits each line in two, timed 4.2s later
+    let mut ass = "[Events]\n".to_string();
+    for cue in &reference {
+        let start = cue.start.as_millis() as u64 + 4_200;
+        let end = cue.end.as_millis() as u64 + 4_200;
+        let middle = (start + end) / 2;
+        for (from, to) in [(start, middle), (middle, end)] {
+            ass.push_str(&format!(
+                "Dialogue: 0,{},{},Default,,0,0,0,,Ligne\n",
+                format_ass_timestamp(Duration::from_millis(from)),
+                format_ass_timestamp(Duration::from_millis(to))
+            ));
+        }
+    }
+    let translation = parse_ass(&ass);
+    assert_eq!(translation.len(), reference.len() * 2);
+    let alignment = find_retiming(
+        &cue_intervals(&reference),
+        &cue_intervals(&translation),
+        60_000,
+        true,
+    );
+    assert_eq!(alignment.retiming.scale, 1.0);
+    assert!((alignment.retiming.offset_ms + 4_200).abs() <= 10);
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use common::cue;
+use mkv_subtitle_extractor::audio_clip_args;
+use mkv_subtitle_extractor::plan_anki_cards;
+use mkv_subtitle_extractor::screenshot_args;
+use mkv_subtitle_extractor::write_anki_csv;
+use mkv_subtitle_extractor::AnkiOptions;
+use mkv_subtitle_extractor::ClipOptions;
+use std::path::Path;
+use std::time::Duration;
+
+#[test]
+fn test_plan_anki_cards() {
+    let front = [
+        cue(62_345, 64_000, "<i>\"Wait!\"</i>\nPlease."),
+        cue(65_000, 66_000, " "),
+    ];
+    let back = [cue(62_400, 64_100, "待って！")];
+    let anki_options = AnkiOptions {
+        deck: "Movie".to_string(),
+        screenshots: true,
+        ..Default::default()
+    };
+    let (cards, clips) = plan_anki_cards(
+        "[Group] My Movie - 01 [1080p]",
+        &front,
+        &back,
+        Path::new("out/media"),
+        &anki_options,
+    );
+    // The blank cue makes no card
+    assert_eq!(cards.len(), 1);
+    assert_eq!(
+        clips[0].audio.as_deref(),
+        // Brackets would end Anki's `[sound:...]` tag early
+        Some(Path::new(
+            "out/media/_Group_ My Movie - 01 _1080p_.0001.00-01-02.345.m4a"
+        ))
+    );
+    assert_eq!(clips[0].end, Duration::from_millis(64_100));
+    assert_eq!(
+        write_anki_csv("Movie", &cards),
+        "#separator:Comma\n#html:true\n#notetype:Basic\n#deck:Movie\n#tags column:3\n\
+         \"<i>\"\"Wait!\"\"</i><br>Please.<br>[sound:_Group_ My Movie - 01 _1080p_.0001.00-01-02.345.m4a]\",\
+         \"待って！<br><img src=\"\"_Group_ My Movie - 01 _1080p_.0001.00-01-02.345.jpg\"\">\",\
+         \"[Group]_My_Movie_-_01_[1080p]\"\n"
+    );
+}
+
+#[test]
+fn test_clip_args() {
+    let clip_options = ClipOptions {
+        screenshot_height: Some(480),
+        ..Default::default()
+    };
+    let start = Duration::from_millis(62_345);
+    let end = Duration::from_millis(64_000);
+    assert_eq!(
+        audio_clip_args(
+            Path::new("Movie.mkv"),
+            start,
+            end,
+            Path::new("a.m4a"),
+            &clip_options
+        ),
+        [
+            "-v",
+            "error",
+            "-ss",
+            "62.095",
+            "-i",
+            "./Movie.mkv",
+            "-t",
+            "2.155",
+            "-map",
+            "0:a:0",
+            "-c:a",
+            "aac",
+            "-b:a",
+            "128k",
+            "./a.m4a",
+        ]
+    );
+    assert_eq!(
+        screenshot_args(
+            Path::new("Movie.mkv"),
+            start,
+            Path::new("a.jpg"),
+            &clip_options
+        ),
+        [
+            "-v",
+            "error",
+            "-ss",
+            "62.345",
+            "-i",
+            "./Movie.mkv",
+            "-map",
+            "0:v:0",
+            "-frames:v",
+            "1",
+            "-vf",
+            "scale=-2:480",
+            "-q:v",
+            "3",
+            "./a.jpg",
+        ]
+    );
+}
@@ -0,0 +1,126 @@
+use mkv_subtitle_extractor::bitmap::quantize;
+use mkv_subtitle_extractor::bitmap::resize_subtitle;
+use mkv_subtitle_extractor::pgs::encode_display_sets;
+use mkv_subtitle_extractor::pgs::parse_display_sets;
+use mkv_subtitle_extractor::pgs::render_display_sets;
+use mkv_subtitle_extractor::pgs::write_display_sets;
+use mkv_subtitle_extractor::pgs::RenderedSubtitle;
+use mkv_subtitle_extractor::vobsub::decode_sub;
+use mkv_subtitle_extractor::vobsub::encode_vobsub;
+use mkv_subtitle_extractor::vobsub::parse_extradata_dump;
+use mkv_subtitle_extractor::vobsub::parse_idx;
+
+/// A 300x3 image: a white bar with a black outline row, then a transparent row
+fn sample_image() -> RenderedSubtitle {
+    let mut rgba = Vec::new();
+    for row in 0..3 {
+        for _ in 0..300 {
+            rgba.extend_from_slice(match row {
+                0 => &[255, 255, 255, 255],
+                1 => &[0, 0, 0, 255],
+                _ => &[0, 0, 0, 0],
+            });
+        }
+    }
+    RenderedSubtitle {
+        start: 90_000,
+        end: Some(270_000),
+        x: 100,
+        y: 400,
+        width: 300,
+        height: 3,
+        screen_width: 720,
+        screen_height: 480,
+        forced: true,
+        rgba,
+    }
+}
+
+#[test]
+fn test_vobsub_round_trip() -> eyre::Result<()> {
+    let image = sample_image();
+    let (idx, sub) = encode_vobsub(std::slice::from_ref(&image), Some("en"))?;
+    assert_eq!(sub.len() % 2048, 0);
+    assert!(idx.contains("size: 720x480"));
+    assert!(idx.contains("id: en, index: 0"));
+    assert!(idx.contains("timestamp: 00:00:01:000, filepos: 000000000"));
+
+    let header = parse_idx(&idx)?;
+    let images = decode_sub(&header, &sub)?;
+    assert_eq!(images.len(), 1);
+    let decoded = &images[0];
+    assert_eq!(
+        (decoded.x, decoded.y, decoded.width, decoded.height),
+        (100, 400, 300, 3)
+    );
+    assert_eq!(decoded.start, 90_000);
+    // The end is rounded down to the SPU delay resolution
+    assert!(decoded.end.is_some_and(|end| 270_000 - end < 1024));
+    assert!(decoded.forced);
+    assert_eq!(decoded.rgba, image.rgba);
+    Ok(())
+}
+
+#[test]
+fn test_vobsub_rejects_unencodable_images() {
+    let empty = RenderedSubtitle {
+        width: 0,
+        height: 0,
+        rgba: Vec::new(),
+        ..sample_image()
+    };
+    assert!(encode_vobsub(&[empty], None).is_err());
+    // Positions are 12 bits
+    let offscreen = RenderedSubtitle {
+        x: 4000,
+        ..sample_image()
+    };
+    assert!(encode_vobsub(&[offscreen], None).is_err());
+}
+
+#[test]
+fn test_pgs_round_trip() -> eyre::Result<()> {
+    let image = sample_image();
+    let sup = write_display_sets(&encode_display_sets(std::slice::from_ref(&image)));
+    let sets = parse_display_sets(&sup)?;
+    // An epoch start showing the image, then a clearing display set
+    assert_eq!(sets.len(), 2);
+    let images = render_display_sets(&sets)?;
+    assert_eq!(images.len(), 1);
+    assert_eq!(images[0].end, Some(270_000));
+    assert!(images[0].forced);
+    for (a, b) in images[0].rgba.iter().zip(&image.rgba) {
+        // YCbCr round trips are off by a little
+        assert!(a.abs_diff(*b) <= 2);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_quantize_and_resize() {
+    let image = sample_image();
+    let (palette, indices) = quantize(&image.rgba, 4);
+    assert_eq!(palette.len(), 3);
+    assert_eq!(palette[0], [0, 0, 0, 0]);
+    assert_eq!(indices[600], 0);
+
+    let resized = resize_subtitle(&image, 1440, 960);
+    assert_eq!(
+        (resized.x, resized.y, resized.width, resized.height),
+        (200, 800, 600, 6)
+    );
+    assert_eq!(&resized.rgba[0..4], &[255, 255, 255, 255]);
+}
+
+#[test]
+fn test_parse_extradata_dump() -> eyre::Result<()> {
+    let output = "[STREAM]\nindex=2\nextradata=\n\
+        00000000: 7369 7a65 3a20 3732 3078 3438 300a 7061  size: 720x480.pa\n\
+        00000010: 6c65 7474 653a 2030 3030 3030 30         lette: 000000\n\
+        extradata_size=29\n";
+    let extradata = parse_extradata_dump(output).unwrap();
+    let header = parse_idx(&String::from_utf8(extradata)?)?;
+    assert_eq!((header.width, header.height), (720, 480));
+    assert_eq!(header.palette[0], [0, 0, 0]);
+    Ok(())
+}
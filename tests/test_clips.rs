@@ -0,0 +1,53 @@
+mod common;
+
+use common::cue;
+use mkv_subtitle_extractor::plan_cue_clips;
+use mkv_subtitle_extractor::write_clip_index;
+use mkv_subtitle_extractor::CueClipOptions;
+use regex::RegexBuilder;
+use std::path::Path;
+
+#[test]
+fn test_plan_cue_clips() {
+    let cues = [
+        cue(1_000, 2_000, "Hello there."),
+        cue(62_345, 64_000, "<i>\"Wait!\"</i>\nPlease."),
+        cue(65_000, 66_000, "Goodbye."),
+    ];
+    let cue_clip_options = CueClipOptions {
+        // Matched against the text without markup
+        filter: Some(
+            RegexBuilder::new("^\"wait")
+                .case_insensitive(true)
+                .build()
+                .unwrap(),
+        ),
+        screenshots: true,
+        ..Default::default()
+    };
+    let clips = plan_cue_clips("Movie", &cues, Path::new("out"), &cue_clip_options);
+    assert_eq!(clips.len(), 1);
+    // Numbered by position in the whole track, not among the matches
+    assert_eq!(clips[0].index, 2);
+    assert_eq!(clips[0].request.audio, None);
+    assert_eq!(
+        clips[0].request.screenshot.as_deref(),
+        Some(Path::new("out/Movie.0002.00-01-02.345.jpg"))
+    );
+    assert_eq!(
+        write_clip_index(&clips),
+        "index,start,end,audio,screenshot,text\n\
+         \"2\",\"00:01:02,345\",\"00:01:04,000\",\"\",\"Movie.0002.00-01-02.345.jpg\",\"\"\"Wait!\"\" Please.\"\n"
+    );
+
+    let all = CueClipOptions {
+        audio: true,
+        ..Default::default()
+    };
+    let clips = plan_cue_clips("Movie", &cues, Path::new("out"), &all);
+    assert_eq!(clips.len(), 3);
+    assert_eq!(
+        clips[2].request.audio.as_deref(),
+        Some(Path::new("out/Movie.0003.00-01-05.000.m4a"))
+    );
+}
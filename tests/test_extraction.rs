@@ -7,14 +7,20 @@ use std::path::PathBuf;
 // Adjust the path/imports as needed for your project structure.
 use mkv_subtitle_extractor::enumerate_subtitle_tracks;
 use mkv_subtitle_extractor::extract_subtitle_track;
+use mkv_subtitle_extractor::CancellationToken;
+use mkv_subtitle_extractor::FfmpegOptions;
+use mkv_subtitle_extractor::OutputOptions;
 
 #[tokio::test]
 async fn test_extract_subtitles_and_compare() -> Result<()> {
     // 1) Our target test file with two embedded subtitle streams: one SRT and one ASS
     let mkv_path = PathBuf::from("resources/output_with_subs.mkv");
+    let options = FfmpegOptions::default();
+    let output = OutputOptions::default();
+    let cancel = CancellationToken::new();
 
     // 2) Enumerate all subtitle tracks using your function
-    let tracks = enumerate_subtitle_tracks(&mkv_path).await?;
+    let tracks = enumerate_subtitle_tracks(&mkv_path, &options, &cancel).await?;
     println!("Found tracks: {tracks:#?}");
 
     // 3) We expect two tracks: one subrip, one ass
@@ -33,15 +39,17 @@ async fn test_extract_subtitles_and_compare() -> Result<()> {
     let _ = tokio::fs::remove_file("resources/output_with_subs.1.ass").await;
 
     // 4) Extract the subrip track
-    let extracted_srt = extract_subtitle_track(&mkv_path, subrip_track)
+    let extracted_srt = extract_subtitle_track(&mkv_path, subrip_track, &options, &output, &cancel)
         .await?
-        .ok_or_else(|| eyre::eyre!("Skipping SRT track extraction"))?;
+        .ok_or_else(|| eyre::eyre!("Skipping SRT track extraction"))?
+        .output_path;
     println!("Extracted SRT to: {}", extracted_srt.display());
 
     // 5) Extract the ASS track
-    let extracted_ass = extract_subtitle_track(&mkv_path, ass_track)
+    let extracted_ass = extract_subtitle_track(&mkv_path, ass_track, &options, &output, &cancel)
         .await?
-        .ok_or_else(|| eyre::eyre!("Skipping ASS track extraction"))?;
+        .ok_or_else(|| eyre::eyre!("Skipping ASS track extraction"))?
+        .output_path;
     println!("Extracted ASS to: {}", extracted_ass.display());
 
     // 6) Compare extracted files to your reference `test.srt` and `test.ass`.
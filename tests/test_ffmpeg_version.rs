@@ -0,0 +1,35 @@
+use mkv_subtitle_extractor::parse_ffmpeg_version;
+
+#[test]
+fn test_parse_release_build() {
+    let output = "ffmpeg version 6.1.1-3ubuntu5 Copyright (c) 2000-2023 the FFmpeg developers
+built with gcc 13 (Ubuntu 13.2.0-23ubuntu3)
+libavutil      58. 29.100 / 58. 29.100
+libavformat    60. 16.100 / 60. 16.100
+";
+    let version = parse_ffmpeg_version(output).expect("should parse");
+    assert_eq!(version.raw, "6.1.1-3ubuntu5");
+    assert_eq!(version.release, Some((6, 1)));
+    assert_eq!(version.libavformat, Some((60, 16, 100)));
+    assert!(version.ensure_supported().is_ok());
+}
+
+#[test]
+fn test_parse_dated_git_build() {
+    let output = "ffmpeg version 2024-12-19-git-494c961379-full_build-www.gyan.dev Copyright (c) 2000-2024 the FFmpeg developers
+libavformat    61.  9.100 / 61.  9.100
+";
+    let version = parse_ffmpeg_version(output).expect("should parse");
+    assert_eq!(version.release, None);
+    assert_eq!(version.libavformat, Some((61, 9, 100)));
+}
+
+#[test]
+fn test_reject_old_build() {
+    let output = "ffmpeg version 3.4.8 Copyright (c) 2000-2020 the FFmpeg developers
+libavformat    57. 83.100 / 57. 83.100
+";
+    let version = parse_ffmpeg_version(output).expect("should parse");
+    assert!(version.ensure_supported().is_err());
+    assert!(parse_ffmpeg_version("not ffmpeg at all").is_none());
+}
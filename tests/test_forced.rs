@@ -0,0 +1,102 @@
+mod common;
+
+use common::cue;
+use mkv_subtitle_extractor::flag_forced_subsets;
+use mkv_subtitle_extractor::is_forced_subset;
+use mkv_subtitle_extractor::parse_srt;
+use mkv_subtitle_extractor::Cue;
+use mkv_subtitle_extractor::SubtitleTrack;
+use std::time::Duration;
+
+fn track(index: u32, lang: &str) -> SubtitleTrack {
+    SubtitleTrack {
+        stream_index: index,
+        lang: Some(lang.to_string()),
+        format: "subrip".to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_parse_srt() {
+    let srt = "\u{feff}1\r\n00:00:01,000 --> 00:00:02,500\r\nHello\r\nthere\r\n\r\n\
+        2\r\n00:01:00.250 --> 00:01:01,000 X1:10 X2:20\r\n<i>Bye</i>\r\n\r\n\
+        3\r\nnot a timing line\r\n";
+    let cues = parse_srt(srt);
+    assert_eq!(cues.len(), 2);
+    assert_eq!(cues[0].start, Duration::from_millis(1000));
+    assert_eq!(cues[0].end, Duration::from_millis(2500));
+    assert_eq!(cues[0].text, "Hello\nthere");
+    assert_eq!(cues[1].start, Duration::from_millis(60_250));
+    assert_eq!(cues[1].text, "<i>Bye</i>");
+}
+
+#[test]
+fn test_flag_forced_subsets() {
+    let full: Vec<Cue> = (0..20)
+        .map(|i| cue(i * 10_000, i * 10_000 + 3_000, ""))
+        .collect();
+    // Two of the full track's lines, e.g. signs in a foreign language
+    let forced = vec![cue(50_000, 53_000, ""), cue(120_000, 123_000, "")];
+    // Same count, but at unrelated times
+    let unrelated = vec![cue(5_000, 7_000, ""), cue(15_000, 17_000, "")];
+
+    let mut tracks = vec![
+        track(0, "eng"),
+        track(1, "eng"),
+        track(2, "eng"),
+        track(3, "fre"),
+    ];
+    let cues = vec![
+        Some(full.clone()),
+        Some(forced.clone()),
+        Some(unrelated),
+        Some(forced),
+    ];
+    flag_forced_subsets(&mut tracks, &cues);
+    assert!(!tracks[0].likely_forced);
+    assert!(tracks[1].likely_forced);
+    assert!(!tracks[2].likely_forced);
+    // No other French track to compare with
+    assert!(!tracks[3].likely_forced);
+    assert!(tracks[1].to_string().contains("(likely forced)"));
+}
+
+#[test]
+fn test_flag_forced_subsets_languages() {
+    let full: Vec<Cue> = (0..20)
+        .map(|i| cue(i * 10_000, i * 10_000 + 3_000, ""))
+        .collect();
+    let forced = vec![cue(50_000, 53_000, ""), cue(120_000, 123_000, "")];
+
+    // `en` and `eng` are the same language
+    let mut tracks = vec![track(0, "eng"), track(1, "en")];
+    flag_forced_subsets(&mut tracks, &[Some(full.clone()), Some(forced.clone())]);
+    assert!(tracks[1].likely_forced);
+
+    // Untagged tracks aren't known to share a language
+    let mut tracks = vec![track(0, "eng"), track(1, "eng")];
+    tracks[0].lang = None;
+    tracks[1].lang = None;
+    flag_forced_subsets(&mut tracks, &[Some(full), Some(forced)]);
+    assert!(!tracks[1].likely_forced);
+}
+
+#[test]
+fn test_forced_subset_of_long_cues() {
+    // Cues longer than any fixed window must still be found
+    let full: Vec<Cue> = (0..10)
+        .map(|i| cue(i * 60_000, i * 60_000 + 30_000, ""))
+        .collect();
+    let forced = vec![cue(140_000, 145_000, "")];
+    assert!(is_forced_subset(&forced, &full));
+}
+
+#[test]
+fn test_overlaps_inverted_cue() {
+    // A malformed track can end a cue before it starts; this must not panic
+    let inverted = cue(10_000, 5_000, "");
+    assert!(!inverted.overlaps(&cue(0, 4_000, "")));
+    assert!(!cue(0, 4_000, "").overlaps(&inverted));
+    assert!(inverted.overlaps(&cue(10_000, 12_000, "")));
+}
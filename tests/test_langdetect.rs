@@ -0,0 +1,72 @@
+use mkv_subtitle_extractor::detect_cue_language;
+use mkv_subtitle_extractor::detect_language;
+use mkv_subtitle_extractor::parse_srt;
+
+#[test]
+fn test_detect_latin_languages() {
+    for (lang, text) in [
+        (
+            "eng",
+            "I told you we should have left before the storm. Now the bridge is gone and \
+             nobody knows when they will come to find us.",
+        ),
+        (
+            "fre",
+            "Je t'avais dit qu'il fallait partir avant la tempête. Maintenant le pont est \
+             détruit et personne ne sait quand ils viendront nous chercher.",
+        ),
+        (
+            "ger",
+            "Ich habe dir gesagt, dass wir vor dem Sturm gehen sollten. Jetzt ist die Brücke \
+             weg und niemand weiß, wann sie uns finden werden.",
+        ),
+        (
+            "spa",
+            "Te dije que teníamos que irnos antes de la tormenta. Ahora el puente ha \
+             desaparecido y nadie sabe cuándo vendrán a buscarnos.",
+        ),
+    ] {
+        assert_eq!(detect_language(text), Some(lang), "{text}");
+    }
+}
+
+#[test]
+fn test_detect_scripts() {
+    assert_eq!(
+        detect_language("私はあなたに嵐の前に出発するべきだと言いました。今、橋はなくなってしまった。誰も私たちを探しに来ない。"),
+        Some("jpn")
+    );
+    assert_eq!(
+        detect_language("Я говорил тебе, что нам нужно уйти до бури. Теперь моста нет, и никто не знает, когда нас найдут."),
+        Some("rus")
+    );
+    assert_eq!(detect_language("Too short"), None);
+}
+
+#[test]
+fn test_detect_cue_language_ignores_markup() {
+    let srt = "1\n00:00:01,000 --> 00:00:02,000\n{\\an8}<i>Where did you put the keys?</i>\n\n\
+        2\n00:00:03,000 --> 00:00:04,000\n<font color=\"#ffffff\">I left them on the kitchen table, \
+        next to the letters.</font>\n\n";
+    assert_eq!(detect_cue_language(&parse_srt(srt)), Some("eng"));
+}
+
+#[test]
+fn test_close_languages_are_not_mistaken() {
+    let danish = "Kan du hente bilen i morgen tidlig? Jeg skal nå toget klokken syv, og bussen \
+        kører først halv otte.";
+    assert_eq!(detect_language(danish), Some("dan"));
+    // Too close to the Danish profile to tell apart, so no guess rather than the wrong one
+    let norwegian = "Kan du hente bilen i morgen tidlig? Jeg må rekke toget klokka sju, og bussen \
+        går ikke før halv åtte.";
+    assert_eq!(detect_language(norwegian), None);
+
+    let portuguese =
+        "Ontem fomos à praia com as crianças e depois almoçámos em casa da avó. Amanhã \
+        vamos voltar se não chover.";
+    assert_eq!(detect_language(portuguese), Some("por"));
+    // Galician has no profile, and mustn't pass for Portuguese
+    let galician = "Onte fomos á praia cos nenos e despois xantamos na casa da avoa. Mañá imos \
+        volver se non chove.";
+    assert_eq!(detect_language(galician), None);
+}
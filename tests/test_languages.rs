@@ -0,0 +1,123 @@
+use mkv_subtitle_extractor::language_matches;
+use mkv_subtitle_extractor::language_name;
+use mkv_subtitle_extractor::normalize_language;
+use mkv_subtitle_extractor::parse_matroska_tracks;
+use mkv_subtitle_extractor::two_letter_code;
+use mkv_subtitle_extractor::MatroskaTrack;
+use mkv_subtitle_extractor::SubtitleTrack;
+
+#[test]
+fn test_normalize_codes() {
+    for code in ["de", "ger", "deu", "DEU", "de-AT"] {
+        assert_eq!(normalize_language(code), "ger", "{code}");
+    }
+    assert_eq!(two_letter_code("fre"), "fr");
+    assert_eq!(two_letter_code("zh_hant_tw"), "zh-Hant-TW");
+    // Unknown codes and languages without a 639-1 code pass through
+    assert_eq!(two_letter_code("yue"), "yue");
+    assert_eq!(normalize_language("XYZ"), "xyz");
+    // Non-ASCII subtags must not be sliced mid-character
+    assert_eq!(two_letter_code("x-ñé"), "x-ñé");
+    assert_eq!(two_letter_code("en-ÑÉ"), "en-ÑÉ");
+    assert!(!language_matches("en-ñé", "eng"));
+}
+
+#[test]
+fn test_language_names() {
+    assert_eq!(language_name("eng").as_deref(), Some("English"));
+    assert_eq!(
+        language_name("pt-BR").as_deref(),
+        Some("Portuguese (Brazil)")
+    );
+    assert_eq!(
+        language_name("es-419").as_deref(),
+        Some("Spanish (Latin America)")
+    );
+    assert_eq!(language_name("und"), None);
+
+    let track = SubtitleTrack {
+        stream_index: 3,
+        lang: Some("chi".to_string()),
+        format: "subrip".to_string(),
+        lang_detected: true,
+        ..Default::default()
+    };
+    assert!(track.to_string().contains("(chi, Chinese, detected)"));
+}
+
+#[test]
+fn test_language_matches() {
+    assert!(language_matches("en", "eng"));
+    assert!(language_matches("deu", "ger"));
+    assert!(language_matches("pt", "pt-BR"));
+    assert!(!language_matches("pt-BR", "pt-PT"));
+    assert!(!language_matches("pt-BR", "por"));
+    assert!(!language_matches("eng", "fre"));
+}
+
+/// Build an EBML element from its ID bytes and payload, with a one-byte size
+fn element(id: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut out = id.to_vec();
+    out.push(0x80 | payload.len() as u8);
+    out.extend_from_slice(payload);
+    out
+}
+
+#[test]
+fn test_parse_matroska_tracks() -> eyre::Result<()> {
+    let video = element(&[0xAE], &element(&[0x83], &[0x01]));
+    let subtitle = element(
+        &[0xAE],
+        &[
+            element(&[0x83], &[0x11]),
+            element(&[0x22, 0xB5, 0x9C], b"spa"),
+            element(&[0x22, 0xB5, 0x9D], b"es-419"),
+            element(&[0x53, 0x6E], b"Latino"),
+        ]
+        .concat(),
+    );
+    let tracks = element(&[0x16, 0x54, 0xAE, 0x6B], &[video, subtitle].concat());
+    let info = element(&[0x15, 0x49, 0xA9, 0x66], &[0; 8]);
+    let file = [
+        element(
+            &[0x1A, 0x45, 0xDF, 0xA3],
+            &[0x42, 0x82, 0x84, b'w', b'e', b'b', b'm'],
+        ),
+        // A segment of unknown size, as written by live muxers
+        vec![
+            0x18, 0x53, 0x80, 0x67, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ],
+        info,
+        tracks,
+    ]
+    .concat();
+
+    let headers = parse_matroska_tracks(std::io::Cursor::new(file))?;
+    assert_eq!(
+        headers,
+        vec![MatroskaTrack {
+            language: Some("spa".to_string()),
+            language_bcp47: Some("es-419".to_string()),
+            name: Some("Latino".to_string()),
+        }]
+    );
+
+    let track = SubtitleTrack {
+        lang: headers[0].language.clone(),
+        lang_ietf: headers[0].language_bcp47.clone(),
+        format: "subrip".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(track.language(), Some("es-419"));
+    assert!(track
+        .to_string()
+        .contains("(es-419, Spanish (Latin America))"));
+    // A BCP-47 tag without a region adds nothing over the ISO 639-2 code
+    let plain = SubtitleTrack {
+        lang: Some("eng".to_string()),
+        lang_ietf: Some("en".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(plain.language(), Some("eng"));
+    Ok(())
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::cue;
+use mkv_subtitle_extractor::merge_cues;
+use mkv_subtitle_extractor::write_merged_ass;
+use mkv_subtitle_extractor::write_merged_srt;
+use std::time::Duration;
+
+#[test]
+fn test_merge_cues() {
+    let english = [
+        cue(1_000, 3_000, "Where are you going?"),
+        cue(4_000, 6_000, "Home."),
+    ];
+    let japanese = [
+        cue(1_100, 3_200, "どこへ行くの？"),
+        cue(4_000, 5_000, "家に"),
+        cue(5_000, 6_100, "帰る。"),
+        cue(8_000, 9_000, "（ドアが閉まる）"),
+    ];
+    let merged = merge_cues(&english, &japanese);
+    assert_eq!(merged.len(), 3);
+    assert_eq!(merged[0].end, Duration::from_millis(3_200));
+    assert_eq!(merged[1].secondary, "家に\n帰る。");
+    assert_eq!(merged[2].primary, "");
+
+    assert_eq!(
+        write_merged_srt(&merged[..2]),
+        "1\n00:00:01,000 --> 00:00:03,200\nWhere are you going?\nどこへ行くの？\n\n\
+         2\n00:00:04,000 --> 00:00:06,100\nHome.\n家に\n帰る。\n\n"
+    );
+}
+
+#[test]
+fn test_write_merged_ass() {
+    let merged = merge_cues(
+        &[cue(
+            1_000,
+            2_000,
+            "<i>Hello</i>\n<font color=\"red\">there</font>",
+        )],
+        &[cue(1_000, 2_000, "こんにちは")],
+    );
+    let ass = write_merged_ass(&merged, "eng / jpn");
+    assert!(ass.contains("Title: eng / jpn\n"));
+    assert!(ass
+        .contains("Dialogue: 0,0:00:01.00,0:00:02.00,Bottom,,0,0,0,,{\\i1}Hello{\\i0}\\Nthere\n"));
+    assert!(ass.contains("Dialogue: 0,0:00:01.00,0:00:02.00,Top,,0,0,0,,こんにちは\n"));
+}
@@ -0,0 +1,72 @@
+use mkv_subtitle_extractor::render_output_name;
+use mkv_subtitle_extractor::Config;
+use mkv_subtitle_extractor::NameParts;
+use mkv_subtitle_extractor::OverwritePolicy;
+use mkv_subtitle_extractor::DEFAULT_NAMING_TEMPLATE;
+
+fn parts() -> NameParts {
+    NameParts {
+        stem: "Blade Runner 2049".to_string(),
+        index: 2,
+        lang: Some("eng".to_string()),
+        title: None,
+        format: "subrip".to_string(),
+        forced: false,
+        ext: "srt".to_string(),
+    }
+}
+
+#[test]
+fn test_default_template_drops_empty_parts() -> eyre::Result<()> {
+    let name = render_output_name(DEFAULT_NAMING_TEMPLATE, &parts())?;
+    assert_eq!(name, "Blade Runner 2049.2.eng.srt");
+
+    let mut untagged = parts();
+    untagged.lang = None;
+    untagged.title = Some("SDH".to_string());
+    let name = render_output_name(DEFAULT_NAMING_TEMPLATE, &untagged)?;
+    assert_eq!(name, "Blade Runner 2049.2.SDH.srt");
+
+    let mut forced = parts();
+    forced.forced = true;
+    let name = render_output_name(DEFAULT_NAMING_TEMPLATE, &forced)?;
+    assert_eq!(name, "Blade Runner 2049.2.eng.forced.srt");
+    Ok(())
+}
+
+#[test]
+fn test_custom_template() -> eyre::Result<()> {
+    // The extension is appended when the template leaves it out
+    let name = render_output_name("{stem}_{lang}", &parts())?;
+    assert_eq!(name, "Blade Runner 2049_eng.srt");
+    let mut brazilian = parts();
+    brazilian.lang = Some("por-BR".to_string());
+    let name = render_output_name("{stem}.{lang2}.{ext}", &brazilian)?;
+    assert_eq!(name, "Blade Runner 2049.pt-BR.srt");
+    let name = render_output_name("{stem}.{lang3}.{ext}", &brazilian)?;
+    assert_eq!(name, "Blade Runner 2049.por.srt");
+    assert!(render_output_name("{stem}.{nope}", &parts()).is_err());
+    assert!(render_output_name("{stem", &parts()).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_config_layering() -> eyre::Result<()> {
+    let user: Config = toml::from_str(
+        r#"
+        languages = ["eng", "jpn"]
+        overwrite = "skip"
+        naming = "{stem}.{lang}.{ext}"
+        "#,
+    )?;
+    let local: Config = toml::from_str(r#"overwrite = "always""#)?;
+    let merged = Config::built_in().merge(user).merge(local);
+    assert_eq!(merged.overwrite, Some(OverwritePolicy::Always));
+    assert_eq!(merged.naming.as_deref(), Some("{stem}.{lang}.{ext}"));
+    assert_eq!(
+        merged.languages,
+        Some(vec!["eng".to_string(), "jpn".to_string()])
+    );
+    assert!(toml::from_str::<Config>("not-a-key = 1").is_err());
+    Ok(())
+}
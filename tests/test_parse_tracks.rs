@@ -0,0 +1,108 @@
+use mkv_subtitle_extractor::closed_caption_track;
+use mkv_subtitle_extractor::lavfi_movie_graph;
+use mkv_subtitle_extractor::output_format_for_codec;
+use mkv_subtitle_extractor::parse_closed_caption_streams;
+use mkv_subtitle_extractor::parse_subtitle_tracks;
+use mkv_subtitle_extractor::plan_subtitle_extraction;
+use mkv_subtitle_extractor::FfmpegOptions;
+use mkv_subtitle_extractor::OutputOptions;
+use std::path::Path;
+
+#[test]
+fn test_parse_mp4_and_ts_streams() -> eyre::Result<()> {
+    let output = r#"
+Input #0, mov,mp4,m4a,3gp,3g2,mj2, from 'episode.mp4':
+  Stream #0:0[0x1](und): Video: h264 (High) (avc1 / 0x31637661), yuv420p, 1920x1080, 23.98 fps (default)
+  Stream #0:1[0x2](eng): Audio: aac (LC) (mp4a / 0x6134706D), 48000 Hz, stereo, fltp, 128 kb/s (default)
+  Stream #0:2[0x3](eng): Subtitle: mov_text (tx3g / 0x67337874), 0 kb/s (default) (forced)
+    Metadata:
+      handler_name    : English
+  Stream #0:3[0x4](spa): Audio: aac (LC), 48000 Hz, stereo, fltp
+    Metadata:
+      title           : Spanish commentary
+  Stream #0:4[0x1100](deu): Subtitle: dvb_subtitle ([6][0][0][0] / 0x0006)
+"#;
+    let tracks = parse_subtitle_tracks(output)?;
+    assert_eq!(tracks.len(), 2);
+
+    assert_eq!(tracks[0].stream_index, 0);
+    assert_eq!(tracks[0].lang.as_deref(), Some("eng"));
+    assert_eq!(tracks[0].format, "mov_text");
+    assert!(tracks[0].default && tracks[0].forced);
+    // The audio stream's title must not leak into the subtitle before it
+    assert_eq!(tracks[0].title, None);
+
+    assert_eq!(tracks[1].stream_index, 1);
+    assert_eq!(tracks[1].lang.as_deref(), Some("deu"));
+    assert_eq!(tracks[1].format, "dvb_subtitle");
+    Ok(())
+}
+
+#[test]
+fn test_codec_output_mapping() {
+    assert_eq!(output_format_for_codec("subrip").ext, "srt");
+    assert_eq!(output_format_for_codec("subrip").encoder, None);
+    assert_eq!(output_format_for_codec("mov_text").ext, "srt");
+    assert_eq!(output_format_for_codec("mov_text").encoder, Some("srt"));
+    assert_eq!(output_format_for_codec("webvtt").muxer, "webvtt");
+    assert_eq!(output_format_for_codec("dvb_subtitle").muxer, "matroska");
+}
+
+#[test]
+fn test_transcode_fallback() -> eyre::Result<()> {
+    let tracks = parse_subtitle_tracks(
+        "  Stream #0:2(eng): Subtitle: ass\n  Stream #0:3[0x3](eng): Subtitle: mov_text (tx3g / 0x67337874)",
+    )?;
+    let options = FfmpegOptions::default();
+    let output = OutputOptions::default();
+
+    // ASS is stream copied first, and converted with the ass encoder if that fails
+    let plan = plan_subtitle_extraction(Path::new("movie.mkv"), &tracks[0], &options, &output)?;
+    assert!(plan.args().contains(&"copy".to_string()));
+    let fallback = plan
+        .transcode_fallback()
+        .expect("text tracks have a fallback");
+    assert_eq!(fallback.encoder.as_deref(), Some("ass"));
+
+    // mov_text is converted up front, so there is nothing to fall back to
+    let plan = plan_subtitle_extraction(Path::new("movie.mp4"), &tracks[1], &options, &output)?;
+    assert_eq!(plan.encoder.as_deref(), Some("srt"));
+    assert!(plan.transcode_fallback().is_none());
+    assert_eq!(plan.output_path, Path::new("./movie.1.eng.srt"));
+    Ok(())
+}
+
+#[test]
+fn test_closed_captions() -> eyre::Result<()> {
+    assert_eq!(parse_closed_caption_streams("0,1\n1,0\n"), vec![0]);
+    assert_eq!(
+        lavfi_movie_graph("Show [1080p], Part 1.ts", 0),
+        r"movie=Show \[1080p\]\, Part 1.ts:si=0[out0+subcc]"
+    );
+
+    let track = closed_caption_track(2, 0);
+    let plan = plan_subtitle_extraction(
+        Path::new("news.ts"),
+        &track,
+        &FfmpegOptions::default(),
+        &OutputOptions::default(),
+    )?;
+    assert_eq!(
+        plan.args(),
+        [
+            "-f",
+            "lavfi",
+            "-i",
+            "movie=news.ts:si=0[out0+subcc]",
+            "-map",
+            "0:s",
+            "-c:s",
+            "srt",
+            "-f",
+            "srt",
+            "output.srt"
+        ]
+    );
+    assert_eq!(plan.output_path, Path::new("./news.2.Closed Captions.srt"));
+    Ok(())
+}
@@ -0,0 +1,145 @@
+use mkv_subtitle_extractor::pgs::decode_rle;
+use mkv_subtitle_extractor::pgs::format_pts;
+use mkv_subtitle_extractor::pgs::parse_display_sets;
+use mkv_subtitle_extractor::pgs::render_display_sets;
+use mkv_subtitle_extractor::pgs::summarize_display_sets;
+use mkv_subtitle_extractor::pgs::CompositionState;
+
+fn segment(out: &mut Vec<u8>, pts: u32, kind: u8, payload: &[u8]) {
+    out.extend_from_slice(b"PG");
+    out.extend_from_slice(&pts.to_be_bytes());
+    out.extend_from_slice(&0u32.to_be_bytes());
+    out.push(kind);
+    out.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// A 1920x1080 epoch showing a forced 4x2 object at (10, 20) from 1s to 2s
+fn sample_sup() -> Vec<u8> {
+    // Row 0: four pixels of colour 1; row 1: two transparent pixels then two of colour 1
+    let rle = [
+        0x00, 0x84, 0x01, 0x00, 0x00, 0x00, 0x02, 0x01, 0x01, 0x00, 0x00,
+    ];
+    let mut sup = Vec::new();
+
+    // PCS: 1920x1080, epoch start, palette 0, one forced object
+    let pcs = [
+        0x07, 0x80, 0x04, 0x38, 0x10, 0x00, 0x01, 0x80, 0x00, 0x00, 0x01, //
+        0x00, 0x00, 0x00, 0x40, 0x00, 0x0A, 0x00, 0x14,
+    ];
+    segment(&mut sup, 90_000, 0x16, &pcs);
+    segment(
+        &mut sup,
+        90_000,
+        0x17,
+        &[0x01, 0x00, 0x00, 0x0A, 0x00, 0x14, 0x00, 0x04, 0x00, 0x02],
+    );
+    // PDS: entry 1 is opaque white
+    segment(
+        &mut sup,
+        90_000,
+        0x14,
+        &[0x00, 0x00, 0x01, 235, 128, 128, 255],
+    );
+    // ODS split over two fragments
+    let mut first = vec![
+        0x00,
+        0x00,
+        0x00,
+        0x80,
+        0x00,
+        0x00,
+        (rle.len() + 4) as u8,
+        0x00,
+        0x04,
+        0x00,
+        0x02,
+    ];
+    first.extend_from_slice(&rle[..5]);
+    segment(&mut sup, 90_000, 0x15, &first);
+    let mut last = vec![0x00, 0x00, 0x00, 0x40];
+    last.extend_from_slice(&rle[5..]);
+    segment(&mut sup, 90_000, 0x15, &last);
+    segment(&mut sup, 90_000, 0x80, &[]);
+
+    // Clearing display set with no objects
+    let pcs = [
+        0x07, 0x80, 0x04, 0x38, 0x10, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+    ];
+    segment(&mut sup, 180_000, 0x16, &pcs);
+    segment(&mut sup, 180_000, 0x80, &[]);
+    sup
+}
+
+#[test]
+fn test_parse_display_sets() -> eyre::Result<()> {
+    let sets = parse_display_sets(&sample_sup())?;
+    assert_eq!(sets.len(), 2);
+    assert_eq!(sets[0].composition.state, CompositionState::EpochStart);
+    assert_eq!(
+        (sets[0].composition.width, sets[0].composition.height),
+        (1920, 1080)
+    );
+    assert!(sets[0].composition.objects[0].forced);
+    assert_eq!(sets[0].objects.len(), 1);
+    assert_eq!(sets[0].objects[0].rle.len(), 11);
+    assert!(sets[1].composition.objects.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_render_display_sets() -> eyre::Result<()> {
+    let sets = parse_display_sets(&sample_sup())?;
+    let images = render_display_sets(&sets)?;
+    assert_eq!(images.len(), 1);
+    let image = &images[0];
+    assert_eq!(
+        (image.x, image.y, image.width, image.height),
+        (10, 20, 4, 2)
+    );
+    assert_eq!(format_pts(image.start), "00:00:01.000");
+    assert_eq!(image.end.map(format_pts).as_deref(), Some("00:00:02.000"));
+    assert!(image.forced);
+    assert_eq!(&image.rgba[0..4], &[255, 255, 255, 255]);
+    // Row 1 starts with a transparent pixel
+    assert_eq!(image.rgba[4 * 4 + 3], 0);
+    Ok(())
+}
+
+#[test]
+fn test_render_display_sets_out_of_range() -> eyre::Result<()> {
+    let mut sets = parse_display_sets(&sample_sup())?;
+    // The 4 pixel wide object would end past 65535
+    sets[0].composition.objects[0].x = 65534;
+    assert!(render_display_sets(&sets)?.is_empty());
+
+    // Cropped to nothing
+    let mut sets = parse_display_sets(&sample_sup())?;
+    sets[0].composition.objects[0].crop = Some((0, 0, 0, 2));
+    assert!(render_display_sets(&sets)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_decode_rle_long_runs() -> eyre::Result<()> {
+    // 300 pixels of colour 7, then end of line
+    let pixels = decode_rle(&[0x00, 0xC1, 0x2C, 0x07, 0x00, 0x00], 300, 1)?;
+    assert!(pixels.iter().all(|&p| p == 7));
+    Ok(())
+}
+
+#[test]
+fn test_summarize_display_sets() -> eyre::Result<()> {
+    let sets = parse_display_sets(&sample_sup())?;
+    let summary = summarize_display_sets(&sets);
+    assert_eq!(summary.display_sets, 2);
+    assert_eq!(summary.epoch_starts, vec![90_000]);
+    assert_eq!(summary.composition_sizes, vec![(1920, 1080)]);
+    assert_eq!(summary.palette_definitions, 1);
+    assert_eq!(summary.events.len(), 1);
+    assert_eq!(summary.events[0].end, Some(180_000));
+    assert_eq!(summary.forced_events(), 1);
+    assert!(summary.issues.is_empty());
+    assert!(summary.to_string().contains("(forced-only track)"));
+    Ok(())
+}
@@ -0,0 +1,110 @@
+//! Tests for the process limits, using `sh`, `sleep`, `echo` and `touch` as stand-ins for ffmpeg.
+//!
+//! They need a Unix shell but not FFmpeg, so they're skipped on other platforms.
+
+#![cfg(unix)]
+
+use mkv_subtitle_extractor::run_with_limits;
+use mkv_subtitle_extractor::stream_with_limits;
+use mkv_subtitle_extractor::CancellationToken;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+
+/// A shell that creates `marker` after a second, unless it is killed first
+fn slow_command(marker: &Path) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c")
+        .arg(format!("sleep 1 && touch '{}'", marker.display()));
+    cmd
+}
+
+fn marker_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("mkvsubs-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[tokio::test]
+async fn test_run_with_limits_output() -> eyre::Result<()> {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg("echo hello");
+    let output = run_with_limits(
+        cmd,
+        Some(Duration::from_secs(10)),
+        &CancellationToken::new(),
+    )
+    .await?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hello\n");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_run_with_limits_timeout_kills_child() {
+    let marker = marker_path("timeout");
+    let started = Instant::now();
+    let result = run_with_limits(
+        slow_command(&marker),
+        Some(Duration::from_millis(100)),
+        &CancellationToken::new(),
+    )
+    .await;
+    let error = result.expect_err("should time out").to_string();
+    assert!(error.contains("timed out"), "{error}");
+    assert!(started.elapsed() < Duration::from_secs(1));
+    // Had the shell survived, it would have created the marker by now
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(!marker.exists());
+}
+
+#[tokio::test]
+async fn test_run_with_limits_cancel_kills_child() {
+    let marker = marker_path("cancel");
+    let cancel = CancellationToken::new();
+    tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            cancel.cancel();
+        }
+    });
+    let result = run_with_limits(slow_command(&marker), None, &cancel).await;
+    let error = result.expect_err("should be cancelled").to_string();
+    assert!(error.contains("Cancelled"), "{error}");
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(!marker.exists());
+
+    // An already cancelled token doesn't start anything
+    let result = run_with_limits(slow_command(&marker), None, &cancel).await;
+    assert!(result.is_err());
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(!marker.exists());
+}
+
+#[tokio::test]
+async fn test_stream_with_limits_drains_stderr() -> eyre::Result<()> {
+    // Far more stderr than a pipe holds, written before stdout closes
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(
+        "i=0; while [ $i -lt 20000 ]; do echo \"error in packet $i\" >&2; i=$((i+1)); done; echo done",
+    );
+    let (output, stdout) = stream_with_limits(
+        cmd,
+        Some(Duration::from_secs(30)),
+        &CancellationToken::new(),
+        |mut stdout| async move {
+            let mut text = String::new();
+            stdout.read_to_string(&mut text).await?;
+            eyre::Ok(text)
+        },
+    )
+    .await?;
+    assert!(output.status.success());
+    assert_eq!(stdout, "done\n");
+    assert!(output.stderr.len() > 64 * 1024);
+    Ok(())
+}
@@ -0,0 +1,175 @@
+use mkv_subtitle_extractor::backup_path_for;
+use mkv_subtitle_extractor::count_streams;
+use mkv_subtitle_extractor::mkvpropedit_args;
+use mkv_subtitle_extractor::parse_sidecar_name;
+use mkv_subtitle_extractor::parse_subtitle_tracks;
+use mkv_subtitle_extractor::plan_mux;
+use mkv_subtitle_extractor::plan_strip;
+use mkv_subtitle_extractor::plan_tag_remux;
+use mkv_subtitle_extractor::tracks_to_drop;
+use mkv_subtitle_extractor::FfmpegOptions;
+use mkv_subtitle_extractor::Sidecar;
+use mkv_subtitle_extractor::TrackEdits;
+use std::path::Path;
+use std::path::PathBuf;
+
+#[test]
+fn test_parse_sidecar_name() {
+    let sidecar = parse_sidecar_name(
+        "Blade Runner 2049",
+        "Blade Runner 2049.2.eng.SDH.forced.srt",
+    );
+    assert_eq!(
+        sidecar,
+        Some(Sidecar {
+            index: Some(2),
+            lang: Some("eng".to_string()),
+            title: Some("SDH".to_string()),
+            forced: true,
+            hearing_impaired: true,
+            ..Default::default()
+        })
+    );
+    let sidecar = parse_sidecar_name("Movie", "Movie.pt-BR.Commentary.ass").unwrap();
+    assert_eq!(sidecar.lang.as_deref(), Some("pt-BR"));
+    assert_eq!(sidecar.title.as_deref(), Some("Commentary"));
+    assert_eq!(
+        parse_sidecar_name("Movie", "Movie.srt"),
+        Some(Sidecar::default())
+    );
+
+    // Other videos, other extensions
+    assert_eq!(parse_sidecar_name("Movie", "Movie 2.eng.srt"), None);
+    assert_eq!(parse_sidecar_name("Movie", "Movie.eng.txt"), None);
+}
+
+#[test]
+fn test_plan_mux() {
+    let listing = "  Stream #0:0: Video: h264\n  Stream #0:1(eng): Subtitle: subrip\n  Stream #0:2: Attachment: ttf\n";
+    assert_eq!(count_streams(listing, "Subtitle"), 1);
+    assert_eq!(count_streams(listing, "Attachment"), 1);
+
+    let sidecar = Sidecar {
+        path: PathBuf::from("Movie.de.Signs.forced.ass"),
+        lang: Some("de".to_string()),
+        title: Some("Signs".to_string()),
+        forced: true,
+        ..Default::default()
+    };
+    let plan = plan_mux(
+        Path::new("Movie.mkv"),
+        1,
+        1,
+        &[sidecar],
+        &[PathBuf::from("fonts/Arial.ttf")],
+        Path::new("Movie.muxed.mkv"),
+        &FfmpegOptions::default(),
+    );
+    let line = plan.command_line();
+    assert!(line.contains("-i ./Movie.mkv -i ./Movie.de.Signs.forced.ass -map 0 -map 1 -c copy"));
+    assert!(line.contains(
+        "-metadata:s:s:1 language=ger -metadata:s:s:1 title=Signs -disposition:s:1 forced"
+    ));
+    assert!(line.contains(
+        "-attach ./fonts/Arial.ttf -metadata:s:t:1 mimetype=application/x-truetype-font"
+    ));
+    assert_eq!(plan.temp_path, Path::new("Movie.muxed.tmp.mkv"));
+    assert!(line.ends_with("-f matroska ./Movie.muxed.tmp.mkv"));
+}
+
+#[test]
+fn test_command_line_quoting() {
+    let sidecar = Sidecar {
+        path: PathBuf::from("Don't Look Up.en.srt"),
+        title: Some("Director's \"Cut\"".to_string()),
+        ..Default::default()
+    };
+    let plan = plan_mux(
+        Path::new("Don't Look Up.mkv"),
+        0,
+        0,
+        &[sidecar],
+        &[],
+        Path::new("Don't Look Up.muxed.mkv"),
+        &FfmpegOptions::default(),
+    );
+    // POSIX single quotes, with each embedded quote written as '\''
+    let line = plan.command_line();
+    assert!(line.contains(r"-i './Don'\''t Look Up.mkv' -i './Don'\''t Look Up.en.srt'"));
+    assert!(line.contains(r#"'title=Director'\''s "Cut"'"#));
+}
+
+#[test]
+fn test_plan_strip() -> eyre::Result<()> {
+    let tracks = parse_subtitle_tracks(
+        "  Stream #0:2(eng): Subtitle: subrip\n  Stream #0:3(ger): Subtitle: subrip (hearing impaired)\n  Stream #0:4: Subtitle: hdmv_pgs_subtitle",
+    )?;
+    let drop = ["sdh".parse()?];
+    assert_eq!(tracks_to_drop(&tracks, &drop, &[]), vec![1]);
+    // Untagged tracks aren't in any kept language
+    assert_eq!(
+        tracks_to_drop(&tracks, &[], &["en".to_string()]),
+        vec![1, 2]
+    );
+
+    let dropped = [&tracks[1], &tracks[2]];
+    let plan = plan_strip(
+        Path::new("Movie.mkv"),
+        &dropped,
+        Path::new("Movie.mkv"),
+        &FfmpegOptions::default(),
+    );
+    assert!(plan
+        .command_line()
+        .ends_with("-i ./Movie.mkv -map 0 -map -0:s:1 -map -0:s:2 -c copy ./Movie.tmp.mkv"));
+    assert_eq!(
+        plan.backup_path,
+        Some(backup_path_for(Path::new("Movie.mkv")))
+    );
+    assert_eq!(
+        backup_path_for(Path::new("dir/Movie.mkv")),
+        Path::new("dir/Movie.mkv.bak")
+    );
+    Ok(())
+}
+
+#[test]
+fn test_tag_commands() -> eyre::Result<()> {
+    let tracks = parse_subtitle_tracks(
+        "  Stream #0:2(und): Subtitle: subrip (default)\n  Stream #0:3(und): Subtitle: subrip",
+    )?;
+    let edits = TrackEdits {
+        lang: Some("pt-BR".to_string()),
+        title: Some(String::new()),
+        forced: Some(true),
+        ..Default::default()
+    };
+    let args = mkvpropedit_args(Path::new("Movie.mkv"), &[&tracks[1]], &edits);
+    assert_eq!(
+        args.join(" "),
+        "./Movie.mkv --edit track:s2 --delete name --set language=por --set language-ietf=pt-BR --set flag-forced=1"
+    );
+    // A plain language replaces any stale BCP-47 tag too
+    let plain = TrackEdits {
+        lang: Some("ger".to_string()),
+        ..Default::default()
+    };
+    let args = mkvpropedit_args(Path::new("-Movie.mkv"), &[&tracks[0]], &plain);
+    assert_eq!(
+        args.join(" "),
+        "./-Movie.mkv --edit track:s1 --set language=ger --set language-ietf=de"
+    );
+
+    // The ffmpeg fallback keeps the flags that aren't being changed
+    let plan = plan_tag_remux(
+        Path::new("Movie.mkv"),
+        &[&tracks[0]],
+        &edits,
+        &FfmpegOptions::default(),
+    );
+    assert!(plan.command_line().contains(
+        "-metadata:s:s:0 language=por -metadata:s:s:0 title= -disposition:s:0 default+forced"
+    ));
+    assert!(plan.backup_path.is_some());
+    Ok(())
+}
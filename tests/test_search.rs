@@ -0,0 +1,51 @@
+mod common;
+
+use common::cue;
+use mkv_subtitle_extractor::find_input_files_recursive;
+use mkv_subtitle_extractor::search_cues;
+use mkv_subtitle_extractor::SearchMatch;
+use regex::RegexBuilder;
+use std::time::Duration;
+
+#[test]
+fn test_search_cues() {
+    let cues = [
+        cue(1_000, 2_000, "- Where are you going?\n- <i>Nowhere.</i>"),
+        cue(62_345, 64_000, "I'm going nowhere fast."),
+        cue(65_000, 66_000, "Now here we are."),
+    ];
+    let pattern = RegexBuilder::new(r"\bnowhere\b")
+        .case_insensitive(true)
+        .build()
+        .unwrap();
+    // Each line is matched on its own, without its markup
+    assert_eq!(
+        search_cues(&cues, &pattern),
+        [
+            SearchMatch {
+                start: Duration::from_millis(1_000),
+                line: "- Nowhere.".to_string(),
+            },
+            SearchMatch {
+                start: Duration::from_millis(62_345),
+                line: "I'm going nowhere fast.".to_string(),
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_find_input_files_recursive() -> eyre::Result<()> {
+    let dir = std::env::temp_dir().join(format!("mkvsubs-search-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("Season 1"))?;
+    for name in ["b.mkv", "notes.txt", "Season 1/a.MKV", "Season 1/a.srt"] {
+        std::fs::write(dir.join(name), [])?;
+    }
+    let found = find_input_files_recursive(&dir).await;
+    // Not a directory
+    let file = find_input_files_recursive(&dir.join("b.mkv")).await;
+    std::fs::remove_dir_all(&dir)?;
+    assert_eq!(found?, [dir.join("Season 1/a.MKV"), dir.join("b.mkv")]);
+    assert!(file.is_err());
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use mkv_subtitle_extractor::SelectionRules;
+use mkv_subtitle_extractor::SubtitleTrack;
+use mkv_subtitle_extractor::TrackFilter;
+
+fn track(index: u32, lang: &str, format: &str, title: Option<&str>, forced: bool) -> SubtitleTrack {
+    SubtitleTrack {
+        stream_index: index,
+        lang: Some(lang.to_string()),
+        format: format.to_string(),
+        title: title.map(str::to_string),
+        forced,
+        ..Default::default()
+    }
+}
+
+fn rules(prefer: &[&str], always: &[&str]) -> eyre::Result<SelectionRules> {
+    Ok(SelectionRules {
+        prefer: prefer
+            .iter()
+            .map(|x| x.parse())
+            .collect::<eyre::Result<_>>()?,
+        always: always
+            .iter()
+            .map(|x| x.parse())
+            .collect::<eyre::Result<_>>()?,
+        ..Default::default()
+    })
+}
+
+#[test]
+fn test_prefer_ranking_and_always() -> eyre::Result<()> {
+    // "prefer eng non-SDH text track, else eng SDH, else any eng PGS; always also take forced eng"
+    let rules = rules(
+        &["lang=eng text !sdh", "lang=eng sdh", "lang=eng bitmap"],
+        &["lang=eng forced"],
+    )?;
+
+    let tracks = vec![
+        track(0, "eng", "hdmv_pgs_subtitle", None, false),
+        track(1, "eng", "subrip", Some("English SDH"), false),
+        track(2, "eng", "subrip", Some("English"), false),
+        track(3, "eng", "hdmv_pgs_subtitle", Some("Forced"), true),
+        track(4, "jpn", "ass", None, false),
+    ];
+    // Track 3 is a bitmap, so the first rule skips it; it comes in through `always`
+    assert_eq!(rules.select(&tracks), vec![2, 3]);
+
+    // Without a plain English text track, the SDH one wins
+    assert_eq!(rules.select(&tracks[..2]), vec![1]);
+    assert_eq!(rules.select(&tracks[..1]), vec![0]);
+    assert!(rules.select(&tracks[4..]).is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_filter_parsing() {
+    let filter: TrackFilter = "lang=eng|en  !forced text".parse().unwrap();
+    assert_eq!(filter.to_string(), "lang=eng|en !forced text");
+    assert!("".parse::<TrackFilter>().is_err());
+    assert!("colour=red".parse::<TrackFilter>().is_err());
+    assert!("!lang=eng".parse::<TrackFilter>().is_err());
+}